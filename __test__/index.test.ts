@@ -1,7 +1,9 @@
-import { initTracingSubscriber, Lmdb } from "../index.js";
+import { Codec, ErrorCode, initTracingSubscriber, Lmdb } from "../index.js";
 import { type Database as UnsafeDatabase, open as openLMDBUnsafe } from "lmdb";
 import * as v8 from "node:v8";
 import { mkdirSync, rmSync } from "node:fs";
+import { putFrom } from "../typescript/put-from";
+import { rawEntries } from "../typescript/raw-entries";
 
 beforeAll(() => {
   initTracingSubscriber();
@@ -62,6 +64,81 @@ describe("lmdb", () => {
     }
   });
 
+  it("prefixed handles share the database", async () => {
+    db = new Lmdb({
+      path: "./databases/test.db",
+      asyncWrites,
+      mapSize: MAP_SIZE,
+    });
+
+    const prefixed = db.withPrefix("a:");
+    await prefixed.put("key", v8.serialize("prefixed"));
+    expect(v8.deserialize(db.getSync("a:key")!)).toEqual("prefixed");
+    expect(v8.deserialize(prefixed.getSync("key")!)).toEqual("prefixed");
+    expect(prefixed.getSync("a:key")).toEqual(null);
+    prefixed.close();
+  });
+
+  it("reports entry storage metadata", async () => {
+    db = new Lmdb({
+      path: "./databases/test.db",
+      asyncWrites,
+      mapSize: MAP_SIZE,
+    });
+
+    const value = Buffer.alloc(4096, 1);
+    await db.put("key", value);
+    const meta = db.getEntryMeta("key");
+    expect(meta?.value).toEqual(value);
+    expect(meta?.uncompressedSize).toEqual(4096);
+    expect(meta?.compressedSize).toBeLessThan(4096);
+    expect(db.getEntryMeta("missing")).toEqual(null);
+  });
+
+  it("reserved entries are stored uncompressed", async () => {
+    db = new Lmdb({
+      path: "./databases/test.db",
+      asyncWrites,
+      mapSize: MAP_SIZE,
+    });
+
+    await db.putReserved("key", 8, Buffer.from([1, 2, 3]));
+    expect(db.getSync("key")).toEqual(Buffer.from([1, 2, 3, 0, 0, 0, 0, 0]));
+    expect(db.getEntryMeta("key")?.codec).toEqual(Codec.None);
+  });
+
+  it("errors have a typed code", async () => {
+    db = new Lmdb({
+      path: "./databases/test.db",
+      asyncWrites,
+      mapSize: MAP_SIZE,
+    });
+    db.close();
+
+    expect(() => db?.getSync("key")).toThrow(
+      expect.objectContaining({ code: ErrorCode.DatabaseClosed }),
+    );
+    db = null;
+  });
+
+  it("can be reopened once closed", async () => {
+    db = new Lmdb({
+      path: "./databases/test.db",
+      asyncWrites,
+      mapSize: MAP_SIZE,
+    });
+    db.putNoConfirm("key", Buffer.from([1]));
+    db.close();
+    await db.closed;
+
+    db = new Lmdb({
+      path: "./databases/test.db",
+      asyncWrites,
+      mapSize: MAP_SIZE,
+    });
+    expect(db.getSync("key")).toEqual(Buffer.from([1]));
+  });
+
   it("read and write many entries", async () => {
     db = new Lmdb({
       path: "./databases/test.db",
@@ -86,6 +163,61 @@ describe("lmdb", () => {
     }
   });
 
+  it("writes entries out of a shared buffer", async () => {
+    db = new Lmdb({
+      path: "./databases/test.db",
+      asyncWrites,
+      mapSize: MAP_SIZE,
+    });
+
+    const buffer = Buffer.from([1, 2, 3, 4, 5]);
+    await db.putManyFromBuffer(buffer, [
+      { key: "a", offset: 0, length: 2 },
+      { key: "b", offset: 2, length: 3 },
+    ]);
+    expect(db.getSync("a")).toEqual(Buffer.from([1, 2]));
+    expect(db.getSync("b")).toEqual(Buffer.from([3, 4, 5]));
+    expect(() =>
+      db?.putManyFromBuffer(buffer, [{ key: "c", offset: 4, length: 2 }]),
+    ).toThrow(expect.objectContaining({ code: ErrorCode.InvalidArgument }));
+  });
+
+  it("iterates over raw entries in batches", async () => {
+    db = new Lmdb({
+      path: "./databases/test.db",
+      asyncWrites,
+      mapSize: MAP_SIZE,
+    });
+
+    await db.putMany([
+      { key: "b", value: Buffer.from([2]) },
+      { key: "a", value: Buffer.from([1]) },
+      { key: "c", value: Buffer.from([3]) },
+    ]);
+    await db.putReserved("d", 1, Buffer.from([4]));
+    const entries = [...rawEntries(db, { batchSize: 2 })];
+    expect(entries.map(({ key }) => key)).toEqual(["a", "b", "c", "d"]);
+    expect(entries[0].value).toEqual(db.getRaw("a"));
+    expect(entries[0].codec).toEqual(Codec.Lz4);
+    expect(entries[3].codec).toEqual(Codec.None);
+  });
+
+  it("writes entries from an async iterator in batches", async () => {
+    db = new Lmdb({
+      path: "./databases/test.db",
+      asyncWrites,
+      mapSize: MAP_SIZE,
+    });
+
+    async function* entries() {
+      for (let i = 0; i < 25; i += 1) {
+        yield { key: `${i}`, value: v8.serialize(i) };
+      }
+    }
+    expect(await putFrom(db, entries(), { batchSize: 10 })).toEqual(25);
+    expect(v8.deserialize(db.getSync("24")!)).toEqual(24);
+  });
+
   describe("reading", () => {
     beforeEach(async () => {
       db = new Lmdb({
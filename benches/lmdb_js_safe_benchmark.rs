@@ -1,20 +1,27 @@
 use std::hint::black_box;
 
-use criterion::{Criterion, criterion_group, criterion_main};
+use criterion::{BenchmarkId, Criterion, criterion_group, criterion_main};
 
 use lmdb_js_lite::writer::{DatabaseWriter, LMDBOptions};
 
-fn criterion_benchmark(c: &mut Criterion) {
-  let input = {
-    std::fs::remove_dir_all("benchmark-databases").unwrap();
-    std::fs::create_dir_all("benchmark-databases").unwrap();
-    DatabaseWriter::new(&LMDBOptions {
-      path: "benchmark-databases/test.db".to_string(),
-      async_writes: false,
-      map_size: Some(1024 * 1024 * 1024),
-    })
-    .unwrap()
-  };
+const NUM_ENTRIES: usize = 10000;
+const BATCH_SIZE: usize = 100;
+
+fn open_database(name: &str) -> DatabaseWriter {
+  let path = format!("benchmark-databases/{name}");
+  let _ = std::fs::remove_dir_all(&path);
+  std::fs::create_dir_all("benchmark-databases").unwrap();
+  DatabaseWriter::new(&LMDBOptions {
+    path,
+    async_writes: false,
+    map_size: Some(1024.0 * 1024.0 * 1024.0),
+    ..Default::default()
+  })
+  .unwrap()
+}
+
+fn write_benchmark(c: &mut Criterion) {
+  let input = open_database("test.db");
   let mut txn = input.environment().write_txn().unwrap();
   c.bench_function("inserting entries", |b| {
     b.iter(|| {
@@ -25,5 +32,57 @@ fn criterion_benchmark(c: &mut Criterion) {
   });
 }
 
-criterion_group!(benches, criterion_benchmark);
+fn read_benchmark(c: &mut Criterion) {
+  let input = open_database("reads.db");
+  // Compressible values, similar to serialized cache entries
+  let value: Vec<u8> = (0..4096).map(|i| (i % 64) as u8).collect();
+  let mut txn = input.environment().write_txn().unwrap();
+  for i in 0..NUM_ENTRIES {
+    input.put(&mut txn, &format!("compressed/{i}"), &value).unwrap();
+    input
+      .put_reserved(&mut txn, &format!("uncompressed/{i}"), value.len(), &value)
+      .unwrap();
+  }
+  txn.commit().unwrap();
+
+  let mut group = c.benchmark_group("reading entries");
+  for kind in ["compressed", "uncompressed"] {
+    // The same entry from a transaction that is kept open
+    group.bench_function(BenchmarkId::new("hot", kind), |b| {
+      let txn = input.environment().read_txn().unwrap();
+      let key = format!("{kind}/0");
+      b.iter(|| input.get(&txn, black_box(&key)).unwrap())
+    });
+    // A different entry from a new transaction each time
+    group.bench_function(BenchmarkId::new("cold", kind), |b| {
+      let mut i = 0;
+      b.iter(|| {
+        let txn = input.environment().read_txn().unwrap();
+        i = (i + 1) % NUM_ENTRIES;
+        input.get(&txn, black_box(&format!("{kind}/{i}"))).unwrap()
+      })
+    });
+  }
+
+  let keys: Vec<String> = (0..BATCH_SIZE).map(|i| format!("compressed/{i}")).collect();
+  group.bench_function(BenchmarkId::new("batched", BATCH_SIZE), |b| {
+    b.iter(|| {
+      let txn = input.environment().read_txn().unwrap();
+      for key in &keys {
+        black_box(input.get(&txn, key).unwrap());
+      }
+    })
+  });
+  group.bench_function(BenchmarkId::new("single", BATCH_SIZE), |b| {
+    b.iter(|| {
+      for key in &keys {
+        let txn = input.environment().read_txn().unwrap();
+        black_box(input.get(&txn, key).unwrap());
+      }
+    })
+  });
+  group.finish();
+}
+
+criterion_group!(benches, write_benchmark, read_benchmark);
 criterion_main!(benches);
@@ -3,6 +3,116 @@
 
 /* auto-generated by NAPI-RS */
 
+export const enum ErrorCode {
+  /** The handle or database has been closed */
+  DatabaseClosed = 'DatabaseClosed',
+  /** The database is still closing and can't be opened again yet */
+  DatabaseClosing = 'DatabaseClosing',
+  /**
+   * The database is already open in this process with different options, or
+   * was created with options that change how entries are stored
+   */
+  BadOpenOptions = 'BadOpenOptions',
+  /** The writer thread is no longer accepting messages */
+  WriterStopped = 'WriterStopped',
+  IOError = 'IOError',
+  /** The database files can't be accessed, `EACCES` or `EPERM` */
+  PermissionDenied = 'PermissionDenied',
+  /**
+   * There isn't enough disk space, either `ENOSPC` or a failed preflight
+   * check before a large write
+   */
+  InsufficientDiskSpace = 'InsufficientDiskSpace',
+  /** The database files are locked or in use, `EBUSY` or `EAGAIN` */
+  ResourceBusy = 'ResourceBusy',
+  CompressError = 'CompressError',
+  DecompressError = 'DecompressError',
+  EncodingError = 'EncodingError',
+  DecodingError = 'DecodingError',
+  /** A key is over [`crate::writer::LMDBOptions::max_key_length`] */
+  KeyTooLong = 'KeyTooLong',
+  /** A value is over [`crate::writer::LMDBOptions::max_value_size`] */
+  ValueTooLarge = 'ValueTooLarge',
+  /** The operation would reach outside of a tenant handle's keys */
+  TenantIsolation = 'TenantIsolation',
+  /** The operation would write to the database from a dry-run handle */
+  DryRun = 'DryRun',
+  /** The write was dropped from the queue by `cancelPending` */
+  Cancelled = 'Cancelled',
+  /**
+   * A transaction was committed since the snapshot was exported, see
+   * `importSnapshot`
+   */
+  SnapshotExpired = 'SnapshotExpired',
+  /**
+   * The write was made outside of an explicit write transaction, see
+   * [`crate::writer::LMDBOptions::require_explicit_transactions`]
+   */
+  TransactionRequired = 'TransactionRequired',
+  /**
+   * The explicit write transaction was aborted for exceeding its limits,
+   * see [`crate::writer::WriteTransactionLimitPolicy::Abort`]
+   */
+  TransactionAborted = 'TransactionAborted',
+  /** The operation isn't allowed by the handle's mode, see `withMode` */
+  HandleMode = 'HandleMode',
+  /**
+   * Resolving a promise panicked, see
+   * [`crate::writer::LMDBOptions::resolve_panic_policy`]
+   */
+  ResolveFailed = 'ResolveFailed',
+  /**
+   * An argument is out of range, such as an entry reaching past the end of
+   * its buffer
+   */
+  InvalidArgument = 'InvalidArgument',
+  /** `MDB_KEYEXIST` */
+  KeyExist = 'KeyExist',
+  /** `MDB_NOTFOUND` */
+  NotFound = 'NotFound',
+  /** `MDB_PAGE_NOTFOUND` */
+  PageNotFound = 'PageNotFound',
+  /** `MDB_CORRUPTED` */
+  Corrupted = 'Corrupted',
+  /** `MDB_PANIC` */
+  Panic = 'Panic',
+  /** `MDB_VERSION_MISMATCH` */
+  VersionMismatch = 'VersionMismatch',
+  /** `MDB_INVALID` */
+  Invalid = 'Invalid',
+  /** `MDB_MAP_FULL` */
+  MapFull = 'MapFull',
+  /** `MDB_DBS_FULL` */
+  DbsFull = 'DbsFull',
+  /** `MDB_READERS_FULL` */
+  ReadersFull = 'ReadersFull',
+  /** `MDB_TLS_FULL` */
+  TlsFull = 'TlsFull',
+  /** `MDB_TXN_FULL` */
+  TxnFull = 'TxnFull',
+  /** `MDB_CURSOR_FULL` */
+  CursorFull = 'CursorFull',
+  /** `MDB_PAGE_FULL` */
+  PageFull = 'PageFull',
+  /** `MDB_MAP_RESIZED` */
+  MapResized = 'MapResized',
+  /** `MDB_INCOMPATIBLE` */
+  Incompatible = 'Incompatible',
+  /** `MDB_BAD_RSLOT` */
+  BadRslot = 'BadRslot',
+  /** `MDB_BAD_TXN` */
+  BadTxn = 'BadTxn',
+  /** `MDB_BAD_VALSIZE` */
+  BadValSize = 'BadValSize',
+  /** `MDB_BAD_DBI` */
+  BadDbi = 'BadDbi',
+  /** `MDB_PROBLEM` */
+  Problem = 'Problem',
+  /** Any other LMDB return code */
+  LmdbError = 'LmdbError',
+  /** Failures in the N-API layer, such as failing to create JavaScript values */
+  Internal = 'Internal'
+}
 export interface LmdbOptions {
   /** The database directory path */
   path: string
@@ -21,24 +131,1104 @@ export interface LmdbOptions {
    * if this isn't set it'll default to around 10MB.
    */
   mapSize?: number
+  /**
+   * The maximum number of named databases that can be opened in the
+   * environment, see [`mdb_env_set_maxdbs`](http://www.lmdb.tech/doc/group__mdb.html#gaa2fc2f1f37cb1115e733b62cab2fcdbc).
+   * The named databases used internally don't count towards it.
+   *
+   * Defaults to 16.
+   */
+  maxDbs?: number
+  /**
+   * The name of the writer thread, as shown by debuggers and `ps`.
+   *
+   * Defaults to `lmdb-writer`. Linux truncates thread names to 15 bytes.
+   */
+  writerThreadName?: string
+  /**
+   * The scheduling priority of the writer thread. A background cache writer
+   * usually shouldn't compete with compilation threads at equal priority.
+   *
+   * Defaults to [`WriterThreadPriority::Normal`].
+   */
+  writerThreadPriority?: WriterThreadPriority
+  /**
+   * If set, the writer thread is stopped after this many milliseconds
+   * without messages, and transparently restarted on the next message.
+   *
+   * This is useful for processes that open many rarely used databases. The
+   * writer thread is never stopped while a write transaction is open.
+   */
+  writerIdleTimeoutMs?: number
+  /**
+   * What happens when resolving a message panics, for example because the
+   * promise's environment is tearing down.
+   *
+   * Defaults to [`ResolvePanicPolicy::Panic`].
+   */
+  resolvePanicPolicy?: ResolvePanicPolicy
+  /**
+   * If set, explicit write transactions open for longer than this many
+   * milliseconds are ended according to
+   * [`LMDBOptions::write_transaction_limit_policy`].
+   *
+   * A forgotten `commitWriteTransaction()` otherwise blocks all other writers
+   * indefinitely.
+   */
+  maxWriteTransactionAgeMs?: number
+  /**
+   * If set, explicit write transactions are ended according to
+   * [`LMDBOptions::write_transaction_limit_policy`] once this many entries
+   * have been written within them.
+   */
+  maxWriteTransactionOperations?: number
+  /**
+   * What to do with write transactions that exceed the limits above.
+   *
+   * Defaults to [`WriteTransactionLimitPolicy::Commit`].
+   */
+  writeTransactionLimitPolicy?: WriteTransactionLimitPolicy
+  /**
+   * If set, keys longer than this many bytes are handled according to
+   * [`LMDBOptions::size_limit_policy`]. LMDB itself rejects keys over 511
+   * bytes.
+   */
+  maxKeyLength?: number
+  /**
+   * If set, values larger than this many bytes are handled according to
+   * [`LMDBOptions::size_limit_policy`].
+   */
+  maxValueSize?: number
+  /**
+   * What to do with keys and values over the limits above.
+   *
+   * Defaults to [`SizeLimitPolicy::Reject`].
+   */
+  sizeLimitPolicy?: SizeLimitPolicy
+  /**
+   * If enabled, keys longer than LMDB's maximum key size are stored under a
+   * hash of the key. The original key is kept in a side table and checked
+   * on reads, so hash collisions read as missing entries rather than
+   * returning another key's value.
+   *
+   * Can't be changed once the database has entries.
+   */
+  hashLongKeys?: boolean
+  /**
+   * Transforms applied to values on the writer thread before they are
+   * compressed, in order
+   */
+  valueTransforms?: Array<ValueTransform>
+  /**
+   * If enabled, reader slots left in `lock.mdb` by processes that are no
+   * longer running are cleared when the database is opened. Otherwise stale
+   * readers from a crashed process keep old pages from being reused until
+   * LMDB happens to check for them.
+   */
+  autoRecoverLock?: boolean
+  /**
+   * How point lookups read the memory map. Scans, such as `deleteWhere`,
+   * `copyEntries` with a prefix and `cloneTo`, switch to
+   * [`AccessPattern::Sequential`] while they run. That applies to the whole
+   * map, so point lookups running meanwhile read with it too. Only has an
+   * effect on Linux.
+   *
+   * Defaults to [`AccessPattern::Normal`].
+   */
+  accessPattern?: AccessPattern
+  /**
+   * How keys are normalized before entries are written or looked up.
+   * Handles apply it to keys and prefixes passed from JavaScript.
+   *
+   * Defaults to [`KeyCollation::Binary`]. Can't be changed once the database
+   * has entries.
+   */
+  keyCollation?: KeyCollation
+  /**
+   * If set, this many entries spread across the database are read when it
+   * is opened. If they show that the last transaction is corrupted, the
+   * database is reopened at the previous snapshot, discarding that
+   * transaction, see [`DatabaseWriter::recovery`].
+   *
+   * Falling back requires that no other process has the database open.
+   */
+  verifyOnOpen?: number
+  /**
+   * If enabled, disk space for the whole map size is allocated when the
+   * database is opened, so file systems don't have to allocate blocks
+   * while entries are written. Only supported on Linux. Fails with
+   * `ErrorCode.InsufficientDiskSpace` if there isn't room for it.
+   */
+  preallocate?: boolean
+  /**
+   * If enabled along with [`LMDBOptions::preallocate`], every page of the
+   * memory map is read once when the database is opened, so the first
+   * writes don't fault pages in. This makes opening slower, in proportion
+   * to the map size.
+   */
+  pretouch?: boolean
+  /**
+   * If set, every message handled by the writer thread is recorded in a
+   * journal at this path, with its key, size, timing and outcome, to
+   * diagnose missing or unexpected entries.
+   */
+  journalPath?: string
+  /**
+   * Size of the journal in bytes before it is moved to `<path>.1`, replacing
+   * the previous one. Defaults to 10MB.
+   */
+  journalMaxBytes?: number
+  /**
+   * If enabled, deleting an entry leaves a tombstone with the id of the
+   * transaction that deleted it, until it's written again or the tombstone
+   * is purged, so change feeds and replicas can tell deleted entries apart
+   * from entries they haven't seen yet.
+   *
+   * Deleted entries are kept, marked as tombstoned, until their tombstones
+   * are purged. Reads and scans treat them as missing in the meantime.
+   */
+  tombstones?: boolean
+  /**
+   * If enabled, the id of the transaction that last wrote each entry is
+   * recorded, so `deleteWhere` can delete entries by age with
+   * `olderThanTxnId`. This takes an extra write per entry. Entries written
+   * while it was disabled count as written before any transaction.
+   */
+  trackWriteTxnIds?: boolean
+  /**
+   * If enabled, writes and deletes made outside of `startWriteTransaction`
+   * are rejected with `ErrorCode.TransactionRequired` instead of being
+   * committed in a transaction of their own, to catch unbatched writes.
+   */
+  requireExplicitTransactions?: boolean
+  /**
+   * If set, values of at least this many bytes are stored once no matter
+   * how many keys hold them. Each key refers to the value by its hash, and
+   * the value is deleted once no key refers to it anymore.
+   *
+   * Keep this set once deduplicated values have been written, otherwise
+   * deleting their keys doesn't release them.
+   */
+  dedupMinSize?: number
+  /**
+   * If enabled, the journal, tracing spans and logged errors record a hash
+   * of each key instead of the key itself, so they can be shared in bug
+   * reports without exposing file paths. Values are never recorded.
+   */
+  redactKeys?: boolean
+  /**
+   * If set, reads and writes are counted per key prefix, the part of the
+   * key up to and including the first occurrence of this separator, see
+   * [`DatabaseWriter::access_stats`]. Keys without it count towards the
+   * empty prefix.
+   */
+  accessStatsSeparator?: string
+  /**
+   * Fraction of reads and writes counted for
+   * [`LMDBOptions::access_stats_separator`], with counts scaled back up
+   * when reported. Defaults to 0.01.
+   */
+  accessStatsSampleRate?: number
+  /**
+   * If enabled, the database is opened read-only, for processes reading a
+   * database another process writes to, see [`crate::LMDB::open_read_only`].
+   * Handles onto it can't write, and nothing is written to the directory
+   * when it's opened.
+   */
+  readOnly?: boolean
+}
+/** How keys are normalized, see [`LMDBOptions::key_collation`] */
+export const enum KeyCollation {
+  /** Keys are used as they are */
+  Binary = 'binary',
+  /**
+   * Keys are lowercased, so keys that only differ in case, such as paths on
+   * case-insensitive file systems, refer to the same entry
+   */
+  CaseInsensitive = 'caseInsensitive',
+  /**
+   * Keys are normalized to Unicode NFC, so keys that only differ in how
+   * accented characters are composed, such as paths on macOS file systems,
+   * refer to the same entry
+   */
+  Nfc = 'nfc'
+}
+/** What [`DatabaseWriter::benchmark`] measures */
+export const enum BenchmarkMode {
+  /**
+   * Writes committed one transaction each, which is bound by how fast the
+   * file system syncs
+   */
+  Write = 'write',
+  /** Writes in a single transaction, which is bound by compression and LMDB */
+  BatchWrite = 'batchWrite',
+  /** Reads of entries written beforehand */
+  Read = 'read'
+}
+/** Built-in preprocessing of values, see [`LMDBOptions::value_transforms`] */
+export const enum ValueTransform {
+  /**
+   * Store gzip and zstd compressed values as-is instead of compressing them
+   * again
+   */
+  CompressedPassthrough = 'compressedPassthrough',
+  /**
+   * Remove whitespace outside of strings from values that start with `{` or
+   * `[`. Only enable this for databases that store JSON.
+   */
+  MinifyJson = 'minifyJson'
+}
+/** What to do with keys or values over the size limits */
+export const enum SizeLimitPolicy {
+  /** Fail the write */
+  Reject = 'reject',
+  /**
+   * Store overlong keys as their truncated prefix followed by a hash of the
+   * whole key. Oversized values are rejected.
+   */
+  TruncateKeyHash = 'truncateKeyHash',
+  /**
+   * Split oversized values into chunks of at most `maxValueSize` bytes,
+   * reassembled when read. Overlong keys are rejected, as are keys of
+   * oversized values within 9 bytes of LMDB's maximum key size, which is
+   * what each chunk adds to the key.
+   */
+  ChunkValue = 'chunkValue'
+}
+/** What to do with an explicit write transaction that exceeds its limits */
+export const enum WriteTransactionLimitPolicy {
+  /** Commit the transaction, keeping its writes */
+  Commit = 'commit',
+  /**
+   * Abort the transaction, discarding its writes. Writes and the commit
+   * that follow are rejected with `ErrorCode.TransactionAborted`, until a
+   * new transaction is started.
+   */
+  Abort = 'abort'
+}
+/**
+ * How the memory map is expected to be read, passed on to `madvise`. Only
+ * has an effect on Linux.
+ *
+ * The advice covers the whole memory map, as the pages of a range of keys
+ * aren't next to each other in it, so it applies to every read of the
+ * database in this process while it's in effect.
+ */
+export const enum AccessPattern {
+  /** The kernel's default read-ahead */
+  Normal = 'normal',
+  /** Read ahead aggressively and free pages soon after they have been read */
+  Sequential = 'sequential',
+  /** Don't read ahead, keeping resident memory down for point lookups */
+  Random = 'random'
+}
+/** Scheduling priority of the writer thread */
+export const enum WriterThreadPriority {
+  /** Keep the process default priority */
+  Normal = 'normal',
+  /** Niceness 10 on Linux, `QOS_CLASS_UTILITY` on macOS */
+  Low = 'low',
+  /** Niceness 19 on Linux, `QOS_CLASS_BACKGROUND` on macOS */
+  Background = 'background'
+}
+/** See [`LMDBOptions::resolve_panic_policy`] */
+export const enum ResolvePanicPolicy {
+  /** Let the panic unwind the writer thread, which stops handling messages */
+  Panic = 'panic',
+  /**
+   * Report a [`DatabaseWriterError::ResolvePanicked`] to the error listener
+   * and keep handling messages
+   */
+  Report = 'report'
 }
 function initTracingSubscriber(): void
+/**
+ * Limit how many databases are open at once, or lift the limit with `null`.
+ *
+ * Opening a database beyond the limit first flushes and closes the least
+ * recently used ones that have no queued writes or open write transaction.
+ * Handles onto a closed database fail with `ErrorCode.DatabaseClosed`, and
+ * its environment is released once they're closed or garbage collected.
+ * Databases that are busy are left open, even if that exceeds the limit.
+ */
+function setMaxOpenEnvironments(max?: number | undefined | null): void
+/**
+ * Move the clock forward by `ms` milliseconds, for every database in the
+ * process. Only available in builds with the `test-clock` feature, and
+ * undefined in other builds.
+ */
+const advanceClock: ((ms: number) => void) | undefined
+/** How an entry is encoded on disk */
+export const enum Codec {
+  /** LZ4 block compression, prefixed with the uncompressed size */
+  Lz4 = 'lz4',
+  /**
+   * Stored as-is, for values that don't compress and
+   * [`DatabaseWriter::put_reserved`]
+   */
+  None = 'none'
+}
 export interface Entry {
   key: string
   value: Buffer
 }
+export interface BufferEntry {
+  key: string
+  /** Where the value starts in the shared buffer */
+  offset: number
+  /** Length of the value in bytes */
+  length: number
+}
+export interface RawEntriesOptions {
+  /** Continue after this key, as returned in `next` */
+  after?: string
+  /** Defaults to 1000 */
+  limit?: number
+}
+export interface RawEntry {
+  key: string
+  /** The entry as it is stored, as returned by `getRaw` */
+  value: Buffer
+  codec: Codec
+}
+export interface RawEntries {
+  entries: Array<RawEntry>
+  /**
+   * Key to pass as `after` to read the next entries, unset once there is
+   * nothing left to read
+   */
+  next?: string
+}
+export interface TombstoneEntry {
+  key: string
+  /**
+   * Id of the transaction that deleted the entry, comparable with
+   * `lastTxnId` from `getEntryMeta`
+   */
+  txnId: number
+}
+export interface Tombstones {
+  tombstones: Array<TombstoneEntry>
+  /**
+   * Key to pass as `after` to read the next tombstones, unset once there
+   * is nothing left to read
+   */
+  next?: string
+}
+export interface PurgeTombstonesOptions {
+  /** Only purge tombstones left by transactions before this one */
+  beforeTxnId?: number
+}
+export interface DatabaseKey {
+  /** The name of the named database to read from */
+  db: string
+  key: string
+}
+export interface EntryMeta {
+  value: Buffer
+  /** Size of the value as stored in the database, in bytes */
+  compressedSize: number
+  /** Size of the decoded value, in bytes */
+  uncompressedSize: number
+  codec: Codec
+  /**
+   * Id of the last committed transaction when the entry was read. Not set
+   * when reading within an explicit read transaction, since that snapshot
+   * may be older.
+   */
+  lastTxnId?: number
+}
+export interface WriteTransactionState {
+  /** Whether an explicit write transaction is open */
+  active: boolean
+  /** Number of entries written within the open transaction */
+  operations: number
+  /** Milliseconds since the open transaction was started */
+  ageMs?: number
+}
+export interface ReadTransactionOptions {
+  /** Replace a snapshot that is already held with a new one */
+  refresh?: boolean
+}
+export interface GetManyOptions {
+  /** Fail the whole batch when an entry can't be read. Defaults to `true`. */
+  stopOnError?: boolean
+}
+export interface EntryError {
+  code: ErrorCode
+  message: string
+}
+/** The result for one key of `getManySync` with `stopOnError: false` */
+export interface GetManyResult {
+  /** Unset if the entry is missing or couldn't be read */
+  value?: Buffer
+  /** Set if the entry couldn't be read */
+  error?: EntryError
+}
+/** Values read by `getManyPacked` */
+export interface PackedValues {
+  /**
+   * Each value found, as its length in a little-endian `u32` followed by
+   * its bytes
+   */
+  buffer: Buffer
+  /**
+   * Offset of the length of each key's value in `buffer`, or `-1` if the
+   * key is missing
+   */
+  offsets: Array<number>
+}
+/** A message in the writer thread's queue */
+export interface PendingOperation {
+  /** Name of the method that sent the message */
+  operation: string
+  /** The key written or read, for operations on a single key */
+  key?: string
+}
+/** A database shared with other processes, see [`LMDB::share_read_only`] */
+export interface SharedEnvironment {
+  path: string
+  /** Id of the last transaction committed when the database was shared */
+  generation: number
+  /** Size of the memory map in bytes */
+  mapSize: number
+}
+export interface MoveToOptions {
+  /** Delete the old database directory once the database has been moved */
+  deleteOld?: boolean
+}
+export interface ReadReplicaOptions {
+  /** How often the snapshot read from is replaced with a new one */
+  refreshMs: number
+}
+export interface WriterErrorEvent {
+  /** Name of the operation that failed */
+  operation: string
+  code: ErrorCode
+  message: string
+}
+export interface CommitEvent {
+  /** Number of entries written in the transaction */
+  operations: number
+  /** Number of key and value bytes written, before compression */
+  bytes: number
+  /** Milliseconds it took to commit the transaction */
+  durationMs: number
+}
+export interface StartupRecovery {
+  /** Id of the corrupted transaction that was discarded */
+  discardedTxnId: number
+  /** The error reading it */
+  reason: string
+}
+export interface KeyOrderInfo {
+  /**
+   * Always `bytewise`: keys are compared byte by byte, with a key ordered
+   * before any longer key it is a prefix of. This is LMDB's default and
+   * doesn't depend on the platform or locale.
+   */
+  comparator: string
+  /**
+   * Always `utf8`. Comparing UTF-8 bytes orders keys by code point, which
+   * differs from comparing JavaScript strings for characters outside of
+   * the Basic Multilingual Plane.
+   */
+  encoding: string
+  /** Keys are compared after being collated */
+  collation: KeyCollation
+  /**
+   * Whether some keys may be stored under a hash, with `hashLongKeys` or
+   * the `truncateKeyHash` size limit policy. Those entries are ordered by
+   * their hash rather than by their key.
+   */
+  hashedKeys: boolean
+}
+export interface PrefixAccessStats {
+  prefix: string
+  /** Estimated number of reads of keys with the prefix */
+  reads: number
+  /** Estimated number of writes and deletes of keys with the prefix */
+  writes: number
+}
+export interface AccessStats {
+  /** Prefixes ordered from the most to the least accessed */
+  prefixes: Array<PrefixAccessStats>
+  /**
+   * Estimated number of reads of prefixes beyond the number counted
+   * separately
+   */
+  untrackedReads: number
+  /**
+   * Estimated number of writes of prefixes beyond the number counted
+   * separately
+   */
+  untrackedWrites: number
+}
+export interface WriterMetrics {
+  /** Number of messages handled by the writer thread */
+  messages: number
+  /** Number of write transactions committed */
+  commits: number
+  /**
+   * Milliseconds messages spent queued before the writer thread picked them
+   * up
+   */
+  queueWaitMs: number
+  /** Milliseconds spent transforming and compressing values */
+  compressionMs: number
+  /** Milliseconds spent writing entries into LMDB */
+  putMs: number
+  /** Milliseconds spent committing write transactions */
+  commitMs: number
+}
+export interface OverlayChange {
+  key: string
+  /** Size of the entry in the database, if there is one */
+  oldSize?: number
+  /** Size the entry would take up once written */
+  newSize: number
+}
+export interface PutManyOptions {
+  /**
+   * Skip entries whose key already holds the same value, without
+   * compressing or writing them again. Values are compared within the
+   * write transaction. Defaults to false.
+   */
+  skipUnchanged?: boolean
+}
+export interface CopyEntriesOptions {
+  /**
+   * Copy values as they are stored instead of decompressing them and
+   * compressing them again in the target database. Defaults to true.
+   */
+  raw?: boolean
+}
+export interface ValueSizeFilter {
+  /** Only include entries taking up at least this many bytes on disk */
+  minValueSize?: number
+  /** Only include entries taking up at most this many bytes on disk */
+  maxValueSize?: number
+}
+export interface KeyHistogramOptions {
+  /** Separates the segments of a key, defaults to `:` */
+  delimiter?: string
+  /** Number of segments making up a namespace, defaults to 1 */
+  depth?: number
+}
+export interface KeyHistogramEntry {
+  namespace: string
+  entries: number
+  keyBytes: number
+  /** Bytes taken up on disk by the values */
+  valueBytes: number
+}
+/** Which entries `deleteWhere` deletes */
+export interface DeleteWhereFilter {
+  /** Only delete keys starting with this prefix */
+  prefix?: string
+  /**
+   * Only delete entries last written by a transaction before this one, such
+   * as an id returned by `startReadTransaction`. Requires the database to
+   * be opened with `trackWriteTxnIds`.
+   */
+  olderThanTxnId?: number
+  /** Only delete entries taking up more than this many bytes on disk */
+  largerThanBytes?: number
+}
+export interface DeleteWhereResult {
+  /** Number of entries looked at */
+  scanned: number
+  deleted: number
+  /** Bytes taken up on disk by the deleted entries */
+  bytes: number
+}
+export interface BenchmarkOptions {
+  /** Defaults to 1000 */
+  ops?: number
+  /** Size of each value in bytes, defaults to 1024 */
+  valueSize?: number
+  /** Defaults to `write` */
+  mode?: BenchmarkMode
+}
+export interface BenchmarkResult {
+  ops: number
+  durationMs: number
+  opsPerSecond: number
+  /** Latencies of a single operation */
+  p50Ms: number
+  p90Ms: number
+  p99Ms: number
+  maxMs: number
+}
+/** What a handle is allowed to do, see [`LMDB::with_mode`] */
+export const enum HandleMode {
+  Read = 'read',
+  Write = 'write',
+  ReadWrite = 'readwrite'
+}
 export type LMDB = Lmdb
 export declare class Lmdb {
   constructor(options: LmdbOptions)
+  /**
+   * Open a database shared by another process with `shareReadOnly`, for
+   * example by the process that spawned this one, so workers can read its
+   * cache without taking part in writes. The returned handle can only read.
+   *
+   * Fails with `ErrorCode.InvalidArgument` if the database is older than
+   * when it was shared, which means the directory was replaced since.
+   *
+   * If the writing process grows the memory map past its size when it was
+   * shared, the new size is adopted by the next read that starts while no
+   * other read transaction of this process is open on the database, such
+   * as one started with `startReadTransaction` or held by a read replica.
+   * Until then, reads fail with `ErrorCode.MapResized`.
+   */
+  static openReadOnly(shared: SharedEnvironment): Lmdb
+  /**
+   * Describe this database for another process to open with
+   * `Lmdb.openReadOnly`, for example a worker it spawns. The result can be
+   * passed as JSON.
+   *
+   * The generation is the id of the last committed transaction, so the
+   * other process can tell it opened the same database. Writes queued
+   * but not committed yet aren't covered, await `fence()` first to include
+   * them.
+   */
+  shareReadOnly(): SharedEnvironment
+  /**
+   * Create a lightweight handle onto the same database where all keys are
+   * transparently prefixed with `prefix`.
+   *
+   * This can be used to emulate sub-databases without paying for string
+   * concatenation on the JavaScript side. Prefixes compose, so
+   * `db.withPrefix("a:").withPrefix("b:")` writes keys as `a:b:<key>`.
+   */
+  withPrefix(prefix: string): Lmdb
+  /**
+   * Create a handle onto the same database whose operations are traced
+   * with `traceId`. The writer thread handles their messages within a
+   * `writer_message` span with a `trace_id` field, which also covers how
+   * long they were queued, so request tracing can include cache latencies.
+   */
+  withTraceId(traceId: string): Lmdb
+  /**
+   * Create a handle onto the same database that can only read or only write,
+   * for libraries to hand out handles scoped to what they need. Calls the
+   * handle isn't allowed to make fail with [`ErrorCode::HandleMode`]
+   * without reaching the writer thread.
+   *
+   * Handles created from the handle keep its mode, which can only be
+   * narrowed further.
+   */
+  withMode(mode: HandleMode): Lmdb
   get(key: string): Promise<Buffer | null | undefined>
+  /**
+   * Create a handle onto the same database that can only reach the keys of
+   * `tenant`, for servers sharing one database between tenants.
+   *
+   * Keys are prefixed with the tenant id and a NUL separator, so tenants
+   * never see each other's keys. Operations that would reach outside of the
+   * tenant's keys, such as `cloneTo` and `getManyFrom`, fail with
+   * `ErrorCode.TenantIsolation`. Handles created from a tenant handle stay
+   * bound to the tenant.
+   */
+  forTenant(tenant: string): Lmdb
+  /**
+   * Create a handle onto the same database whose writes are kept in an
+   * in-memory overlay instead of being written to the database, to see what
+   * an operation would write.
+   *
+   * `get`, `getSync` and `getManySync` on the handle see the overlay, other
+   * reads only see the database. Writes that can't be kept in the overlay,
+   * such as `deleteWhere` and `copyEntries` into the handle, fail with
+   * `ErrorCode.DryRun`. Handles created from a dry-run handle share its
+   * overlay.
+   */
+  dryRun(): Lmdb
+  /**
+   * List the writes held by a dry-run handle under this handle's prefix,
+   * with the size of each entry in the database before and after they would
+   * be written. Sizes are of the compressed values.
+   */
+  overlayDiff(): Array<OverlayChange>
+  /**
+   * Create a handle onto the same database whose synchronous reads come
+   * from a shared snapshot, replaced with a new one on the first read more
+   * than `refreshMs` after it was taken.
+   *
+   * Reads don't need to create a transaction each, but may not see writes
+   * committed since the last refresh. Handles created from the replica
+   * share its snapshot, which is released once they are all closed. A read
+   * transaction started on the handle takes precedence over the snapshot.
+   *
+   * Like a read transaction, the snapshot takes up a reader slot while it's
+   * held.
+   */
+  openReadReplica(options: ReadReplicaOptions): Lmdb
+  /**
+   * Take a snapshot of the database that handles in other threads can read
+   * from, returning an id to pass to them.
+   *
+   * A worker opens the same database and calls `importSnapshot(id)` to get a
+   * handle whose synchronous reads come from the snapshot, so it sees the
+   * same values as every other reader of it regardless of later writes.
+   * Each importing handle reads through its own read transaction, so
+   * workers read in parallel.
+   *
+   * LMDB can only start read transactions on the latest snapshot, so
+   * workers have to import it before the next write transaction commits.
+   * The id can be imported until it's released with `releaseSnapshot(id)`.
+   */
+  exportSnapshot(): number
+  /**
+   * Create a handle onto the same database whose synchronous reads come
+   * from a snapshot exported with `exportSnapshot`, possibly on another
+   * thread. A read transaction started on the handle takes precedence over
+   * the snapshot. Handles created from it share its read transaction, which
+   * is released once they are all closed.
+   *
+   * Fails with `ErrorCode.InvalidArgument` if there's no snapshot with the
+   * id or it was taken of another database, and with
+   * `ErrorCode.SnapshotExpired` if a write transaction was committed since
+   * it was exported.
+   */
+  importSnapshot(id: number): Lmdb
+  /**
+   * Release a snapshot exported with `exportSnapshot`. Handles that already
+   * imported it keep reading from it until they're closed. Returns whether
+   * there was a snapshot with the id.
+   */
+  releaseSnapshot(id: number): boolean
+  /**
+   * Read keys out of multiple named databases within a single read
+   * transaction, so that values are consistent with each other.
+   *
+   * Fails with `ErrorCode.InvalidArgument` for the named databases used
+   * internally, whose names start with `__lmdb_js_lite/`.
+   */
+  getManyFrom(keys: Array<DatabaseKey>): Promise<Array<Buffer | null | undefined>>
   getSync(key: string): Buffer | null
-  getManySync(keys: Array<string>): Array<Buffer | undefined | null>
-  putMany(entries: Array<Entry>): Promise<void>
+  /**
+   * Read many entries at once. By default the whole batch fails if any of
+   * them can't be read. With `stopOnError: false` a result is returned for
+   * each key instead, holding either its value or the error reading it.
+   */
+  getManySync(keys: Array<string>, options?: GetManyOptions & { stopOnError?: true }): Array<Buffer | undefined | null>
+  getManySync(keys: Array<string>, options: GetManyOptions & { stopOnError: false }): Array<GetManyResult>
+  /**
+   * Read many entries into a single buffer, saving the allocation of a
+   * buffer per value when reading thousands of small entries.
+   */
+  getManyPacked(keys: Array<string>): PackedValues
+  /** Read an entry along with how much space it takes up on disk. */
+  getEntryMeta(key: string): EntryMeta | null
+  /**
+   * Read an entry as it is stored, without decompressing it. The result can
+   * be written into another database with `putRaw`.
+   */
+  getRaw(key: string): Buffer | null
+  /**
+   * Read up to `limit` entries in key order as they are stored, without
+   * decompressing them, for tools that move entries between databases with
+   * `putRaw`.
+   *
+   * Each call reads from its own snapshot unless a read transaction is
+   * open on the handle.
+   */
+  getRawEntries(options?: RawEntriesOptions | undefined | null): RawEntries
+  /**
+   * Read up to `limit` tombstones left by deletes when the database is
+   * opened with `tombstones` enabled, in key order. Tombstones only hold
+   * the deleted keys, not their values.
+   */
+  getTombstones(options?: RawEntriesOptions | undefined | null): Tombstones
+  /**
+   * Read up to `count` pseudo-random entries as they are stored, jumping to
+   * random keys instead of scanning the database. Useful to estimate value
+   * sizes and how well they compress on large databases.
+   *
+   * Entries aren't picked uniformly, keys in small namespaces are more
+   * likely to be picked than keys in large ones, and fewer entries are
+   * returned if the same ones keep being picked.
+   */
+  sample(count: number): Array<RawEntry>
+  /**
+   * Count entries and their sizes by namespace, the first `depth` segments
+   * of their keys when split by `delimiter`, for a `du`-style view of what
+   * takes up space. Keys with fewer segments are their own namespace.
+   *
+   * Only keys are scanned, values aren't read.
+   */
+  keyHistogram(options?: KeyHistogramOptions | undefined | null): Array<KeyHistogramEntry>
+  /**
+   * List the keys of entries whose stored, compressed size is within the
+   * bounds in `filter`. Useful to find the entries that dominate disk usage.
+   */
+  getKeysBy(filter: ValueSizeFilter): Array<string>
+  putMany(entries: Array<Entry>, options?: PutManyOptions | undefined | null): Promise<void>
+  /**
+   * Like `putMany`, with values read out of a single `buffer`, so values
+   * that are already assembled into one buffer don't need a `Buffer` each.
+   * Fails with `ErrorCode.InvalidArgument` if an entry reaches past the end
+   * of `buffer`, without writing anything.
+   */
+  putManyFromBuffer(buffer: Buffer, entries: Array<BufferEntry>): Promise<void>
   put(key: string, data: Buffer): Promise<void>
+  /**
+   * Write an entry that is deleted again when the write transaction it was
+   * written in commits, for intermediate state that should never persist.
+   * Outside of a transaction, it's deleted the next time the database is
+   * opened once this process has exited, unless it's written again with
+   * `put`.
+   */
+  putTemp(key: string, data: Buffer): Promise<void>
+  /**
+   * Write a value under the hash of its contents, resolving with that key.
+   * Values that are already stored aren't written again, so identical
+   * values share a single entry.
+   */
+  putContent(data: Buffer): Promise<string>
+  /**
+   * Write a value uncompressed into `size` bytes reserved directly in the
+   * memory map, zero-padding anything `data` doesn't fill. Useful for large
+   * values that don't compress well.
+   *
+   * `data` is copied before this returns, as JavaScript could change the
+   * buffer while the writer thread reads it. Reads return all `size` bytes,
+   * including the padding.
+   */
+  putReserved(key: string, size: number, data: Buffer): Promise<void>
+  /** Store a value returned by `getRaw` without compressing it again */
+  putRaw(key: string, data: Buffer): Promise<void>
+  /**
+   * Write a value of up to 128 bytes and block until it's committed, rather
+   * than returning a promise. The value is stored uncompressed, and tiny
+   * writes queued together, such as from several workers, are committed in
+   * a single transaction.
+   *
+   * This is meant for the small metadata entries incremental builds write
+   * many of. The calling thread also waits for any writes queued before
+   * this one, so prefer `put` while large writes are in flight. Larger
+   * values fail with `ErrorCode.ValueTooLarge`.
+   */
+  putTinySync(key: string, data: Buffer): void
   putNoConfirm(key: string, data: Buffer): void
-  startReadTransaction(): void
+  /**
+   * Delete every entry matching all conditions in `filter`. The entries are
+   * scanned on the writer thread, in batched transactions unless an explicit
+   * write transaction is open.
+   *
+   * Fails with `ErrorCode.InvalidArgument` if `olderThanTxnId` is set but
+   * the database wasn't opened with `trackWriteTxnIds`.
+   */
+  deleteWhere(filter: DeleteWhereFilter): Promise<DeleteWhereResult>
+  /**
+   * Add a reference to an entry, resolving with its new reference count.
+   * Counts are kept apart from the entries and can be used to share
+   * `putContent` entries, see `sweepUnreferenced`.
+   */
+  addRef(key: string): Promise<number>
+  /**
+   * Release a reference to an entry, resolving with its new reference
+   * count. Counts don't go below zero.
+   */
+  release(key: string): Promise<number>
+  /**
+   * Delete the entries whose references have all been released, across
+   * the whole database. Entries that never had a reference added are left
+   * alone.
+   */
+  sweepUnreferenced(): Promise<DeleteWhereResult>
+  /**
+   * Remove tombstones across the whole database, resolving with how many
+   * were removed. Once every consumer has seen deletions up to a
+   * transaction, pass it as `beforeTxnId` to keep the tombstones of later
+   * deletions. The entries the purged tombstones mark as deleted are
+   * removed along with them.
+   */
+  purgeTombstones(options?: PurgeTombstonesOptions | undefined | null): Promise<number>
+  /**
+   * Measure write or read throughput of this environment with its current
+   * options. Runs on the writer thread between other writes, using keys
+   * that are deleted once it's done.
+   */
+  benchmark(options?: BenchmarkOptions | undefined | null): Promise<BenchmarkResult>
+  /**
+   * Copy the database into `path`, compacting it along the way, and open the
+   * copy as a new independent database.
+   *
+   * Writes queued before this call are included in the copy, but writes in a
+   * write transaction that hasn't been committed yet are not.
+   */
+  cloneTo(path: string): Promise<Lmdb>
+  /**
+   * Move the database to `path`, compacting it along the way. Every handle
+   * onto the database, in any thread, uses the database at the new path
+   * once the returned promise resolves.
+   *
+   * Writes queued before this call are included. Operations started while
+   * the database is being copied fail with `ErrorCode.DatabaseClosed`, as
+   * do reads through handles holding a read transaction, snapshot or read
+   * replica of the old database. Fails with `ErrorCode.BadTxn` if a write
+   * transaction is open.
+   *
+   * With `deleteOld`, the old directory is deleted afterwards. Its space is
+   * only reclaimed once all handles are closed, as they keep it mapped.
+   */
+  moveTo(path: string, moveOptions?: MoveToOptions | undefined | null): Promise<void>
+  /**
+   * Copy entries into the database `target` is a handle for, resolving to
+   * the number of entries copied. Entries are given either as a list of keys
+   * or as a key prefix, and are written under the same keys relative to
+   * each handle's prefix.
+   *
+   * The copy runs on the target's writer thread, reading from a single
+   * snapshot of this database.
+   */
+  copyEntries(target: Lmdb, keysOrPrefix: Array<string> | string, options?: CopyEntriesOptions | undefined | null): Promise<number>
+  /**
+   * Write `entries` to this database and `otherEntries` to the database
+   * `other` is a handle for, so that either both are written or, if the
+   * process exits part way through, the rest is written the next time both
+   * databases are open in the same process.
+   *
+   * This database commits its entries along with a marker recording
+   * `otherEntries`, then `other` commits its entries and the marker is
+   * cleared. If writing to `other` fails the promise rejects and the marker
+   * is kept, so the write is retried when the databases are next opened.
+   * `other` records which batches it applied, so a retry doesn't overwrite
+   * entries written to it since. Fails if a write transaction is open on
+   * either database.
+   *
+   * The marker holds a full copy of `otherEntries` until the batch
+   * completes, so large values take up space in both databases meanwhile.
+   */
+  coordinatedBatch(entries: Array<Entry>, other: Lmdb, otherEntries: Array<Entry>): Promise<void>
+  /**
+   * Start reading from a snapshot of the database, returning the id of the
+   * transaction the snapshot was taken after.
+   *
+   * If a snapshot is already held it is kept and its id returned, unless
+   * `refresh` is set, in which case it is replaced with a new snapshot.
+   */
+  startReadTransaction(options?: ReadTransactionOptions | undefined | null): number
   commitReadTransaction(): void
   startWriteTransaction(): Promise<void>
+  /**
+   * Report whether the writer holds an explicit write transaction, how many
+   * writes are pending in it, and for how long it has been open.
+   */
+  writeTransactionState(): Promise<WriteTransactionState>
+  /**
+   * Resolve with the id of the last transaction committed once the writes
+   * sent before this call have been handled, without flushing anything.
+   *
+   * External systems can compare these ids to order their actions
+   * relative to writes. Writes in an open write transaction aren't
+   * committed yet, so they're not covered until it's committed. With
+   * `asyncWrites`, committed transactions may not have been synced to disk.
+   */
+  fence(): Promise<number>
   commitWriteTransaction(): Promise<void>
+  /**
+   * List the operations the writer thread hasn't picked up yet, in the
+   * order they were sent, optionally only those for `key`. Operations on
+   * many keys are only listed on handles without a prefix.
+   */
+  pendingOps(key?: string | undefined | null): Array<PendingOperation>
+  /**
+   * Drop the writes to `key` that are still queued, for example once a
+   * newer value supersedes them, returning how many were dropped. Their
+   * promises reject with `ErrorCode.Cancelled`. Only writes to a single
+   * key can be cancelled, not `putMany`.
+   */
+  cancelPending(key: string): number
+  /**
+   * Time spent in each stage of the write pipeline since the database was
+   * opened, to tell whether writes are bound by compression or by IO
+   */
+  writerMetrics(): WriterMetrics
+  /**
+   * Reads and writes per key prefix since the database was opened, to find
+   * namespaces worth pinning or evicting. Empty unless the database was
+   * opened with `accessStatsSeparator`.
+   *
+   * Only a sample of accesses is counted, see `accessStatsSampleRate`, so
+   * counts are estimates. Up to 1000 prefixes are counted separately.
+   */
+  getAccessStats(): AccessStats
+  /**
+   * The transaction discarded when the database was opened with
+   * `verifyOnOpen`, if its last transaction was found to be corrupted
+   */
+  startupRecovery(): StartupRecovery | null
+  /**
+   * How keys are ordered by range scans such as `getKeysBy`, and by
+   * anything else that reads entries in order. The order is stable across
+   * platforms for databases opened with the same options.
+   */
+  keyOrderInfo(): KeyOrderInfo
+  /**
+   * Call `callback` after every write transaction commits with the number of
+   * entries and bytes written, and how long the commit took. Passing `null`
+   * removes the callback.
+   *
+   * There is a single callback per database, shared by all of its handles.
+   */
+  onCommit(callback: ((event: CommitEvent) => void) | null): void
+  /**
+   * Call `callback` with errors that no promise is waiting for, such as
+   * failed `putNoConfirm` writes or failing to commit a write transaction
+   * that exceeded its limits. Passing `null` removes the callback, after
+   * which these errors are only logged.
+   *
+   * With `resolvePanicPolicy: 'report'`, promises that fail to resolve are
+   * reported here with `ErrorCode.ResolveFailed`.
+   *
+   * There is a single callback per database, shared by all of its handles.
+   */
+  onError(callback: ((event: WriterErrorEvent) => void) | null): void
   close(): void
+  /**
+   * Resolves once the database is fully closed, after `close` has been
+   * called on the last handle onto it. Resolves right away if this handle
+   * is still open or other handles still use the database.
+   */
+  get closed(): Promise<void>
+}
+export interface ManagerStats {
+  /** Number of databases tracked by the manager */
+  databases: number
+  /** Total number of entries across all databases */
+  entries: number
+  /** Total size of the data files on disk in bytes */
+  diskSize: number
+  /** Total size of the memory maps in bytes */
+  mapSize: number
+}
+export type LMDBManager = LmdbManager
+export declare class LmdbManager {
+  constructor()
+  /**
+   * Open a database and start tracking it. Opening the same path twice
+   * returns handles onto the same database.
+   */
+  open(options: LmdbOptions): Lmdb
+  /** Aggregate statistics over all tracked databases */
+  stats(): ManagerStats
+  /**
+   * Flush the data buffers of all tracked databases to disk.
+   *
+   * This is only useful for databases opened with `asyncWrites`, and only
+   * flushes writes that have already been committed.
+   */
+  flushAll(): void
+  /**
+   * Close all tracked databases.
+   *
+   * Every handle onto these databases, including the ones returned by
+   * [`LMDBManager::open`], will fail with a closed database error from now
+   * on. Writes queued before this call are still applied.
+   */
+  closeAll(): void
 }
@@ -310,7 +310,21 @@ if (!nativeBinding) {
   throw new Error(`Failed to load native binding`)
 }
 
-const { initTracingSubscriber, Lmdb } = nativeBinding
+const { ErrorCode, KeyCollation, BenchmarkMode, AccessPattern, WriterThreadPriority, WriteTransactionLimitPolicy, SizeLimitPolicy, ValueTransform, Codec, ResolvePanicPolicy, initTracingSubscriber, setMaxOpenEnvironments, advanceClock, HandleMode, Lmdb, LmdbManager } = nativeBinding
 
+module.exports.ErrorCode = ErrorCode
+module.exports.KeyCollation = KeyCollation
+module.exports.BenchmarkMode = BenchmarkMode
+module.exports.AccessPattern = AccessPattern
+module.exports.WriterThreadPriority = WriterThreadPriority
+module.exports.WriteTransactionLimitPolicy = WriteTransactionLimitPolicy
+module.exports.SizeLimitPolicy = SizeLimitPolicy
+module.exports.ValueTransform = ValueTransform
+module.exports.Codec = Codec
+module.exports.ResolvePanicPolicy = ResolvePanicPolicy
 module.exports.initTracingSubscriber = initTracingSubscriber
+module.exports.setMaxOpenEnvironments = setMaxOpenEnvironments
+module.exports.advanceClock = advanceClock
+module.exports.HandleMode = HandleMode
 module.exports.Lmdb = Lmdb
+module.exports.LmdbManager = LmdbManager
@@ -0,0 +1,230 @@
+//! Batches written across two databases, see [`crate::LMDB::coordinated_batch`].
+//!
+//! Each database commits on its own, so a batch is written in four steps:
+//!
+//! 1. The source's entries are committed along with a marker recording the
+//!    target's path and entries
+//! 2. The target's entries are committed along with the id of the batch
+//! 3. The marker is cleared from the source
+//! 4. The id is cleared from the target
+//!
+//! If the process exits before the third step, the marker is left behind.
+//! Once both databases are next open in the same process, the remaining
+//! steps run again from the second one, see [`reconcile`]. The target's
+//! entries are only written again if it doesn't hold the id of the batch,
+//! which means the second step didn't commit, so writes made to the target
+//! since the batch was applied aren't overwritten. If the process exits
+//! between the third and fourth steps, only the id is left in the target.
+//!
+//! The marker holds a full copy of the target's entries, as that's what is
+//! written again, so a batch takes up space in the source until it completes.
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::writer::{
+  DatabaseWriter, DatabaseWriterError, DatabaseWriterHandle, DatabaseWriterMessage, ResolveCallback,
+};
+use crate::NativeEntry;
+
+/// A batch whose entries are yet to be written to its target
+#[derive(Clone)]
+pub struct PendingBatch {
+  pub id: String,
+  /// Canonical path of the database the entries are written to, see
+  /// [`canonical_path`]
+  pub target: String,
+  pub entries: Vec<NativeEntry>,
+}
+
+/// What [`DatabaseWriterMessage::ApplyBatch`] does with the marker of a batch
+pub enum BatchMarker {
+  /// Record the batch on its source
+  Record(PendingBatch),
+  /// Clear the batch with this id from its source
+  Clear(String),
+  /// Record the id of the batch on its target, or skip its entries if the
+  /// id is already there
+  Apply(String),
+  /// Clear the id of the batch from its target
+  ClearApplied(String),
+}
+
+/// The path batches record their target by, so a database opened through
+/// different paths to the same directory is recognised as their target
+pub fn canonical_path(path: &str) -> String {
+  Path::new(path)
+    .canonicalize()
+    .ok()
+    .and_then(|path| path.into_os_string().into_string().ok())
+    .unwrap_or_else(|| path.to_string())
+}
+
+/// A unique id for a new batch
+pub fn next_id() -> String {
+  static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+  let timestamp = SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .unwrap_or_default()
+    .as_nanos();
+  let pid = std::process::id();
+  let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+  format!("{timestamp:x}-{pid:x}-{id:x}")
+}
+
+impl PendingBatch {
+  /// The target path followed by the entries, with every field prefixed by
+  /// its length as a little-endian `u64`
+  pub fn encode(&self) -> Vec<u8> {
+    let mut data = Vec::new();
+    let mut push = |field: &[u8]| {
+      data.extend_from_slice(&(field.len() as u64).to_le_bytes());
+      data.extend_from_slice(field);
+    };
+    push(self.target.as_bytes());
+    for NativeEntry { key, value } in &self.entries {
+      push(key.as_bytes());
+      push(value);
+    }
+    data
+  }
+
+  pub fn decode(id: &str, mut data: &[u8]) -> Option<Self> {
+    let mut next = || {
+      let (len, rest) = data.split_first_chunk::<8>()?;
+      let len = usize::try_from(u64::from_le_bytes(*len)).ok()?;
+      let field = rest.get(..len)?;
+      data = &rest[len..];
+      Some(field)
+    };
+    let target = String::from_utf8(next()?.to_vec()).ok()?;
+    let mut entries = Vec::new();
+    while let Some(key) = next() {
+      entries.push(NativeEntry {
+        key: String::from_utf8(key.to_vec()).ok()?,
+        value: next()?.to_vec(),
+      });
+    }
+    data.is_empty().then(|| PendingBatch {
+      id: id.to_string(),
+      target,
+      entries,
+    })
+  }
+}
+
+/// Write the entries of a batch recorded on `source` to `target`, then clear
+/// its marker from both
+pub fn complete(
+  source: Arc<DatabaseWriterHandle>,
+  target: &Arc<DatabaseWriterHandle>,
+  batch: PendingBatch,
+  resolve: ResolveCallback<()>,
+) {
+  let PendingBatch { id, entries, .. } = batch;
+  let applied_on = Arc::clone(target);
+  send(
+    target,
+    DatabaseWriterMessage::ApplyBatch {
+      entries,
+      marker: BatchMarker::Apply(id.clone()),
+      resolve: Box::new(move |result| match result {
+        Ok(()) => send(
+          &source,
+          DatabaseWriterMessage::ApplyBatch {
+            entries: Vec::new(),
+            marker: BatchMarker::Clear(id.clone()),
+            resolve: Box::new(move |result| match result {
+              // Left behind if this fails, which only takes up the id
+              Ok(()) => send(
+                &applied_on,
+                DatabaseWriterMessage::ApplyBatch {
+                  entries: Vec::new(),
+                  marker: BatchMarker::ClearApplied(id),
+                  resolve,
+                },
+              ),
+              Err(err) => resolve(Err(err)),
+            }),
+          },
+        ),
+        Err(err) => resolve(Err(err)),
+      }),
+    },
+  );
+}
+
+/// Complete the batches left on `source` for the database at `target_path`,
+/// returning how many there were
+pub fn reconcile(
+  source: &Arc<DatabaseWriterHandle>,
+  source_database: &DatabaseWriter,
+  target: &Arc<DatabaseWriterHandle>,
+  target_path: &str,
+) -> Result<usize, DatabaseWriterError> {
+  let target_path = canonical_path(target_path);
+  let txn = source_database.read_txn()?;
+  let batches = source_database.pending_batches(&txn)?;
+  drop(txn);
+
+  let mut reconciled = 0;
+  for batch in batches {
+    if batch.target != target_path {
+      continue;
+    }
+    reconciled += 1;
+    let id = batch.id.clone();
+    complete(
+      source.clone(),
+      target,
+      batch,
+      Box::new(move |result| {
+        if let Err(err) = result {
+          tracing::warn!("Failed to reconcile coordinated batch {id}: {err}");
+        }
+      }),
+    );
+  }
+  Ok(reconciled)
+}
+
+/// Sending only fails if the writer thread can't be restarted, in which case
+/// the message stays queued until it is
+fn send(writer: &DatabaseWriterHandle, message: DatabaseWriterMessage) {
+  if let Err(err) = writer.send(message) {
+    tracing::warn!("Failed to send coordinated batch to the writer thread: {err}");
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn pending_batches_round_trip() {
+    let batch = PendingBatch {
+      id: next_id(),
+      target: "/tmp/target".to_string(),
+      entries: vec![
+        NativeEntry {
+          key: "a".to_string(),
+          value: vec![1, 2, 3],
+        },
+        NativeEntry {
+          key: "b".to_string(),
+          value: Vec::new(),
+        },
+      ],
+    };
+    let data = batch.encode();
+    let decoded = PendingBatch::decode(&batch.id, &data).unwrap();
+    assert_eq!(decoded.target, "/tmp/target");
+    assert_eq!(decoded.entries.len(), 2);
+    assert_eq!(decoded.entries[0].key, "a");
+    assert_eq!(decoded.entries[0].value, vec![1, 2, 3]);
+    assert!(decoded.entries[1].value.is_empty());
+
+    assert!(PendingBatch::decode(&batch.id, &data[..data.len() - 1]).is_none());
+  }
+}
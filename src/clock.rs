@@ -0,0 +1,93 @@
+//! The time write transaction limits, read replica refreshes, the journal and
+//! pipeline metrics go by.
+//!
+//! With the `test-clock` feature, tests can move it forward with
+//! [`advance_clock`] instead of sleeping. The writer thread checks the age of
+//! its open transaction after each message it handles, so a transaction that
+//! expired because the clock was advanced is ended on the next message.
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+#[cfg(feature = "test-clock")]
+use std::sync::atomic::{AtomicU64, Ordering};
+
+#[cfg(feature = "test-clock")]
+use napi_derive::napi;
+
+/// How far [`advance_clock`] has moved the clock ahead, in nanoseconds
+#[cfg(feature = "test-clock")]
+static OFFSET_NANOS: AtomicU64 = AtomicU64::new(0);
+
+#[cfg(feature = "test-clock")]
+fn offset() -> Duration {
+  Duration::from_nanos(OFFSET_NANOS.load(Ordering::Relaxed))
+}
+
+#[cfg(not(feature = "test-clock"))]
+fn offset() -> Duration {
+  Duration::ZERO
+}
+
+/// Move the clock forward by `ms` milliseconds, for every database in the
+/// process. Only available in builds with the `test-clock` feature.
+#[cfg(feature = "test-clock")]
+#[napi]
+pub fn advance_clock(ms: f64) {
+  let nanos = Duration::from_secs_f64(ms.max(0.0) / 1000.0).as_nanos() as u64;
+  OFFSET_NANOS.fetch_add(nanos, Ordering::Relaxed);
+}
+
+pub fn now() -> Instant {
+  Instant::now() + offset()
+}
+
+pub fn elapsed(since: Instant) -> Duration {
+  now().saturating_duration_since(since)
+}
+
+/// Milliseconds since the Unix epoch
+pub fn unix_ms() -> u64 {
+  (SystemTime::now() + offset())
+    .duration_since(UNIX_EPOCH)
+    .unwrap_or_default()
+    .as_millis() as u64
+}
+
+#[cfg(all(test, feature = "test-clock"))]
+mod test {
+  use std::env::temp_dir;
+  use std::sync::mpsc::channel;
+
+  use super::*;
+  use crate::writer::{
+    start_make_database_writer, DatabaseWriterMessage, LMDBOptions, ReadReplica,
+  };
+
+  #[test]
+  fn advancing_the_clock_refreshes_read_replicas() {
+    let path = temp_dir()
+      .join("lmdb-js-lite")
+      .join("advancing_the_clock_refreshes_read_replicas");
+    let _ = std::fs::remove_dir_all(&path);
+    let options = LMDBOptions {
+      path: path.to_str().unwrap().to_string(),
+      ..Default::default()
+    };
+    let (writer, database) = start_make_database_writer(&options).unwrap();
+    let replica = ReadReplica::new(Duration::from_secs(3600));
+    let id = replica.snapshot(&database).unwrap().id;
+
+    let (tx, rx) = channel();
+    writer
+      .send(DatabaseWriterMessage::Put {
+        key: "key".into(),
+        value: vec![1],
+        resolve: Box::new(move |result| tx.send(result).unwrap()),
+      })
+      .unwrap();
+    rx.recv().unwrap().unwrap();
+    assert_eq!(replica.snapshot(&database).unwrap().id, id);
+
+    advance_clock(3600.0 * 1000.0);
+    assert_eq!(replica.snapshot(&database).unwrap().id, id + 1);
+  }
+}
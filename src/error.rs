@@ -0,0 +1,293 @@
+//! Error codes exposed to JavaScript.
+//!
+//! Every error thrown or rejected by this module has its `code` property set
+//! to an [`ErrorCode`], so callers can compare against the exported enum rather
+//! than matching on formatted messages.
+use napi::bindgen_prelude::Env;
+use napi_derive::napi;
+
+use crate::writer::DatabaseWriterError;
+
+#[napi(string_enum)]
+#[derive(Debug, PartialEq)]
+pub enum ErrorCode {
+  /// The handle or database has been closed
+  DatabaseClosed,
+  /// The database is still closing and can't be opened again yet
+  DatabaseClosing,
+  /// The database is already open in this process with different options, or
+  /// was created with options that change how entries are stored
+  BadOpenOptions,
+  /// The writer thread is no longer accepting messages
+  WriterStopped,
+  IOError,
+  /// The database files can't be accessed, `EACCES` or `EPERM`
+  PermissionDenied,
+  /// There isn't enough disk space, either `ENOSPC` or a failed preflight
+  /// check before a large write
+  InsufficientDiskSpace,
+  /// The database files are locked or in use, `EBUSY` or `EAGAIN`
+  ResourceBusy,
+  CompressError,
+  DecompressError,
+  EncodingError,
+  DecodingError,
+  /// A key is over [`crate::writer::LMDBOptions::max_key_length`]
+  KeyTooLong,
+  /// A value is over [`crate::writer::LMDBOptions::max_value_size`]
+  ValueTooLarge,
+  /// The operation would reach outside of a tenant handle's keys
+  TenantIsolation,
+  /// The operation would write to the database from a dry-run handle
+  DryRun,
+  /// The write was dropped from the queue by `cancelPending`
+  Cancelled,
+  /// A transaction was committed since the snapshot was exported, see
+  /// `importSnapshot`
+  SnapshotExpired,
+  /// The write was made outside of an explicit write transaction, see
+  /// [`crate::writer::LMDBOptions::require_explicit_transactions`]
+  TransactionRequired,
+  /// The explicit write transaction was aborted for exceeding its limits,
+  /// see [`crate::writer::WriteTransactionLimitPolicy::Abort`]
+  TransactionAborted,
+  /// The operation isn't allowed by the handle's mode, see `withMode`
+  HandleMode,
+  /// Resolving a promise panicked, see
+  /// [`crate::writer::LMDBOptions::resolve_panic_policy`]
+  ResolveFailed,
+  /// An argument is out of range, such as an entry reaching past the end of
+  /// its buffer
+  InvalidArgument,
+  /// `MDB_KEYEXIST`
+  KeyExist,
+  /// `MDB_NOTFOUND`
+  NotFound,
+  /// `MDB_PAGE_NOTFOUND`
+  PageNotFound,
+  /// `MDB_CORRUPTED`
+  Corrupted,
+  /// `MDB_PANIC`
+  Panic,
+  /// `MDB_VERSION_MISMATCH`
+  VersionMismatch,
+  /// `MDB_INVALID`
+  Invalid,
+  /// `MDB_MAP_FULL`
+  MapFull,
+  /// `MDB_DBS_FULL`
+  DbsFull,
+  /// `MDB_READERS_FULL`
+  ReadersFull,
+  /// `MDB_TLS_FULL`
+  TlsFull,
+  /// `MDB_TXN_FULL`
+  TxnFull,
+  /// `MDB_CURSOR_FULL`
+  CursorFull,
+  /// `MDB_PAGE_FULL`
+  PageFull,
+  /// `MDB_MAP_RESIZED`
+  MapResized,
+  /// `MDB_INCOMPATIBLE`
+  Incompatible,
+  /// `MDB_BAD_RSLOT`
+  BadRslot,
+  /// `MDB_BAD_TXN`
+  BadTxn,
+  /// `MDB_BAD_VALSIZE`
+  BadValSize,
+  /// `MDB_BAD_DBI`
+  BadDbi,
+  /// `MDB_PROBLEM`
+  Problem,
+  /// Any other LMDB return code
+  LmdbError,
+  /// Failures in the N-API layer, such as failing to create JavaScript values
+  Internal,
+}
+
+impl AsRef<str> for ErrorCode {
+  fn as_ref(&self) -> &str {
+    match self {
+      ErrorCode::DatabaseClosed => "DatabaseClosed",
+      ErrorCode::DatabaseClosing => "DatabaseClosing",
+      ErrorCode::BadOpenOptions => "BadOpenOptions",
+      ErrorCode::WriterStopped => "WriterStopped",
+      ErrorCode::IOError => "IOError",
+      ErrorCode::PermissionDenied => "PermissionDenied",
+      ErrorCode::InsufficientDiskSpace => "InsufficientDiskSpace",
+      ErrorCode::ResourceBusy => "ResourceBusy",
+      ErrorCode::CompressError => "CompressError",
+      ErrorCode::DecompressError => "DecompressError",
+      ErrorCode::EncodingError => "EncodingError",
+      ErrorCode::DecodingError => "DecodingError",
+      ErrorCode::KeyTooLong => "KeyTooLong",
+      ErrorCode::ValueTooLarge => "ValueTooLarge",
+      ErrorCode::TenantIsolation => "TenantIsolation",
+      ErrorCode::DryRun => "DryRun",
+      ErrorCode::Cancelled => "Cancelled",
+      ErrorCode::SnapshotExpired => "SnapshotExpired",
+      ErrorCode::TransactionRequired => "TransactionRequired",
+      ErrorCode::TransactionAborted => "TransactionAborted",
+      ErrorCode::HandleMode => "HandleMode",
+      ErrorCode::ResolveFailed => "ResolveFailed",
+      ErrorCode::InvalidArgument => "InvalidArgument",
+      ErrorCode::KeyExist => "KeyExist",
+      ErrorCode::NotFound => "NotFound",
+      ErrorCode::PageNotFound => "PageNotFound",
+      ErrorCode::Corrupted => "Corrupted",
+      ErrorCode::Panic => "Panic",
+      ErrorCode::VersionMismatch => "VersionMismatch",
+      ErrorCode::Invalid => "Invalid",
+      ErrorCode::MapFull => "MapFull",
+      ErrorCode::DbsFull => "DbsFull",
+      ErrorCode::ReadersFull => "ReadersFull",
+      ErrorCode::TlsFull => "TlsFull",
+      ErrorCode::TxnFull => "TxnFull",
+      ErrorCode::CursorFull => "CursorFull",
+      ErrorCode::PageFull => "PageFull",
+      ErrorCode::MapResized => "MapResized",
+      ErrorCode::Incompatible => "Incompatible",
+      ErrorCode::BadRslot => "BadRslot",
+      ErrorCode::BadTxn => "BadTxn",
+      ErrorCode::BadValSize => "BadValSize",
+      ErrorCode::BadDbi => "BadDbi",
+      ErrorCode::Problem => "Problem",
+      ErrorCode::LmdbError => "LmdbError",
+      ErrorCode::Internal => "Internal",
+    }
+  }
+}
+
+impl From<&heed::MdbError> for ErrorCode {
+  fn from(err: &heed::MdbError) -> Self {
+    use heed::MdbError;
+    match err {
+      MdbError::KeyExist => ErrorCode::KeyExist,
+      MdbError::NotFound => ErrorCode::NotFound,
+      MdbError::PageNotFound => ErrorCode::PageNotFound,
+      MdbError::Corrupted => ErrorCode::Corrupted,
+      MdbError::Panic => ErrorCode::Panic,
+      MdbError::VersionMismatch => ErrorCode::VersionMismatch,
+      MdbError::Invalid => ErrorCode::Invalid,
+      MdbError::MapFull => ErrorCode::MapFull,
+      MdbError::DbsFull => ErrorCode::DbsFull,
+      MdbError::ReadersFull => ErrorCode::ReadersFull,
+      MdbError::TlsFull => ErrorCode::TlsFull,
+      MdbError::TxnFull => ErrorCode::TxnFull,
+      MdbError::CursorFull => ErrorCode::CursorFull,
+      MdbError::PageFull => ErrorCode::PageFull,
+      MdbError::MapResized => ErrorCode::MapResized,
+      MdbError::Incompatible => ErrorCode::Incompatible,
+      MdbError::BadRslot => ErrorCode::BadRslot,
+      MdbError::BadTxn => ErrorCode::BadTxn,
+      MdbError::BadValSize => ErrorCode::BadValSize,
+      MdbError::BadDbi => ErrorCode::BadDbi,
+      MdbError::Problem => ErrorCode::Problem,
+      MdbError::Other(_) => ErrorCode::LmdbError,
+    }
+  }
+}
+
+impl From<&std::io::Error> for ErrorCode {
+  fn from(err: &std::io::Error) -> Self {
+    match err.raw_os_error() {
+      Some(libc::EACCES | libc::EPERM) => ErrorCode::PermissionDenied,
+      Some(libc::ENOSPC) => ErrorCode::InsufficientDiskSpace,
+      Some(libc::EBUSY | libc::EAGAIN) => ErrorCode::ResourceBusy,
+      _ => ErrorCode::IOError,
+    }
+  }
+}
+
+impl From<&heed::Error> for ErrorCode {
+  fn from(err: &heed::Error) -> Self {
+    match err {
+      heed::Error::Io(err) => err.into(),
+      heed::Error::Mdb(err) => err.into(),
+      heed::Error::Encoding(_) => ErrorCode::EncodingError,
+      heed::Error::Decoding(_) => ErrorCode::DecodingError,
+      heed::Error::DatabaseClosing => ErrorCode::DatabaseClosing,
+      heed::Error::BadOpenOptions { .. } => ErrorCode::BadOpenOptions,
+    }
+  }
+}
+
+impl DatabaseWriterError {
+  pub fn code(&self) -> ErrorCode {
+    match self {
+      DatabaseWriterError::HeedError(err) => err.into(),
+      DatabaseWriterError::OpenError { source, .. } => source.into(),
+      DatabaseWriterError::IOError(err) => err.into(),
+      DatabaseWriterError::DecompressError(_) => ErrorCode::DecompressError,
+      DatabaseWriterError::CompressError(_) => ErrorCode::CompressError,
+      DatabaseWriterError::SendError => ErrorCode::WriterStopped,
+      DatabaseWriterError::ReservedSpaceExceeded { .. } => ErrorCode::BadValSize,
+      DatabaseWriterError::KeyTooLong { .. } => ErrorCode::KeyTooLong,
+      DatabaseWriterError::ValueTooLarge { .. } => ErrorCode::ValueTooLarge,
+      DatabaseWriterError::MissingChunks(_) => ErrorCode::Corrupted,
+      DatabaseWriterError::MissingDeduplicatedValue(_) => ErrorCode::Corrupted,
+      DatabaseWriterError::InvalidRawEntry(_) => ErrorCode::DecodingError,
+      DatabaseWriterError::InvalidBatchMarker(_) => ErrorCode::DecodingError,
+      DatabaseWriterError::DryRun(_) => ErrorCode::DryRun,
+      DatabaseWriterError::InternalDatabase(_) => ErrorCode::InvalidArgument,
+      DatabaseWriterError::Cancelled => ErrorCode::Cancelled,
+      DatabaseWriterError::SnapshotExpired { .. } => ErrorCode::SnapshotExpired,
+      DatabaseWriterError::TransactionOpen(_) => ErrorCode::BadTxn,
+      DatabaseWriterError::TransactionRequired(_) => ErrorCode::TransactionRequired,
+      DatabaseWriterError::TransactionAborted(_) => ErrorCode::TransactionAborted,
+      DatabaseWriterError::HandleMode { .. } => ErrorCode::HandleMode,
+      DatabaseWriterError::BatchCommit { code, .. } => *code,
+      DatabaseWriterError::InsufficientDiskSpace { .. } => ErrorCode::InsufficientDiskSpace,
+      DatabaseWriterError::ResolvePanicked { .. } => ErrorCode::ResolveFailed,
+      DatabaseWriterError::OptionsMismatch { .. } => ErrorCode::BadOpenOptions,
+    }
+  }
+}
+
+impl From<DatabaseWriterError> for napi::Error<ErrorCode> {
+  fn from(err: DatabaseWriterError) -> Self {
+    napi::Error::new(err.code(), err.to_string())
+  }
+}
+
+/// Errors that don't come from the database, such as failing to create
+/// JavaScript values
+pub fn internal_error(err: impl std::fmt::Debug) -> napi::Error<ErrorCode> {
+  napi::Error::new(ErrorCode::Internal, format!("[napi] {err:?}"))
+}
+
+/// Deferred promises can only be rejected with a `napi::Error<Status>`. To
+/// keep the [`ErrorCode`], the JavaScript error object is created up-front
+/// and the promise is rejected with it.
+pub fn rejection(env: Env, err: impl Into<napi::Error<ErrorCode>>) -> napi::Error {
+  napi::Error::from(napi::JsError::from(err.into()).into_unknown(env))
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn database_errors_map_to_codes() {
+    let err = DatabaseWriterError::from(heed::Error::Mdb(heed::MdbError::MapFull));
+    assert_eq!(err.code(), ErrorCode::MapFull);
+    let err = napi::Error::<ErrorCode>::from(err);
+    assert_eq!(err.status, ErrorCode::MapFull);
+    assert_eq!(err.status.as_ref(), "MapFull");
+    assert_eq!(
+      DatabaseWriterError::SendError.code(),
+      ErrorCode::WriterStopped
+    );
+
+    let err = std::io::Error::from_raw_os_error(libc::ENOSPC);
+    let err = DatabaseWriterError::from(heed::Error::Io(err));
+    assert_eq!(err.code(), ErrorCode::InsufficientDiskSpace);
+    let err = std::io::Error::from_raw_os_error(libc::EACCES);
+    assert_eq!(
+      DatabaseWriterError::from(err).code(),
+      ErrorCode::PermissionDenied
+    );
+  }
+}
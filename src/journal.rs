@@ -0,0 +1,122 @@
+//! A debug journal of the messages handled by the writer thread, see
+//! [`crate::writer::LMDBOptions::journal_path`].
+//!
+//! Each line records when a message was handled, the operation and key, the
+//! number of bytes written, how long it was queued and handled for, and
+//! whether it failed. Once the journal grows over its size limit it is moved
+//! to `<path>.1`, replacing the previous one, and a new journal is started.
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, PoisonError};
+use std::time::Duration;
+
+use crate::clock;
+
+/// Size of a journal before it is rotated, unless configured otherwise
+pub const DEFAULT_JOURNAL_MAX_BYTES: u64 = 10 * 1024 * 1024;
+
+pub struct Journal {
+  path: PathBuf,
+  max_bytes: u64,
+  file: Mutex<JournalFile>,
+}
+
+struct JournalFile {
+  file: File,
+  len: u64,
+}
+
+/// A message handled by the writer thread
+pub struct JournalEntry<'a> {
+  pub operation: &'static str,
+  pub key: Option<&'a str>,
+  /// Key and value bytes written, before compression
+  pub bytes: usize,
+  pub queued: Duration,
+  pub duration: Duration,
+  /// The error the message failed with, if any
+  pub error: Option<&'a str>,
+}
+
+impl Journal {
+  pub fn open(path: &Path, max_bytes: u64) -> std::io::Result<Self> {
+    let file = open_append(path)?;
+    let len = file.metadata()?.len();
+    Ok(Self {
+      path: path.to_path_buf(),
+      max_bytes,
+      file: Mutex::new(JournalFile { file, len }),
+    })
+  }
+
+  /// Append an entry, rotating the journal first if it would grow over its
+  /// size limit
+  pub fn record(&self, entry: &JournalEntry) -> std::io::Result<()> {
+    let timestamp = clock::unix_ms();
+    let line = format!(
+      "{timestamp}\t{}\t{:?}\t{}\t{}\t{}\t{}\n",
+      entry.operation,
+      entry.key.unwrap_or_default(),
+      entry.bytes,
+      entry.queued.as_micros(),
+      entry.duration.as_micros(),
+      entry.error.unwrap_or("ok"),
+    );
+
+    let mut journal = self.file.lock().unwrap_or_else(PoisonError::into_inner);
+    if journal.len > 0 && journal.len + line.len() as u64 > self.max_bytes {
+      let mut rotated = self.path.clone().into_os_string();
+      rotated.push(".1");
+      std::fs::rename(&self.path, rotated)?;
+      *journal = JournalFile {
+        file: open_append(&self.path)?,
+        len: 0,
+      };
+    }
+    journal.file.write_all(line.as_bytes())?;
+    journal.len += line.len() as u64;
+    Ok(())
+  }
+}
+
+fn open_append(path: &Path) -> std::io::Result<File> {
+  if let Some(parent) = path.parent() {
+    std::fs::create_dir_all(parent)?;
+  }
+  OpenOptions::new().create(true).append(true).open(path)
+}
+
+#[cfg(test)]
+mod test {
+  use std::env::temp_dir;
+
+  use super::*;
+
+  #[test]
+  fn journals_are_rotated() {
+    let path = temp_dir()
+      .join("lmdb-js-lite")
+      .join("journals_are_rotated")
+      .join("journal.log");
+    let _ = std::fs::remove_dir_all(path.parent().unwrap());
+    let journal = Journal::open(&path, 100).unwrap();
+    let entry = JournalEntry {
+      operation: "put",
+      key: Some("key"),
+      bytes: 4,
+      queued: Duration::from_micros(5),
+      duration: Duration::from_micros(10),
+      error: None,
+    };
+    journal.record(&entry).unwrap();
+    let contents = std::fs::read_to_string(&path).unwrap();
+    assert!(contents.ends_with("\tput\t\"key\"\t4\t5\t10\tok\n"));
+
+    for _ in 0..3 {
+      journal.record(&entry).unwrap();
+    }
+    assert!(path.with_extension("log.1").exists());
+    assert!(std::fs::metadata(&path).unwrap().len() <= 100);
+  }
+}
@@ -0,0 +1,134 @@
+//! An [`LMDBManager`] tracks many open databases, for example one cache per
+//! project, so that they can be flushed, inspected and closed together.
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use napi_derive::napi;
+
+use crate::error::ErrorCode;
+use crate::writer::{DatabaseWriterError, LMDBOptions};
+use crate::{DatabaseHandle, LMDB};
+
+#[napi(object)]
+pub struct ManagerStats {
+  /// Number of databases tracked by the manager
+  pub databases: u32,
+  /// Total number of entries across all databases
+  pub entries: f64,
+  /// Total size of the data files on disk in bytes
+  pub disk_size: f64,
+  /// Total size of the memory maps in bytes
+  pub map_size: f64,
+}
+
+#[napi]
+pub struct LMDBManager {
+  databases: HashMap<String, Arc<DatabaseHandle>>,
+}
+
+#[napi]
+impl LMDBManager {
+  #[napi(constructor)]
+  #[allow(clippy::new_without_default)]
+  pub fn new() -> Self {
+    Self {
+      databases: HashMap::new(),
+    }
+  }
+
+  /// Open a database and start tracking it. Opening the same path twice
+  /// returns handles onto the same database.
+  #[napi]
+  pub fn open(&mut self, options: LMDBOptions) -> napi::Result<LMDB, ErrorCode> {
+    let path = options.path.clone();
+    let lmdb = LMDB::new(options)?;
+    let database = lmdb.get_database()?;
+    self.databases.insert(path, database.clone());
+    Ok(lmdb)
+  }
+
+  /// Aggregate statistics over all tracked databases
+  #[napi]
+  pub fn stats(&self) -> napi::Result<ManagerStats, ErrorCode> {
+    let mut stats = ManagerStats {
+      databases: self.databases.len() as u32,
+      entries: 0.0,
+      disk_size: 0.0,
+      map_size: 0.0,
+    };
+    for database in self.databases.values() {
+      let database_stats = database.database.stats()?;
+      stats.entries += database_stats.entries as f64;
+      stats.disk_size += database_stats.disk_size as f64;
+      stats.map_size += database_stats.map_size as f64;
+    }
+    Ok(stats)
+  }
+
+  /// Flush the data buffers of all tracked databases to disk.
+  ///
+  /// This is only useful for databases opened with `asyncWrites`, and only
+  /// flushes writes that have already been committed.
+  #[napi]
+  pub fn flush_all(&self) -> napi::Result<(), ErrorCode> {
+    for database in self.databases.values() {
+      database
+        .database
+        .environment()
+        .force_sync()
+        .map_err(DatabaseWriterError::from)?;
+    }
+    Ok(())
+  }
+
+  /// Close all tracked databases.
+  ///
+  /// Every handle onto these databases, including the ones returned by
+  /// [`LMDBManager::open`], will fail with a closed database error from now
+  /// on. Writes queued before this call are still applied.
+  #[napi]
+  pub fn close_all(&mut self) {
+    for (_, database) in self.databases.drain() {
+      database.close();
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use std::env::temp_dir;
+
+  use super::*;
+
+  #[test]
+  fn manager_tracks_and_closes_databases() {
+    let root = temp_dir()
+      .join("lmdb-js-lite")
+      .join("manager_tracks_and_closes_databases");
+    let _ = std::fs::remove_dir_all(&root);
+
+    let mut manager = LMDBManager::new();
+    let mut handles = vec![];
+    for name in ["project-a", "project-b"] {
+      let options = LMDBOptions {
+        path: root.join(name).to_str().unwrap().to_string(),
+        async_writes: true,
+        map_size: None,
+        ..Default::default()
+      };
+      handles.push(manager.open(options).unwrap());
+    }
+
+    let stats = manager.stats().unwrap();
+    assert_eq!(stats.databases, 2);
+    assert_eq!(stats.entries, 0.0);
+    assert!(stats.disk_size > 0.0);
+    manager.flush_all().unwrap();
+
+    manager.close_all();
+    assert_eq!(manager.stats().unwrap().databases, 0);
+    for handle in &handles {
+      assert!(handle.get_database().is_err());
+    }
+  }
+}
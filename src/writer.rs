@@ -1,16 +1,31 @@
-use std::path::Path;
-use std::sync::Arc;
+use std::borrow::Cow;
+use std::cell::{Ref, RefCell};
+use std::collections::{BTreeMap, HashMap};
+use std::io::Write;
+use std::ops::Bound;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, PoisonError, RwLock};
 use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
 
 use crossbeam::channel::{Receiver, Sender};
-use heed::{Env, RoTxn, RwTxn};
+use heed::types::{Bytes, Str, Unit};
 use heed::EnvFlags;
 use heed::EnvOpenOptions;
-use heed::types::{Bytes, Str};
+use heed::{CompactionOption, Env, RoTxn, RwTxn};
 use napi_derive::napi;
+#[cfg(all(feature = "parallel", not(feature = "small")))]
 use rayon::prelude::*;
+use twox_hash::XxHash3_128;
+use unicode_normalization::UnicodeNormalization;
 
-use crate::NativeEntry;
+use crate::batch::{BatchMarker, PendingBatch};
+use crate::clock;
+use crate::error::ErrorCode;
+use crate::journal::{Journal, JournalEntry, DEFAULT_JOURNAL_MAX_BYTES};
+use crate::{DatabaseKey, NativeEntry};
 
 type Result<R> = std::result::Result<R, DatabaseWriterError>;
 
@@ -24,9 +39,88 @@ pub enum DatabaseWriterError {
   DecompressError(#[from] lz4_flex::block::DecompressError),
   #[error("Failed to compress entry {0}")]
   CompressError(#[from] lz4_flex::block::CompressError),
+  #[error("Failed to send message to the writer thread")]
+  SendError,
+  #[error("Value of {len} bytes does not fit in {size} reserved bytes")]
+  ReservedSpaceExceeded { size: usize, len: usize },
+  #[error("Key of {len} bytes exceeds the maximum key length of {max} bytes")]
+  KeyTooLong { len: usize, max: usize },
+  #[error("Value of {len} bytes exceeds the maximum value size of {max} bytes")]
+  ValueTooLarge { len: usize, max: usize },
+  #[error("Chunked entry {0} is missing chunks")]
+  MissingChunks(String),
+  #[error("Deduplicated entry {0} is missing its value")]
+  MissingDeduplicatedValue(String),
+  #[error("Raw value for {0} is not a valid stored entry")]
+  InvalidRawEntry(String),
+  #[error("Marker of coordinated batch {0} is not valid")]
+  InvalidBatchMarker(String),
+  #[error("{0} isn't available on dry-run handles")]
+  DryRun(&'static str),
+  #[error("Named database {0} is used internally and can't be read directly")]
+  InternalDatabase(String),
+  #[error(
+    "Snapshot of transaction {id} was exported, but transaction {latest} was committed since"
+  )]
+  SnapshotExpired { id: u64, latest: u64 },
+  #[error("Queued write was cancelled")]
+  Cancelled,
+  #[error("{0} can't run while a write transaction is open")]
+  TransactionOpen(&'static str),
+  #[error("{0} must be called within an explicit write transaction")]
+  TransactionRequired(&'static str),
+  #[error("{0} failed, the write transaction was aborted for exceeding its limits")]
+  TransactionAborted(&'static str),
+  /// Committing a [`TinyBatch`] failed, shared by every write in it
+  #[error("Failed to commit batched write: {message}")]
+  BatchCommit { code: ErrorCode, message: String },
+  #[error("{operation} isn't allowed on {mode} handles")]
+  HandleMode {
+    operation: &'static str,
+    mode: &'static str,
+  },
+  #[error(
+    "Not enough disk space, {required} bytes are required but only {available} are available"
+  )]
+  InsufficientDiskSpace { required: u64, available: u64 },
+  #[error("Resolving {operation} panicked: {message}")]
+  ResolvePanicked {
+    operation: &'static str,
+    message: String,
+  },
+  #[error(
+    "Database at {path:?} can't be opened with these options: {}",
+    describe_conflicts(.conflicts)
+  )]
+  OptionsMismatch {
+    path: PathBuf,
+    conflicts: Vec<OptionConflict>,
+  },
+  #[error("Failed to open database at {path:?}: {source}")]
+  OpenError {
+    path: PathBuf,
+    #[source]
+    source: heed::Error,
+  },
+}
+
+impl DatabaseWriterError {
+  /// Whether the error means the database file is corrupted, rather than
+  /// that the operation failed
+  pub fn is_corruption(&self) -> bool {
+    use heed::MdbError;
+    match self {
+      DatabaseWriterError::HeedError(heed::Error::Mdb(err)) => matches!(
+        err,
+        MdbError::Corrupted | MdbError::PageNotFound | MdbError::Invalid | MdbError::Panic
+      ),
+      DatabaseWriterError::DecompressError(_) | DatabaseWriterError::MissingChunks(_) => true,
+      _ => false,
+    }
+  }
 }
 
-#[derive(Clone, PartialOrd, PartialEq)]
+#[derive(Clone, Default, PartialOrd, PartialEq)]
 #[napi(object)]
 pub struct LMDBOptions {
   /// The database directory path
@@ -42,330 +136,5781 @@ pub struct LMDBOptions {
   /// The mmap size, this corresponds to [`mdb_env_set_mapsize`](http://www.lmdb.tech/doc/group__mdb.html#gaa2506ec8dab3d969b0e609cd82e619e5)
   /// if this isn't set it'll default to around 10MB.
   pub map_size: Option<f64>,
+  /// The maximum number of named databases that can be opened in the
+  /// environment, see [`mdb_env_set_maxdbs`](http://www.lmdb.tech/doc/group__mdb.html#gaa2fc2f1f37cb1115e733b62cab2fcdbc).
+  /// The named databases used internally don't count towards it.
+  ///
+  /// Defaults to 16.
+  pub max_dbs: Option<u32>,
+  /// The name of the writer thread, as shown by debuggers and `ps`.
+  ///
+  /// Defaults to `lmdb-writer`. Linux truncates thread names to 15 bytes.
+  pub writer_thread_name: Option<String>,
+  /// The scheduling priority of the writer thread. A background cache writer
+  /// usually shouldn't compete with compilation threads at equal priority.
+  ///
+  /// Defaults to [`WriterThreadPriority::Normal`].
+  pub writer_thread_priority: Option<WriterThreadPriority>,
+  /// If set, the writer thread is stopped after this many milliseconds
+  /// without messages, and transparently restarted on the next message.
+  ///
+  /// This is useful for processes that open many rarely used databases. The
+  /// writer thread is never stopped while a write transaction is open.
+  pub writer_idle_timeout_ms: Option<u32>,
+  /// What happens when resolving a message panics, for example because the
+  /// promise's environment is tearing down.
+  ///
+  /// Defaults to [`ResolvePanicPolicy::Panic`].
+  pub resolve_panic_policy: Option<ResolvePanicPolicy>,
+  /// If set, explicit write transactions open for longer than this many
+  /// milliseconds are ended according to
+  /// [`LMDBOptions::write_transaction_limit_policy`].
+  ///
+  /// A forgotten `commitWriteTransaction()` otherwise blocks all other writers
+  /// indefinitely.
+  pub max_write_transaction_age_ms: Option<u32>,
+  /// If set, explicit write transactions are ended according to
+  /// [`LMDBOptions::write_transaction_limit_policy`] once this many entries
+  /// have been written within them.
+  pub max_write_transaction_operations: Option<u32>,
+  /// What to do with write transactions that exceed the limits above.
+  ///
+  /// Defaults to [`WriteTransactionLimitPolicy::Commit`].
+  pub write_transaction_limit_policy: Option<WriteTransactionLimitPolicy>,
+  /// If set, keys longer than this many bytes are handled according to
+  /// [`LMDBOptions::size_limit_policy`]. LMDB itself rejects keys over 511
+  /// bytes.
+  pub max_key_length: Option<u32>,
+  /// If set, values larger than this many bytes are handled according to
+  /// [`LMDBOptions::size_limit_policy`].
+  pub max_value_size: Option<f64>,
+  /// What to do with keys and values over the limits above.
+  ///
+  /// Defaults to [`SizeLimitPolicy::Reject`].
+  pub size_limit_policy: Option<SizeLimitPolicy>,
+  /// If enabled, keys longer than LMDB's maximum key size are stored under a
+  /// hash of the key. The original key is kept in a side table and checked
+  /// on reads, so hash collisions read as missing entries rather than
+  /// returning another key's value.
+  ///
+  /// Can't be changed once the database has entries.
+  pub hash_long_keys: Option<bool>,
+  /// Transforms applied to values on the writer thread before they are
+  /// compressed, in order
+  pub value_transforms: Option<Vec<ValueTransform>>,
+  /// If enabled, reader slots left in `lock.mdb` by processes that are no
+  /// longer running are cleared when the database is opened. Otherwise stale
+  /// readers from a crashed process keep old pages from being reused until
+  /// LMDB happens to check for them.
+  pub auto_recover_lock: Option<bool>,
+  /// How point lookups read the memory map. Scans, such as `deleteWhere`,
+  /// `copyEntries` with a prefix and `cloneTo`, switch to
+  /// [`AccessPattern::Sequential`] while they run. That applies to the whole
+  /// map, so point lookups running meanwhile read with it too. Only has an
+  /// effect on Linux.
+  ///
+  /// Defaults to [`AccessPattern::Normal`].
+  pub access_pattern: Option<AccessPattern>,
+  /// How keys are normalized before entries are written or looked up.
+  /// Handles apply it to keys and prefixes passed from JavaScript.
+  ///
+  /// Defaults to [`KeyCollation::Binary`]. Can't be changed once the database
+  /// has entries.
+  pub key_collation: Option<KeyCollation>,
+  /// If set, this many entries spread across the database are read when it
+  /// is opened. If they show that the last transaction is corrupted, the
+  /// database is reopened at the previous snapshot, discarding that
+  /// transaction, see [`DatabaseWriter::recovery`].
+  ///
+  /// Falling back requires that no other process has the database open.
+  pub verify_on_open: Option<u32>,
+  /// If enabled, disk space for the whole map size is allocated when the
+  /// database is opened, so file systems don't have to allocate blocks
+  /// while entries are written. Only supported on Linux. Fails with
+  /// [`DatabaseWriterError::InsufficientDiskSpace`] if there isn't room for it.
+  pub preallocate: Option<bool>,
+  /// If enabled along with [`LMDBOptions::preallocate`], every page of the
+  /// memory map is read once when the database is opened, so the first
+  /// writes don't fault pages in. This makes opening slower, in proportion
+  /// to the map size.
+  pub pretouch: Option<bool>,
+  /// If set, every message handled by the writer thread is recorded in a
+  /// journal at this path, with its key, size, timing and outcome, to
+  /// diagnose missing or unexpected entries.
+  pub journal_path: Option<String>,
+  /// Size of the journal in bytes before it is moved to `<path>.1`, replacing
+  /// the previous one. Defaults to 10MB.
+  pub journal_max_bytes: Option<f64>,
+  /// If enabled, deleting an entry leaves a tombstone with the id of the
+  /// transaction that deleted it, until it's written again or the tombstone
+  /// is purged, so change feeds and replicas can tell deleted entries apart
+  /// from entries they haven't seen yet.
+  ///
+  /// Deleted entries are kept, marked as tombstoned, until their tombstones
+  /// are purged. Reads and scans treat them as missing in the meantime.
+  pub tombstones: Option<bool>,
+  /// If enabled, the id of the transaction that last wrote each entry is
+  /// recorded, so [`DeleteFilter::older_than_txn_id`] can delete entries by
+  /// age. This takes an extra write per entry. Entries written while it was
+  /// disabled count as written before any transaction.
+  pub track_write_txn_ids: Option<bool>,
+  /// If enabled, writes and deletes made outside of `startWriteTransaction`
+  /// are rejected with [`DatabaseWriterError::TransactionRequired`] instead
+  /// of being committed in a transaction of their own, to catch unbatched
+  /// writes.
+  pub require_explicit_transactions: Option<bool>,
+  /// If set, values of at least this many bytes are stored once no matter
+  /// how many keys hold them. Each key refers to the value by its hash, and
+  /// the value is deleted once no key refers to it anymore.
+  ///
+  /// Keep this set once deduplicated values have been written, otherwise
+  /// deleting their keys doesn't release them.
+  pub dedup_min_size: Option<u32>,
+  /// If enabled, the journal, tracing spans and logged errors record a hash
+  /// of each key instead of the key itself, so they can be shared in bug
+  /// reports without exposing file paths. Values are never recorded.
+  pub redact_keys: Option<bool>,
+  /// If set, reads and writes are counted per key prefix, the part of the
+  /// key up to and including the first occurrence of this separator, see
+  /// [`DatabaseWriter::access_stats`]. Keys without it count towards the
+  /// empty prefix.
+  pub access_stats_separator: Option<String>,
+  /// Fraction of reads and writes counted for
+  /// [`LMDBOptions::access_stats_separator`], with counts scaled back up
+  /// when reported. Defaults to 0.01.
+  pub access_stats_sample_rate: Option<f64>,
+  /// If enabled, the database is opened read-only, for processes reading a
+  /// database another process writes to, see [`crate::LMDB::open_read_only`].
+  /// Handles onto it can't write, and nothing is written to the directory
+  /// when it's opened.
+  pub read_only: Option<bool>,
 }
 
-/// This is a message passing handle into the writer thread.
-///
-/// There is always a single writer thread per database.
-pub struct DatabaseWriterHandle {
-  tx: Sender<DatabaseWriterMessage>,
-  #[allow(unused)]
-  thread_handle: JoinHandle<()>,
+/// How keys are normalized, see [`LMDBOptions::key_collation`]
+#[derive(Debug, PartialEq, PartialOrd)]
+#[napi(string_enum = "camelCase")]
+pub enum KeyCollation {
+  /// Keys are used as they are
+  Binary,
+  /// Keys are lowercased, so keys that only differ in case, such as paths on
+  /// case-insensitive file systems, refer to the same entry
+  CaseInsensitive,
+  /// Keys are normalized to Unicode NFC, so keys that only differ in how
+  /// accented characters are composed, such as paths on macOS file systems,
+  /// refer to the same entry
+  Nfc,
 }
 
-impl DatabaseWriterHandle {
-  /// Send a message into the writer thread.
-  pub fn send(
-    &self,
-    message: DatabaseWriterMessage,
-  ) -> std::result::Result<(), crossbeam::channel::SendError<DatabaseWriterMessage>> {
-    self.tx.send(message)
+impl KeyCollation {
+  pub fn apply(&self, key: String) -> String {
+    match self {
+      KeyCollation::Binary => key,
+      KeyCollation::CaseInsensitive => key.to_lowercase(),
+      KeyCollation::Nfc if unicode_normalization::is_nfc(&key) => key,
+      KeyCollation::Nfc => key.nfc().collect(),
+    }
   }
 }
 
-impl Drop for DatabaseWriterHandle {
-  fn drop(&mut self) {
-    let _ = self.tx.send(DatabaseWriterMessage::Stop);
-  }
+/// Built-in preprocessing of values, see [`LMDBOptions::value_transforms`]
+#[derive(Debug, PartialEq, PartialOrd)]
+#[napi(string_enum = "camelCase")]
+pub enum ValueTransform {
+  /// Store gzip and zstd compressed values as-is instead of compressing them
+  /// again
+  CompressedPassthrough,
+  /// Remove whitespace outside of strings from values that start with `{` or
+  /// `[`. Only enable this for databases that store JSON.
+  MinifyJson,
 }
 
-/// Open the database and start the writer thread. Two handles are returned:
-///
-/// * A raw DB handle that can be used for synchronous reads
-/// * A writer handle that can be used to send messages to the writer thread
-/// 
-/// The writer handle should be used to create write transactions shared across
-/// Node.js threads.
-pub fn start_make_database_writer(
-  options: &LMDBOptions,
-) -> Result<(DatabaseWriterHandle, Arc<DatabaseWriter>)> {
-  let (tx, rx) = crossbeam::channel::unbounded();
-  let writer = Arc::new(DatabaseWriter::new(options)?);
-
-  let thread_handle = std::thread::spawn({
-    let writer = writer.clone();
-    move || {
-      run_database_writer(rx, writer);
-    }
-  });
+/// What to do with keys or values over the size limits
+#[derive(Debug, PartialEq, PartialOrd)]
+#[napi(string_enum = "camelCase")]
+pub enum SizeLimitPolicy {
+  /// Fail the write
+  Reject,
+  /// Store overlong keys as their truncated prefix followed by a hash of the
+  /// whole key. Oversized values are rejected.
+  TruncateKeyHash,
+  /// Split oversized values into chunks of at most `maxValueSize` bytes,
+  /// reassembled when read. Overlong keys are rejected, as are keys of
+  /// oversized values within 9 bytes of LMDB's maximum key size, which is
+  /// what each chunk adds to the key.
+  ChunkValue,
+}
 
-  Ok((DatabaseWriterHandle { tx, thread_handle }, writer))
+/// What to do with an explicit write transaction that exceeds its limits
+#[derive(Debug, PartialEq, PartialOrd)]
+#[napi(string_enum = "lowercase")]
+pub enum WriteTransactionLimitPolicy {
+  /// Commit the transaction, keeping its writes
+  Commit,
+  /// Abort the transaction, discarding its writes. Writes and the commit
+  /// that follow are rejected with
+  /// [`DatabaseWriterError::TransactionAborted`], until a new transaction is
+  /// started.
+  Abort,
 }
 
-/// Main-loop for the database writer thread
-fn run_database_writer(rx: Receiver<DatabaseWriterMessage>, writer: Arc<DatabaseWriter>) {
-  tracing::debug!("Starting database writer thread");
-  let mut current_transaction: Option<RwTxn> = None;
+const DEFAULT_MAX_DBS: u32 = 16;
+const DEFAULT_WRITER_THREAD_NAME: &str = "lmdb-writer";
 
-  while let Ok(msg) = rx.recv() {
-    if handle_message(&writer, &mut current_transaction, msg) {
-      break;
-    }
-  }
+/// Opening is retried this many times on transient errors, waiting
+/// [`OPEN_RETRY_BACKOFF`] before the first retry and doubling it every time
+const OPEN_RETRIES: u32 = 5;
+const OPEN_RETRY_BACKOFF: Duration = Duration::from_millis(10);
 
-  if let Some(txn) = current_transaction {
-    let _ = txn.commit();
+/// Prefix of entries stored without compression. LZ4 entries start with their
+/// uncompressed size, which can't be `u32::MAX` since LMDB values are smaller
+/// than that, so both formats can live in the same database.
+const RAW_ENTRY_MARKER: [u8; 4] = u32::MAX.to_le_bytes();
+
+/// Prefix of entries split up by [`SizeLimitPolicy::ChunkValue`], followed by
+/// the number of chunks
+const CHUNKED_ENTRY_MARKER: [u8; 4] = (u32::MAX - 1).to_le_bytes();
+
+/// Prefix of the named databases used internally. Their names are stored as
+/// keys in the main database, so scans skip keys with this prefix.
+const INTERNAL_KEY_PREFIX: &str = "__lmdb_js_lite/";
+
+/// Jumps to try per entry in [`DatabaseWriter::sample`] before giving up, as
+/// the same entries are picked repeatedly once most of them are sampled
+const SAMPLE_ATTEMPTS_PER_ENTRY: usize = 4;
+
+/// xorshift64, pseudo-random enough to pick keys to sample
+struct SampleRng(u64);
+
+impl SampleRng {
+  fn below(&mut self, count: usize) -> usize {
+    self.0 ^= self.0 << 13;
+    self.0 ^= self.0 >> 7;
+    self.0 ^= self.0 << 17;
+    (self.0 % count as u64) as usize
   }
 }
 
-fn handle_message<'a, 'b>(
-  writer: &'a DatabaseWriter,
-  current_transaction: &'b mut Option<RwTxn<'a>>,
-  msg: DatabaseWriterMessage,
-) -> bool {
-  match msg {
-    DatabaseWriterMessage::Get { key, resolve } => {
-      let run = || {
-        if let Some(txn) = &current_transaction {
-          let result = writer.get(txn, &key)?.map(|d| d.to_owned());
-          Ok(result)
-        } else {
-          let txn = writer.environment.read_txn()?;
-          let result = writer.get(&txn, &key)?.map(|d| d.to_owned());
-          txn.commit()?;
-          Ok(result)
-        }
-      };
-      let result = run();
-      resolve(result.map(|o| o.map(|d| d.to_owned())));
-    }
-    DatabaseWriterMessage::Put {
-      value,
-      resolve,
-      key,
-    } => {
-      let mut run = || {
-        if let Some(txn) = current_transaction {
-          writer.put(txn, &key, &value)?;
-          Ok(())
-        } else {
-          let mut txn = writer.environment.write_txn()?;
-          writer.put(&mut txn, &key, &value)?;
-          txn.commit()?;
-          Ok(())
-        }
-      };
-      let result = run();
-      resolve(result);
-    }
-    DatabaseWriterMessage::Stop => {
-      tracing::debug!("Stopping writer thread");
-      return true;
-    }
-    DatabaseWriterMessage::StartTransaction { resolve } => {
-      if current_transaction.is_none() {
-        let mut run = || {
-          *current_transaction = Some(writer.environment.write_txn()?);
-          Ok(())
-        };
-        resolve(run())
-      } else {
-        resolve(Ok(()))
-      }
-    }
-    DatabaseWriterMessage::CommitTransaction { resolve } => {
-      if let Some(txn) = current_transaction.take() {
-        resolve(txn.commit().map_err(DatabaseWriterError::from))
-      }
-    }
-    DatabaseWriterMessage::PutMany { entries, resolve } => {
-      let mut run = || {
-        let compressed_entries: Vec<Vec<u8>> = entries
-          .par_iter()
-          .map(|entry| lz4_flex::block::compress_prepend_size(&entry.value))
-          .collect();
+/// Number of entries looked at per transaction by
+/// [`DatabaseWriterMessage::DeleteWhere`], so large deletes don't build up one
+/// huge transaction
+const DELETE_BATCH_SIZE: usize = 10_000;
 
-        let mut txn = if let Some(txn) = current_transaction {
-          RwTransaction::Borrowed(txn)
-        } else {
-          let txn = writer.environment.write_txn()?;
-          RwTransaction::Owned(txn)
-        };
+/// Number of entries written per transaction by
+/// [`DatabaseWriterMessage::CopyEntries`]
+const COPY_BATCH_SIZE: usize = 10_000;
 
-        for (NativeEntry { key, .. }, compressed_value) in entries.iter().zip(compressed_entries) {
-          writer
-            .database
-            .put(txn.deref_mut(), key, &compressed_value)?;
-        }
+/// Named database holding the chunks of values split up by
+/// [`SizeLimitPolicy::ChunkValue`]
+const CHUNKS_DATABASE: &str = "__lmdb_js_lite/chunks";
 
-        if let RwTransaction::Owned(txn) = txn {
-          txn.commit()?;
-        }
+/// Prefix of entries whose value is stored in [`DEDUP_VALUES_DATABASE`] by
+/// [`LMDBOptions::dedup_min_size`], followed by the 128-bit XXH3 hash of the
+/// value
+const DEDUP_ENTRY_MARKER: [u8; 4] = (u32::MAX - 2).to_le_bytes();
 
-        Ok(())
-      };
-      let result = run();
-      resolve(result);
-    }
-  }
-  false
+/// Named database holding the encoded values stored once by
+/// [`LMDBOptions::dedup_min_size`], keyed by their hash
+const DEDUP_VALUES_DATABASE: &str = "__lmdb_js_lite/dedup_values";
+
+/// Named database holding the number of keys referring to each value in
+/// [`DEDUP_VALUES_DATABASE`], as a little-endian `u64`
+const DEDUP_REFS_DATABASE: &str = "__lmdb_js_lite/dedup_refs";
+
+/// Hash of the value a deduplicated entry refers to, or `None` for any other
+/// entry
+fn dedup_hash(stored: &[u8]) -> Option<[u8; 16]> {
+  stored.strip_prefix(&DEDUP_ENTRY_MARKER)?.try_into().ok()
 }
 
-type ResolveCallback<T> = Box<dyn FnOnce(Result<T>) + Send>;
+/// Prefix of entries deleted while [`LMDBOptions::tombstones`] is enabled,
+/// followed by the bytes the entry was stored as until it's purged
+const TOMBSTONED_ENTRY_MARKER: [u8; 4] = (u32::MAX - 3).to_le_bytes();
 
-pub enum DatabaseWriterMessage {
-  Get {
-    key: String,
-    resolve: ResolveCallback<Option<Vec<u8>>>,
-  },
-  Put {
-    key: String,
-    value: Vec<u8>,
-    resolve: ResolveCallback<()>,
-  },
-  PutMany {
-    entries: Vec<NativeEntry>,
-    resolve: ResolveCallback<()>,
-  },
-  StartTransaction {
-    resolve: ResolveCallback<()>,
-  },
-  CommitTransaction {
-    resolve: ResolveCallback<()>,
-  },
-  Stop,
+/// Bytes a tombstoned entry was stored as, or `None` for any other entry
+fn tombstoned_entry(stored: &[u8]) -> Option<&[u8]> {
+  stored.strip_prefix(&TOMBSTONED_ENTRY_MARKER)
 }
 
-pub enum RwTransaction<'a, 'b> {
-  Owned(RwTxn<'b>),
-  Borrowed(&'a mut RwTxn<'b>),
+fn chunk_key(key: &str, index: u32) -> String {
+  format!("{key}\0{index:08x}")
 }
 
-impl<'a, 'b> RwTransaction<'a, 'b> {
-  pub fn deref_mut(&mut self) -> &mut RwTxn<'b> {
-    match self {
-      RwTransaction::Borrowed(txn) => txn,
-      RwTransaction::Owned(txn) => txn,
-    }
-  }
+/// Bytes [`chunk_key`] adds to the key of a chunked entry
+const CHUNK_KEY_SUFFIX_LEN: usize = 9;
+
+/// Number of chunks of a chunked entry, or `None` for any other entry
+fn chunk_count(stored: &[u8]) -> Option<u32> {
+  let count = stored.strip_prefix(&CHUNKED_ENTRY_MARKER)?;
+  Some(u32::from_le_bytes(count.try_into().ok()?))
 }
 
-pub enum Transaction<'a, 'b> {
-  Owned(RoTxn<'b>),
-  Borrowed(&'a RoTxn<'b>),
+/// File [`DatabaseWriter::copy_to`] writes a copy to before renaming it into
+/// place, so a crash never leaves a partial `data.mdb` behind
+const PARTIAL_COPY_FILE: &str = "data.mdb.partial";
+
+/// `MDB_PREVSNAPSHOT`, which `EnvFlags` doesn't have a name for. Opens the
+/// environment at the snapshot before the last committed transaction.
+const PREV_SNAPSHOT: u32 = 0x2000000;
+
+/// Named database mapping keys hashed by [`LMDBOptions::hash_long_keys`] to
+/// the original keys
+const LONG_KEYS_DATABASE: &str = "__lmdb_js_lite/long_keys";
+
+/// Named database holding the tombstones left by deletes when
+/// [`LMDBOptions::tombstones`] is enabled. Each is the id of the transaction
+/// that deleted the entry, as a little-endian `u64`, followed by its key.
+/// The entry itself stays in the main database until the tombstone is purged.
+const TOMBSTONES_DATABASE: &str = "__lmdb_js_lite/tombstones";
+
+/// Named database holding the id of the transaction that last wrote each
+/// entry when [`LMDBOptions::track_write_txn_ids`] is enabled, as a
+/// little-endian `u64`
+const WRITE_TXN_IDS_DATABASE: &str = "__lmdb_js_lite/write_txn_ids";
+
+/// Named database holding the keys written by
+/// [`DatabaseWriterMessage::PutTemp`] outside of an explicit transaction,
+/// along with the id of the process that wrote them as a little-endian `u32`
+const TEMP_KEYS_DATABASE: &str = "__lmdb_js_lite/temp_keys";
+
+/// Named database holding the markers of batches written across two
+/// databases that are yet to be completed, see [`crate::batch`]
+const BATCHES_DATABASE: &str = "__lmdb_js_lite/batches";
+
+/// Named database holding the ids of batches written across two databases
+/// that were applied to this one as their target, see [`crate::batch`]
+const APPLIED_BATCHES_DATABASE: &str = "__lmdb_js_lite/applied_batches";
+
+/// Named database holding the options that change how entries are stored,
+/// when they aren't the defaults, see
+/// [`DatabaseWriter::reconcile_stored_options`]
+const OPTIONS_DATABASE: &str = "__lmdb_js_lite/options";
+
+/// An option that differs from the one a database was opened with before,
+/// see [`DatabaseWriterError::OptionsMismatch`]
+#[derive(Clone, Debug, PartialEq)]
+pub struct OptionConflict {
+  /// The name of the option, as passed from JavaScript
+  pub field: &'static str,
+  pub existing: String,
+  pub requested: String,
 }
 
-impl<'a, 'b> Transaction<'a, 'b> {
-  pub fn deref(&self) -> &RoTxn<'b> {
-    match self {
-      Transaction::Borrowed(txn) => txn,
-      Transaction::Owned(txn) => &txn,
-    }
+fn describe_conflicts(conflicts: &[OptionConflict]) -> String {
+  conflicts
+    .iter()
+    .map(|conflict| {
+      format!(
+        "{} is {} but {} was requested",
+        conflict.field, conflict.existing, conflict.requested
+      )
+    })
+    .collect::<Vec<_>>()
+    .join(", ")
+}
+
+/// Fail with [`DatabaseWriterError::OptionsMismatch`] if there are any
+/// conflicts
+fn options_mismatch(options: &LMDBOptions, conflicts: Vec<OptionConflict>) -> Result<()> {
+  if conflicts.is_empty() {
+    return Ok(());
   }
+  Err(DatabaseWriterError::OptionsMismatch {
+    path: PathBuf::from(&options.path),
+    conflicts,
+  })
 }
 
-pub struct DatabaseWriter {
-  environment: Env,
-  database: heed::Database<Str, Bytes>,
+/// The options recorded in [`OPTIONS_DATABASE`], with their defaults applied
+fn stored_options(options: &LMDBOptions) -> [(&'static str, String); 2] {
+  let key_collation = match options.key_collation {
+    None | Some(KeyCollation::Binary) => "binary",
+    Some(KeyCollation::CaseInsensitive) => "caseInsensitive",
+    Some(KeyCollation::Nfc) => "nfc",
+  };
+  [
+    ("keyCollation", key_collation.to_string()),
+    (
+      "hashLongKeys",
+      (options.hash_long_keys == Some(true)).to_string(),
+    ),
+  ]
 }
 
-impl DatabaseWriter {
-  pub fn environment(&self) -> &Env {
-    &self.environment
-  }
+/// Named database holding the reference counts maintained with
+/// [`DatabaseWriter::update_ref_count`]
+const REFS_DATABASE: &str = "__lmdb_js_lite/refs";
+
+/// Handles to the named databases used internally.
+///
+/// LMDB doesn't allow databases to be opened by concurrent transactions,
+/// which reads on the JavaScript thread and writes on the writer thread would
+/// do, and closes databases opened by a transaction that's aborted. So they're
+/// all opened once, by a transaction that's committed, along with the
+/// environment. Read-only environments can't create them, so the ones that
+/// don't exist yet are `None` until another process creates them, see
+/// [`DatabaseWriter::refresh_internal_databases`].
+#[derive(Clone, Copy, Default)]
+struct InternalDatabases {
+  chunks: Option<heed::Database<Str, Bytes>>,
+  dedup_values: Option<heed::Database<Bytes, Bytes>>,
+  dedup_refs: Option<heed::Database<Bytes, Bytes>>,
+  long_keys: Option<heed::Database<Str, Str>>,
+  tombstones: Option<heed::Database<Str, Bytes>>,
+  write_txn_ids: Option<heed::Database<Str, Bytes>>,
+  temp_keys: Option<heed::Database<Str, Bytes>>,
+  batches: Option<heed::Database<Str, Bytes>>,
+  applied_batches: Option<heed::Database<Str, Unit>>,
+  options: Option<heed::Database<Str, Str>>,
+  refs: Option<heed::Database<Str, Bytes>>,
 }
 
-impl DatabaseWriter {
-  /// Create a new [`DatabaseWriter`] handle see [`LMDBOptions`] for
-  /// documentation on the settings.
-  pub fn new(options: &LMDBOptions) -> Result<Self> {
-    let path = Path::new(&options.path);
-    std::fs::create_dir_all(path)?;
-    let environment = unsafe {
-      let mut flags = EnvFlags::empty();
-      flags.set(EnvFlags::MAP_ASYNC, options.async_writes);
-      flags.set(EnvFlags::NO_SYNC, options.async_writes);
-      flags.set(EnvFlags::WRITE_MAP, true);
-      flags.set(EnvFlags::NO_READ_AHEAD, false);
-      flags.set(EnvFlags::NO_META_SYNC, options.async_writes);
-      let mut env_open_options = EnvOpenOptions::new();
-      env_open_options.flags(flags);
-      // http://www.lmdb.tech/doc/group__mdb.html#gaa2506ec8dab3d969b0e609cd82e619e5
-      // max DB size that will be memory mapped
-      if let Some(map_size) = options.map_size {
-        env_open_options.map_size(map_size as usize);
-      }
-      env_open_options.open(path)
-    }?;
-    let mut write_txn = environment.write_txn()?;
-    let database = environment.create_database(&mut write_txn, None)?;
-    write_txn.commit()?;
+impl InternalDatabases {
+  /// Number of named databases used internally, which are allowed on top of
+  /// [`LMDBOptions::max_dbs`]
+  const COUNT: u32 = 11;
 
+  fn create(environment: &Env, txn: &mut RwTxn) -> heed::Result<Self> {
     Ok(Self {
-      database,
-      environment,
+      chunks: Some(environment.create_database(txn, Some(CHUNKS_DATABASE))?),
+      dedup_values: Some(environment.create_database(txn, Some(DEDUP_VALUES_DATABASE))?),
+      dedup_refs: Some(environment.create_database(txn, Some(DEDUP_REFS_DATABASE))?),
+      long_keys: Some(environment.create_database(txn, Some(LONG_KEYS_DATABASE))?),
+      tombstones: Some(environment.create_database(txn, Some(TOMBSTONES_DATABASE))?),
+      write_txn_ids: Some(environment.create_database(txn, Some(WRITE_TXN_IDS_DATABASE))?),
+      temp_keys: Some(environment.create_database(txn, Some(TEMP_KEYS_DATABASE))?),
+      batches: Some(environment.create_database(txn, Some(BATCHES_DATABASE))?),
+      applied_batches: Some(environment.create_database(txn, Some(APPLIED_BATCHES_DATABASE))?),
+      options: Some(environment.create_database(txn, Some(OPTIONS_DATABASE))?),
+      refs: Some(environment.create_database(txn, Some(REFS_DATABASE))?),
     })
   }
 
-  /// Compress an entry and store it
-  pub fn get(&self, txn: &RoTxn, key: &str) -> Result<Option<Vec<u8>>> {
-    if let Some(result) = self.database.get(txn, key)? {
-      let output_buffer = lz4_flex::block::decompress_size_prepended(result)?;
-      Ok(Some(output_buffer))
-    } else {
-      Ok(None)
-    }
+  fn open(environment: &Env, txn: &RoTxn) -> heed::Result<Self> {
+    Ok(Self {
+      chunks: environment.open_database(txn, Some(CHUNKS_DATABASE))?,
+      dedup_values: environment.open_database(txn, Some(DEDUP_VALUES_DATABASE))?,
+      dedup_refs: environment.open_database(txn, Some(DEDUP_REFS_DATABASE))?,
+      long_keys: environment.open_database(txn, Some(LONG_KEYS_DATABASE))?,
+      tombstones: environment.open_database(txn, Some(TOMBSTONES_DATABASE))?,
+      write_txn_ids: environment.open_database(txn, Some(WRITE_TXN_IDS_DATABASE))?,
+      temp_keys: environment.open_database(txn, Some(TEMP_KEYS_DATABASE))?,
+      batches: environment.open_database(txn, Some(BATCHES_DATABASE))?,
+      applied_batches: environment.open_database(txn, Some(APPLIED_BATCHES_DATABASE))?,
+      options: environment.open_database(txn, Some(OPTIONS_DATABASE))?,
+      refs: environment.open_database(txn, Some(REFS_DATABASE))?,
+    })
   }
 
-  /// Read an entry and decompress it
-  pub fn put(&self, txn: &mut RwTxn, key: &str, data: &[u8]) -> Result<()> {
-    let compressed_data = lz4_flex::block::compress_prepend_size(data);
-    self.database.put(txn, key, &compressed_data)?;
-    Ok(())
+  fn is_complete(&self) -> bool {
+    self.chunks.is_some()
+      && self.dedup_values.is_some()
+      && self.dedup_refs.is_some()
+      && self.long_keys.is_some()
+      && self.tombstones.is_some()
+      && self.write_txn_ids.is_some()
+      && self.temp_keys.is_some()
+      && self.batches.is_some()
+      && self.applied_batches.is_some()
+      && self.options.is_some()
+      && self.refs.is_some()
   }
+}
 
-  /// Create a read transaction
-  pub fn read_txn(&self) -> heed::Result<RoTxn> {
-    self.environment.read_txn()
+/// An internal database a write needs. Only read-only environments, which
+/// LMDB doesn't start write transactions on, can be missing them.
+fn writable<K, D>(database: Option<heed::Database<K, D>>) -> Result<heed::Database<K, D>> {
+  database.ok_or_else(|| heed::Error::Mdb(heed::MdbError::Other(libc::EACCES)).into())
+}
+
+/// Shorten a key to `max` bytes by replacing its tail with a hash of the
+/// whole key
+fn hash_key(key: &str, max: usize) -> String {
+  let hash = format!("#{:016x}", fnv1a(key.as_bytes()));
+  let mut end = max.saturating_sub(hash.len());
+  while !key.is_char_boundary(end) {
+    end -= 1;
   }
+  format!("{}{hash}", &key[..end])
+}
 
-  /// Create a static read transaction that owns a reference counted copy of
-  /// the database environment
-  pub fn static_read_txn(&self) -> heed::Result<RoTxn<'static>> {
-    self.environment.clone().static_read_txn()
+/// Free space available to unprivileged users on the filesystem holding
+/// `path`, if it can be determined on this platform
+#[cfg(unix)]
+// The `statvfs` field types differ between platforms
+#[allow(clippy::useless_conversion)]
+fn available_disk_space(path: &Path) -> std::io::Result<Option<u64>> {
+  use std::os::unix::ffi::OsStrExt;
+
+  let path = std::ffi::CString::new(path.as_os_str().as_bytes())?;
+  let mut stat = std::mem::MaybeUninit::<libc::statvfs>::uninit();
+  if unsafe { libc::statvfs(path.as_ptr(), stat.as_mut_ptr()) } != 0 {
+    return Err(std::io::Error::last_os_error());
   }
+  let stat = unsafe { stat.assume_init() };
+  Ok(Some(u64::from(stat.f_bavail) * u64::from(stat.f_frsize)))
 }
 
-#[cfg(test)]
-mod test {
-  use std::env::temp_dir;
-  use std::sync::mpsc::channel;
+#[cfg(not(unix))]
+fn available_disk_space(_path: &Path) -> std::io::Result<Option<u64>> {
+  Ok(None)
+}
 
-  use super::*;
+/// Disk space allocated to the file at `path`, which is less than its length
+/// while the data file of a memory map is sparse
+#[cfg(unix)]
+fn allocated_size(path: &Path) -> std::io::Result<u64> {
+  use std::os::unix::fs::MetadataExt;
 
-  fn random() -> String {
-    let value = rand::random::<i32>();
-    format!("{value}")
+  Ok(std::fs::metadata(path)?.blocks() * 512)
+}
+
+#[cfg(not(unix))]
+fn allocated_size(path: &Path) -> std::io::Result<u64> {
+  Ok(std::fs::metadata(path)?.len())
+}
+
+/// Whether a value starts with a gzip or zstd header
+fn is_compressed(data: &[u8]) -> bool {
+  data.starts_with(&[0x1f, 0x8b]) || data.starts_with(&[0x28, 0xb5, 0x2f, 0xfd])
+}
+
+/// Remove insignificant whitespace from a JSON document. Returns `None` for
+/// values that don't look like a JSON object or array.
+fn minify_json(data: &[u8]) -> Option<Vec<u8>> {
+  let is_whitespace = |byte: u8| matches!(byte, b' ' | b'\t' | b'\n' | b'\r');
+  let start = data.iter().position(|byte| !is_whitespace(*byte))?;
+  if !matches!(data[start], b'{' | b'[') {
+    return None;
   }
 
-  #[test]
-  fn database_writer_can_read_and_write() {
-    let db_path = temp_dir()
-      .join("lmdb-js-lite")
-      .join(random())
-      .join("lmdb-cache-tests.db");
-    let _ = std::fs::remove_dir_all(&db_path);
+  let mut output = Vec::with_capacity(data.len());
+  let mut in_string = false;
+  let mut escaped = false;
+  for &byte in &data[start..] {
+    if in_string {
+      output.push(byte);
+      if escaped {
+        escaped = false;
+      } else if byte == b'\\' {
+        escaped = true;
+      } else if byte == b'"' {
+        in_string = false;
+      }
+    } else if byte == b'"' {
+      in_string = true;
+      output.push(byte);
+    } else if !is_whitespace(byte) {
+      output.push(byte);
+    }
+  }
+  // An unterminated string means this wasn't JSON after all
+  (!in_string).then_some(output)
+}
 
-    let options = LMDBOptions {
-      path: db_path.to_str().unwrap().to_string(),
-      async_writes: false,
-      map_size: None,
-    };
+/// A value after [`LMDBOptions::value_transforms`] have been applied
+struct TransformedValue<'a> {
+  data: Cow<'a, [u8]>,
+  compress: bool,
+}
 
+impl TransformedValue<'_> {
+  /// Encode the value as it is stored in the database
+  fn encode(&self) -> Vec<u8> {
+    if self.compress {
+      encode_compressed(&self.data)
+    } else {
+      encode_entry(Codec::None, &self.data)
+    }
+  }
+}
+
+/// Compress a value, unless that doesn't make it any smaller, in which case
+/// it's stored as it is so reads don't pay for decompressing it
+fn encode_compressed(data: &[u8]) -> Vec<u8> {
+  let compressed = encode_entry(Codec::Lz4, data);
+  // Both encodings have a four byte prefix
+  if compressed.len() >= RAW_ENTRY_MARKER.len() + data.len() {
+    encode_entry(Codec::None, data)
+  } else {
+    compressed
+  }
+}
+
+/// Encode a value as it is stored in the database with the given codec
+fn encode_entry(codec: Codec, data: &[u8]) -> Vec<u8> {
+  match codec {
+    Codec::Lz4 => lz4_flex::block::compress_prepend_size(data),
+    Codec::None => [&RAW_ENTRY_MARKER[..], data].concat(),
+  }
+}
+
+fn remove_partial_copy(path: &Path) -> Result<()> {
+  match std::fs::remove_file(path) {
+    Ok(()) => {
+      tracing::warn!("Removed unfinished copy {path:?}");
+      Ok(())
+    }
+    Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+    Err(err) => Err(err.into()),
+  }
+}
+
+/// Whether the process that wrote a temporary key may still be using it
+#[cfg(unix)]
+fn is_process_running(pid: u32) -> bool {
+  if pid == std::process::id() {
+    return true;
+  }
+  // Zero and negative ids would signal process groups instead
+  if pid == 0 || pid > libc::pid_t::MAX as u32 {
+    return false;
+  }
+  let result = unsafe { libc::kill(pid as libc::pid_t, 0) };
+  result == 0 || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+#[cfg(not(unix))]
+fn is_process_running(pid: u32) -> bool {
+  pid == std::process::id()
+}
+
+/// Errors that may go away if opening is retried, such as another process
+/// holding a lock or still creating the directory. An environment that is
+/// still being closed is waited for by the caller instead, see
+/// [`heed::env_closing_event`].
+fn is_transient_open_error(err: &heed::Error) -> bool {
+  match err {
+    heed::Error::Io(err) => {
+      err.kind() == std::io::ErrorKind::NotFound
+        || matches!(err.raw_os_error(), Some(libc::EBUSY | libc::EAGAIN))
+    }
+    _ => false,
+  }
+}
+
+/// 64-bit FNV-1a, used to shorten overlong keys. Unlike `DefaultHasher` its
+/// output is stable across Rust versions, which matters for keys on disk.
+fn fnv1a(bytes: &[u8]) -> u64 {
+  bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+    (hash ^ u64::from(*byte)).wrapping_mul(0x100000001b3)
+  })
+}
+
+/// Key of a content-addressed value, the 128-bit XXH3 hash of the value in
+/// hex, see [`DatabaseWriter::put_content`]
+pub fn content_key(value: &[u8]) -> String {
+  format!("{:032x}", XxHash3_128::oneshot(value))
+}
+
+/// How the memory map is expected to be read, passed on to `madvise`. Only
+/// has an effect on Linux.
+///
+/// The advice covers the whole memory map, as the pages of a range of keys
+/// aren't next to each other in it, so it applies to every read of the
+/// database in this process while it's in effect.
+#[derive(Debug, PartialEq, PartialOrd)]
+#[napi(string_enum = "lowercase")]
+pub enum AccessPattern {
+  /// The kernel's default read-ahead
+  Normal,
+  /// Read ahead aggressively and free pages soon after they have been read
+  Sequential,
+  /// Don't read ahead, keeping resident memory down for point lookups
+  Random,
+}
+
+/// Address and length of the memory map of `data_path`. LMDB doesn't expose
+/// where it mapped the file, so it is looked up in `/proc/self/maps`.
+#[cfg(target_os = "linux")]
+fn find_memory_map(data_path: &Path) -> std::io::Result<Option<(usize, usize)>> {
+  let maps = std::fs::read_to_string("/proc/self/maps")?;
+  for line in maps.lines() {
+    // address perms offset dev inode path
+    let mut fields = line.splitn(6, ' ');
+    let (Some(range), Some(offset), Some(path)) = (fields.next(), fields.nth(1), fields.nth(2))
+    else {
+      continue;
+    };
+    if Path::new(path.trim_start()) != data_path || u64::from_str_radix(offset, 16) != Ok(0) {
+      continue;
+    }
+    let Some((start, end)) = range.split_once('-') else {
+      continue;
+    };
+    if let (Ok(start), Ok(end)) = (
+      usize::from_str_radix(start, 16),
+      usize::from_str_radix(end, 16),
+    ) {
+      return Ok(Some((start, end - start)));
+    }
+  }
+  Ok(None)
+}
+
+#[cfg(target_os = "linux")]
+fn advise_memory_map(data_path: &Path, pattern: &AccessPattern) -> std::io::Result<()> {
+  let Some((address, len)) = find_memory_map(data_path)? else {
+    return Ok(());
+  };
+  let advice = match pattern {
+    AccessPattern::Normal => libc::MADV_NORMAL,
+    AccessPattern::Sequential => libc::MADV_SEQUENTIAL,
+    AccessPattern::Random => libc::MADV_RANDOM,
+  };
+  if unsafe { libc::madvise(address as *mut libc::c_void, len, advice) } != 0 {
+    return Err(std::io::Error::last_os_error());
+  }
+  Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn advise_memory_map(_data_path: &Path, _pattern: &AccessPattern) -> std::io::Result<()> {
+  Ok(())
+}
+
+/// Allocate `len` bytes of disk space for `data_path`, and read every page of
+/// its memory map if `pretouch` is set
+#[cfg(target_os = "linux")]
+fn preallocate(data_path: &Path, len: usize, pretouch: bool) -> std::io::Result<()> {
+  use std::os::fd::AsRawFd;
+
+  let file = std::fs::OpenOptions::new().write(true).open(data_path)?;
+  let result = unsafe { libc::posix_fallocate(file.as_raw_fd(), 0, len as libc::off_t) };
+  if result != 0 {
+    return Err(std::io::Error::from_raw_os_error(result));
+  }
+  if !pretouch {
+    return Ok(());
+  }
+  let Some((address, len)) = find_memory_map(data_path)? else {
+    return Ok(());
+  };
+  let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+  for offset in (0..len).step_by(page_size) {
+    unsafe { std::ptr::read_volatile((address + offset) as *const u8) };
+  }
+  Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn preallocate(_data_path: &Path, _len: usize, _pretouch: bool) -> std::io::Result<()> {
+  Ok(())
+}
+
+/// Restores [`LMDBOptions::access_pattern`] when the last one still scanning
+/// is dropped, see [`DatabaseWriter::sequential_scan`]
+pub struct SequentialScan<'a>(&'a DatabaseWriter);
+
+impl Drop for SequentialScan<'_> {
+  fn drop(&mut self) {
+    let mut scans = self
+      .0
+      .sequential_scans
+      .lock()
+      .unwrap_or_else(PoisonError::into_inner);
+    *scans -= 1;
+    if *scans == 0 {
+      let pattern = self.0.options.access_pattern.as_ref();
+      self.0.advise(pattern.unwrap_or(&AccessPattern::Normal));
+    }
+  }
+}
+
+/// Scheduling priority of the writer thread
+#[derive(Debug, PartialEq, PartialOrd)]
+#[napi(string_enum = "lowercase")]
+pub enum WriterThreadPriority {
+  /// Keep the process default priority
+  Normal,
+  /// Niceness 10 on Linux, `QOS_CLASS_UTILITY` on macOS
+  Low,
+  /// Niceness 19 on Linux, `QOS_CLASS_BACKGROUND` on macOS
+  Background,
+}
+
+/// See [`LMDBOptions::resolve_panic_policy`]
+#[derive(Debug, PartialEq, PartialOrd)]
+#[napi(string_enum = "lowercase")]
+pub enum ResolvePanicPolicy {
+  /// Let the panic unwind the writer thread, which stops handling messages
+  Panic,
+  /// Report a [`DatabaseWriterError::ResolvePanicked`] to the error listener
+  /// and keep handling messages
+  Report,
+}
+
+/// How an entry is encoded on disk
+#[derive(Debug, PartialEq)]
+#[napi(string_enum = "lowercase")]
+pub enum Codec {
+  /// LZ4 block compression, prefixed with the uncompressed size
+  Lz4,
+  /// Stored as-is, for values that don't compress and
+  /// [`DatabaseWriter::put_reserved`]
+  None,
+}
+
+/// How a codec fared on a sample of entries, see
+/// [`DatabaseWriter::compare_codecs`]
+#[derive(Debug)]
+pub struct CodecComparison {
+  pub codec: Codec,
+  pub entries: usize,
+  /// Size of the values before encoding
+  pub value_bytes: usize,
+  /// Size of the values once encoded, as they would be stored
+  pub encoded_bytes: usize,
+  /// Total time spent encoding and decoding the sample
+  pub encode: Duration,
+  pub decode: Duration,
+}
+
+/// Decode a value as returned by [`DatabaseWriter::get_raw`], checking that
+/// it is a valid stored entry first
+pub fn decode_raw_entry(key: &str, stored: &[u8]) -> Result<Vec<u8>> {
+  check_raw_entry(key, stored)?;
+  Ok(decode_entry(stored)?.0)
+}
+
+/// How a value as returned by [`DatabaseWriter::get_raw`] is encoded
+pub fn raw_entry_codec(stored: &[u8]) -> Codec {
+  if stored.starts_with(&RAW_ENTRY_MARKER) {
+    Codec::None
+  } else {
+    Codec::Lz4
+  }
+}
+
+/// Chunked and deduplicated entries can't be written raw, as their values
+/// are stored separately. LZ4 blocks can't expand to more than 255 times
+/// their size, which rules out sizes no compressor would have written.
+fn check_raw_entry(key: &str, stored: &[u8]) -> Result<()> {
+  let is_valid = stored.starts_with(&RAW_ENTRY_MARKER)
+    || raw_entry_size(stored).is_some_and(|size| size <= stored.len().saturating_mul(255))
+      && chunk_count(stored).is_none()
+      && dedup_hash(stored).is_none();
+  if !is_valid {
+    return Err(DatabaseWriterError::InvalidRawEntry(key.to_string()));
+  }
+  Ok(())
+}
+
+/// Size of the value an entry decodes to, from its header
+fn raw_entry_size(stored: &[u8]) -> Option<usize> {
+  if let Some(value) = stored.strip_prefix(&RAW_ENTRY_MARKER) {
+    return Some(value.len());
+  }
+  let (size, _) = stored.split_first_chunk::<4>()?;
+  usize::try_from(u32::from_le_bytes(*size)).ok()
+}
+
+/// Decode a stored entry, returning the value and how it was encoded
+fn decode_entry(stored: &[u8]) -> Result<(Vec<u8>, Codec)> {
+  if let Some(value) = stored.strip_prefix(&RAW_ENTRY_MARKER) {
+    Ok((value.to_vec(), Codec::None))
+  } else {
+    let value = lz4_flex::block::decompress_size_prepended(stored)?;
+    Ok((value, Codec::Lz4))
+  }
+}
+
+/// This is a message passing handle into the writer thread.
+///
+/// There is always at most a single writer thread per database. If
+/// [`LMDBOptions::writer_idle_timeout_ms`] is set the thread may be stopped
+/// while idle, in which case it is restarted when the next message is sent.
+pub struct DatabaseWriterHandle {
+  tx: Sender<QueuedMessage>,
+  rx: Receiver<QueuedMessage>,
+  writer: Arc<DatabaseWriter>,
+  thread: Arc<Mutex<WriterThreadState>>,
+}
+
+/// A message along with when it was sent, to measure how long it was queued
+struct QueuedMessage {
+  message: DatabaseWriterMessage,
+  sent_at: Instant,
+  /// Id of the message in [`DatabaseWriter::pending_ops`], unset for
+  /// messages that aren't tracked there
+  pending_id: Option<u64>,
+  /// Recorded on the span the message is handled in
+  trace_id: Option<String>,
+}
+
+impl From<DatabaseWriterMessage> for QueuedMessage {
+  fn from(message: DatabaseWriterMessage) -> Self {
+    Self {
+      message,
+      sent_at: clock::now(),
+      pending_id: None,
+      trace_id: None,
+    }
+  }
+}
+
+/// A message in the writer thread's queue, see
+/// [`DatabaseWriter::pending_ops`]
+#[derive(Clone, Debug, PartialEq)]
+pub struct PendingOp {
+  /// Name of the method that sent the message
+  pub operation: &'static str,
+  /// The key written or read, for operations on a single key
+  pub key: Option<String>,
+}
+
+struct TrackedOp {
+  op: PendingOp,
+  cancellable: bool,
+  /// Set by [`DatabaseWriter::cancel_pending`]
+  cancelled: bool,
+}
+
+#[derive(Default)]
+struct PendingOps {
+  next_id: u64,
+  ops: BTreeMap<u64, TrackedOp>,
+}
+
+struct WriterThreadState {
+  thread_handle: Option<JoinHandle<()>>,
+  /// Set to false by the writer thread, while holding the lock, once it has
+  /// decided to exit
+  running: bool,
+}
+
+impl DatabaseWriterHandle {
+  /// Send a message into the writer thread, restarting it if it has been
+  /// stopped for being idle.
+  pub fn send(&self, message: DatabaseWriterMessage) -> Result<()> {
+    self.send_traced(message, None)
+  }
+
+  /// Send a message into the writer thread, to be handled within a span
+  /// recording `trace_id`
+  pub fn send_traced(
+    &self,
+    message: DatabaseWriterMessage,
+    trace_id: Option<String>,
+  ) -> Result<()> {
+    let pending_id = self.writer.track_pending(&message);
+    let message = QueuedMessage {
+      message,
+      sent_at: clock::now(),
+      pending_id: Some(pending_id),
+      trace_id,
+    };
+    if self.tx.send(message).is_err() {
+      self.writer.untrack_pending(pending_id);
+      return Err(DatabaseWriterError::SendError);
+    }
+
+    // The writer thread only exits if the queue is empty while holding this
+    // lock, so if it's no longer running our message is still in the queue.
+    let mut state = self.thread.lock().unwrap_or_else(PoisonError::into_inner);
+    if state.running {
+      return Ok(());
+    }
+    tracing::debug!("Restarting idle writer thread");
+    let stopped = state.thread_handle.replace(spawn_writer_thread(
+      self.rx.clone(),
+      self.writer.clone(),
+      self.thread.clone(),
+    )?);
+    state.running = true;
+    drop(state);
+    // Joined without holding the lock, so other senders aren't held up
+    // while the previous thread finishes exiting
+    if let Some(stopped) = stopped {
+      let _ = stopped.join();
+    }
+
+    Ok(())
+  }
+
+  /// Whether the writer thread has no messages left to pick up and doesn't
+  /// hold an explicit write transaction
+  pub fn is_idle(&self) -> bool {
+    self.rx.is_empty() && !self.writer.transaction_open.load(Ordering::Relaxed)
+  }
+
+  /// Ask the writer thread to stop once it has handled all queued messages.
+  pub fn stop(&self) {
+    let _ = self.tx.send(DatabaseWriterMessage::Stop.into());
+  }
+}
+
+impl Drop for DatabaseWriterHandle {
+  fn drop(&mut self) {
+    self.stop();
+  }
+}
+
+/// Open the database and start the writer thread. Two handles are returned:
+///
+/// * A raw DB handle that can be used for synchronous reads
+/// * A writer handle that can be used to send messages to the writer thread
+/// 
+/// The writer handle should be used to create write transactions shared across
+/// Node.js threads.
+pub fn start_make_database_writer(
+  options: &LMDBOptions,
+) -> Result<(DatabaseWriterHandle, Arc<DatabaseWriter>)> {
+  let writer = Arc::new(DatabaseWriter::new(options)?);
+  Ok((start_database_writer(writer.clone())?, writer))
+}
+
+/// Start a writer thread for a database that is already open, such as one
+/// whose previous writer thread was stopped
+pub fn start_database_writer(writer: Arc<DatabaseWriter>) -> Result<DatabaseWriterHandle> {
+  let (tx, rx) = crossbeam::channel::unbounded();
+  let thread = Arc::new(Mutex::new(WriterThreadState {
+    thread_handle: None,
+    running: true,
+  }));
+  let thread_handle = spawn_writer_thread(rx.clone(), writer.clone(), thread.clone())?;
+  thread
+    .lock()
+    .unwrap_or_else(PoisonError::into_inner)
+    .thread_handle = Some(thread_handle);
+
+  Ok(DatabaseWriterHandle {
+    tx,
+    rx,
+    writer,
+    thread,
+  })
+}
+
+fn spawn_writer_thread(
+  rx: Receiver<QueuedMessage>,
+  writer: Arc<DatabaseWriter>,
+  thread: Arc<Mutex<WriterThreadState>>,
+) -> Result<JoinHandle<()>> {
+  let options = writer.options();
+  let thread_name = options
+    .writer_thread_name
+    .clone()
+    .unwrap_or_else(|| DEFAULT_WRITER_THREAD_NAME.to_string());
+  let priority = options
+    .writer_thread_priority
+    .unwrap_or(WriterThreadPriority::Normal);
+  let thread_handle = std::thread::Builder::new()
+    .name(thread_name)
+    .spawn(move || {
+      if let Err(err) = set_current_thread_priority(priority) {
+        tracing::warn!("Failed to set writer thread priority to {priority:?}: {err}");
+      }
+      run_database_writer(rx, writer, thread);
+    })?;
+  Ok(thread_handle)
+}
+
+#[cfg(target_os = "linux")]
+fn set_current_thread_priority(priority: WriterThreadPriority) -> std::io::Result<()> {
+  let nice = match priority {
+    WriterThreadPriority::Normal => return Ok(()),
+    WriterThreadPriority::Low => 10,
+    WriterThreadPriority::Background => 19,
+  };
+  // On Linux niceness is a per-thread attribute, addressed by the thread id
+  let result = unsafe {
+    let tid = libc::syscall(libc::SYS_gettid) as libc::id_t;
+    libc::setpriority(libc::PRIO_PROCESS, tid, nice)
+  };
+  if result != 0 {
+    return Err(std::io::Error::last_os_error());
+  }
+  Ok(())
+}
+
+#[cfg(target_os = "macos")]
+fn set_current_thread_priority(priority: WriterThreadPriority) -> std::io::Result<()> {
+  let qos_class = match priority {
+    WriterThreadPriority::Normal => return Ok(()),
+    WriterThreadPriority::Low => libc::qos_class_t::QOS_CLASS_UTILITY,
+    WriterThreadPriority::Background => libc::qos_class_t::QOS_CLASS_BACKGROUND,
+  };
+  let result = unsafe { libc::pthread_set_qos_class_self_np(qos_class, 0) };
+  if result != 0 {
+    return Err(std::io::Error::from_raw_os_error(result));
+  }
+  Ok(())
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn set_current_thread_priority(_priority: WriterThreadPriority) -> std::io::Result<()> {
+  Ok(())
+}
+
+/// Main-loop for the database writer thread
+fn run_database_writer(
+  rx: Receiver<QueuedMessage>,
+  writer: Arc<DatabaseWriter>,
+  thread: Arc<Mutex<WriterThreadState>>,
+) {
+  tracing::debug!("Starting database writer thread");
+  let idle_timeout = writer
+    .options()
+    .writer_idle_timeout_ms
+    .map(|timeout| Duration::from_millis(timeout.into()));
+  let max_transaction_age = writer
+    .options()
+    .max_write_transaction_age_ms
+    .map(|age| Duration::from_millis(age.into()));
+  let mut current_transaction: Option<RwTxn> = None;
+  let mut transaction_stats = TransactionStats::default();
+  let mut tiny_batch = TinyBatch::default();
+
+  loop {
+    if rx.is_empty() || tiny_batch.resolves.len() >= TINY_BATCH_MAX_ENTRIES {
+      tiny_batch.commit(&writer);
+    }
+    writer
+      .transaction_open
+      .store(current_transaction.is_some(), Ordering::Relaxed);
+    // Wake up when the open transaction expires, even if no messages arrive
+    let transaction_timeout = max_transaction_age.zip(transaction_stats.started_at).map(
+      |(max_transaction_age, started_at)| {
+        max_transaction_age.saturating_sub(clock::elapsed(started_at))
+      },
+    );
+    let timeout = match (idle_timeout, transaction_timeout) {
+      (Some(idle_timeout), Some(transaction_timeout)) => {
+        Some(idle_timeout.min(transaction_timeout))
+      }
+      (idle_timeout, transaction_timeout) => idle_timeout.or(transaction_timeout),
+    };
+    // Without a timeout this waits indefinitely
+    let received = rx.recv_timeout(timeout.unwrap_or(Duration::MAX));
+    let QueuedMessage {
+      mut message,
+      sent_at,
+      pending_id,
+      trace_id,
+    } = match received {
+      Ok(msg) => msg,
+      // The handle holds onto a receiver as well, so the channel is never
+      // disconnected and this can only be the timeout
+      Err(_) => {
+        if current_transaction.is_some() {
+          enforce_transaction_limits(&writer, &mut current_transaction, &mut transaction_stats);
+          continue;
+        }
+        if idle_timeout.is_none() {
+          continue;
+        }
+        let mut state = thread.lock().unwrap_or_else(PoisonError::into_inner);
+        if rx.is_empty() {
+          tracing::debug!("Stopping idle writer thread");
+          state.running = false;
+          break;
+        }
+        continue;
+      }
+    };
+    let queue_wait = clock::elapsed(sent_at);
+    writer.record_metrics(|metrics| {
+      metrics.messages += 1;
+      metrics.queue_wait += queue_wait;
+    });
+    let (operation, key) = message.operation();
+    let span = tracing::debug_span!(
+      "writer_message",
+      operation,
+      key = key.map(|key| writer.redact_key(key)).as_deref(),
+      trace_id = trace_id.as_deref(),
+      queue_wait_us = queue_wait.as_micros() as u64,
+    );
+    let _entered = span.enter();
+    if let Some(journal) = writer.journal.clone() {
+      let (operation, key) = message.operation();
+      let redacted_key = key.map(|key| writer.redact_key(key).into_owned());
+      let key = key.map(str::to_string);
+      let bytes = message.write_bytes();
+      let start = Instant::now();
+      message.observe(Box::new(move |error| {
+        // Errors about an entry name its key
+        let error = match (error, &key, &redacted_key) {
+          (Some(error), Some(key), Some(redacted_key)) if key != redacted_key => {
+            Some(error.replace(key.as_str(), redacted_key))
+          }
+          (error, ..) => error,
+        };
+        let entry = JournalEntry {
+          operation,
+          key: redacted_key.as_deref(),
+          bytes,
+          queued: queue_wait,
+          duration: start.elapsed(),
+          error: error.as_deref(),
+        };
+        if let Err(err) = journal.record(&entry) {
+          tracing::debug!("Failed to write to the journal: {err}");
+        }
+      }));
+    }
+    if writer.options().resolve_panic_policy == Some(ResolvePanicPolicy::Report) {
+      let writer = writer.clone();
+      let (operation, _) = message.operation();
+      message.guard(Box::new(move |message| {
+        writer.report_error(
+          operation,
+          &DatabaseWriterError::ResolvePanicked { operation, message },
+        );
+      }));
+    }
+    if pending_id.is_some_and(|id| writer.untrack_pending(id)) {
+      message.cancel();
+      continue;
+    }
+    if !matches!(message, DatabaseWriterMessage::PutTiny { .. }) {
+      tiny_batch.commit(&writer);
+    }
+    let start = Instant::now();
+    let stop = handle_message(
+      &writer,
+      &mut current_transaction,
+      &mut tiny_batch,
+      &mut transaction_stats,
+      message,
+    );
+    tracing::debug!(
+      duration_us = start.elapsed().as_micros() as u64,
+      "Handled message"
+    );
+    if stop {
+      break;
+    }
+    enforce_transaction_limits(&writer, &mut current_transaction, &mut transaction_stats);
+  }
+
+  tiny_batch.commit(&writer);
+  if let Some(txn) = current_transaction {
+    if let Err(err) = commit_transaction(&writer, txn, transaction_stats) {
+      writer.report_error("commitWriteTransaction", &err);
+    }
+  }
+}
+
+/// Values up to this many bytes can be written with
+/// [`DatabaseWriterMessage::PutTiny`]
+pub const TINY_VALUE_MAX_SIZE: usize = 128;
+
+/// Number of tiny writes committed in one transaction at most, see
+/// [`TinyBatch`]
+const TINY_BATCH_MAX_ENTRIES: usize = 1_000;
+
+/// Tiny writes made outside of an explicit write transaction, committed
+/// together once nothing else is queued
+#[derive(Default)]
+struct TinyBatch<'a> {
+  txn: Option<RwTxn<'a>>,
+  resolves: Vec<ResolveCallback<()>>,
+  bytes: usize,
+}
+
+impl TinyBatch<'_> {
+  fn commit(&mut self, writer: &DatabaseWriter) {
+    let Some(txn) = self.txn.take() else {
+      return;
+    };
+    let resolves = std::mem::take(&mut self.resolves);
+    let bytes = std::mem::take(&mut self.bytes);
+    match writer.commit(txn, resolves.len(), bytes) {
+      Ok(()) => resolves.into_iter().for_each(|resolve| resolve(Ok(()))),
+      Err(err) => {
+        let (code, message) = (err.code(), err.to_string());
+        for resolve in resolves {
+          resolve(Err(DatabaseWriterError::BatchCommit {
+            code,
+            message: message.clone(),
+          }));
+        }
+      }
+    }
+  }
+}
+
+/// Bookkeeping for the explicit write transaction, if there is one
+#[derive(Default)]
+struct TransactionStats {
+  started_at: Option<Instant>,
+  operations: usize,
+  bytes: usize,
+  /// Keys written by [`DatabaseWriterMessage::PutTemp`], deleted again when
+  /// the transaction commits
+  temp_keys: Vec<String>,
+  /// Whether the transaction was aborted by [`enforce_transaction_limits`],
+  /// kept until the caller tries to commit it or starts another
+  aborted: bool,
+}
+
+/// Commit the explicit write transaction, deleting its temporary keys first
+fn commit_transaction(
+  writer: &DatabaseWriter,
+  mut txn: RwTxn,
+  transaction_stats: TransactionStats,
+) -> Result<()> {
+  for key in &transaction_stats.temp_keys {
+    writer.delete(&mut txn, key)?;
+  }
+  writer.commit(txn, transaction_stats.operations, transaction_stats.bytes)
+}
+
+/// End the explicit write transaction if it has exceeded the limits set in
+/// [`LMDBOptions`]
+fn enforce_transaction_limits(
+  writer: &DatabaseWriter,
+  current_transaction: &mut Option<RwTxn>,
+  transaction_stats: &mut TransactionStats,
+) {
+  let options = writer.options();
+  let Some(started_at) = transaction_stats.started_at else {
+    return;
+  };
+  let age = clock::elapsed(started_at);
+  let exceeded_age = options
+    .max_write_transaction_age_ms
+    .is_some_and(|max_age| age >= Duration::from_millis(max_age.into()));
+  let exceeded_operations = options
+    .max_write_transaction_operations
+    .is_some_and(|max_operations| transaction_stats.operations >= max_operations as usize);
+  if !exceeded_age && !exceeded_operations {
+    return;
+  }
+  let Some(txn) = current_transaction.take() else {
+    return;
+  };
+
+  let policy = options
+    .write_transaction_limit_policy
+    .unwrap_or(WriteTransactionLimitPolicy::Commit);
+  let transaction_stats = std::mem::replace(
+    transaction_stats,
+    TransactionStats {
+      aborted: policy == WriteTransactionLimitPolicy::Abort,
+      ..Default::default()
+    },
+  );
+  let operations = transaction_stats.operations;
+  match policy {
+    WriteTransactionLimitPolicy::Commit => {
+      tracing::warn!(
+        "Committing write transaction open for {age:?} with {operations} writes, it exceeded its limits"
+      );
+      if let Err(err) = commit_transaction(writer, txn, transaction_stats) {
+        writer.report_error("commitWriteTransaction", &err);
+      }
+    }
+    WriteTransactionLimitPolicy::Abort => {
+      tracing::warn!(
+        "Aborting write transaction open for {age:?} with {operations} writes, it exceeded its limits"
+      );
+      txn.abort();
+    }
+  }
+}
+
+fn handle_message<'a, 'b>(
+  writer: &'a DatabaseWriter,
+  current_transaction: &'b mut Option<RwTxn<'a>>,
+  tiny_batch: &mut TinyBatch<'a>,
+  transaction_stats: &mut TransactionStats,
+  msg: DatabaseWriterMessage,
+) -> bool {
+  if let Err(err) = writer.check_transaction(&msg, current_transaction.is_some()) {
+    msg.reject(err);
+    return false;
+  }
+  // Writes meant for an aborted transaction would otherwise be committed on
+  // their own
+  if transaction_stats.aborted
+    && (msg.is_write() || matches!(msg, DatabaseWriterMessage::CommitTransaction { .. }))
+  {
+    if matches!(msg, DatabaseWriterMessage::CommitTransaction { .. }) {
+      transaction_stats.aborted = false;
+    }
+    let operation = msg.operation().0;
+    msg.reject(DatabaseWriterError::TransactionAborted(operation));
+    return false;
+  }
+  let (write_count, write_bytes) = (msg.write_count(), msg.write_bytes());
+  if current_transaction.is_some() {
+    transaction_stats.operations += write_count;
+    transaction_stats.bytes += write_bytes;
+  }
+
+  match msg {
+    DatabaseWriterMessage::Get { key, resolve } => {
+      let run = || {
+        if let Some(txn) = &current_transaction {
+          let result = writer.get(txn, &key)?.map(|d| d.to_owned());
+          Ok(result)
+        } else {
+          let txn = writer.read_txn()?;
+          let result = writer.get(&txn, &key)?.map(|d| d.to_owned());
+          txn.commit()?;
+          Ok(result)
+        }
+      };
+      let result = run();
+      resolve(result.map(|o| o.map(|d| d.to_owned())));
+    }
+    DatabaseWriterMessage::GetManyFrom { keys, resolve } => {
+      let run = || {
+        if let Some(txn) = &current_transaction {
+          writer.get_many_from(txn, &keys)
+        } else {
+          let txn = writer.read_txn()?;
+          let result = writer.get_many_from(&txn, &keys)?;
+          txn.commit()?;
+          Ok(result)
+        }
+      };
+      resolve(run());
+    }
+    DatabaseWriterMessage::Put {
+      value,
+      resolve,
+      key,
+    } => {
+      let mut run = || {
+        if let Some(txn) = current_transaction {
+          writer.put(txn, &key, &value)?;
+          Ok(())
+        } else {
+          let mut txn = writer.environment.write_txn()?;
+          writer.put(&mut txn, &key, &value)?;
+          writer.commit(txn, write_count, write_bytes)?;
+          Ok(())
+        }
+      };
+      let result = run();
+      resolve(result);
+    }
+    DatabaseWriterMessage::PutTiny {
+      key,
+      value,
+      resolve,
+    } => {
+      if let Some(txn) = current_transaction {
+        resolve(writer.put_tiny(txn, &key, &value));
+      } else {
+        let mut run = || {
+          if tiny_batch.txn.is_none() {
+            tiny_batch.txn = Some(writer.environment.write_txn()?);
+          }
+          if let Some(txn) = &mut tiny_batch.txn {
+            writer.put_tiny(txn, &key, &value)?;
+          }
+          Ok(())
+        };
+        match run() {
+          Ok(()) => {
+            tiny_batch.resolves.push(resolve);
+            tiny_batch.bytes += write_bytes;
+          }
+          Err(err) => resolve(Err(err)),
+        }
+      }
+    }
+    DatabaseWriterMessage::PutTemp {
+      key,
+      value,
+      resolve,
+    } => {
+      let run = || {
+        if let Some(txn) = current_transaction {
+          writer.put(txn, &key, &value)?;
+          transaction_stats.temp_keys.push(key);
+          Ok(())
+        } else {
+          let mut txn = writer.environment.write_txn()?;
+          writer.put_temp(&mut txn, &key, &value)?;
+          writer.commit(txn, write_count, write_bytes)?;
+          Ok(())
+        }
+      };
+      resolve(run());
+    }
+    DatabaseWriterMessage::PutReserved {
+      key,
+      size,
+      value,
+      resolve,
+    } => {
+      let mut run = || {
+        if let Some(txn) = current_transaction {
+          writer.put_reserved(txn, &key, size, &value)
+        } else {
+          let mut txn = writer.environment.write_txn()?;
+          writer.put_reserved(&mut txn, &key, size, &value)?;
+          writer.commit(txn, write_count, write_bytes)?;
+          Ok(())
+        }
+      };
+      resolve(run());
+    }
+    DatabaseWriterMessage::PutRaw {
+      key,
+      value,
+      resolve,
+    } => {
+      let mut run = || {
+        if let Some(txn) = current_transaction {
+          writer.put_raw(txn, &key, &value)
+        } else {
+          let mut txn = writer.environment.write_txn()?;
+          writer.put_raw(&mut txn, &key, &value)?;
+          writer.commit(txn, write_count, write_bytes)?;
+          Ok(())
+        }
+      };
+      resolve(run());
+    }
+    DatabaseWriterMessage::PutContent {
+      key,
+      value,
+      resolve,
+    } => {
+      let mut run = || {
+        if let Some(txn) = current_transaction {
+          writer.put_content(txn, &key, &value)
+        } else {
+          let mut txn = writer.environment.write_txn()?;
+          writer.put_content(&mut txn, &key, &value)?;
+          writer.commit(txn, write_count, write_bytes)?;
+          Ok(())
+        }
+      };
+      resolve(run());
+    }
+    DatabaseWriterMessage::CopyEntries {
+      source,
+      selection,
+      source_prefix,
+      target_prefix,
+      raw,
+      resolve,
+    } => {
+      let mut write = |entries: Vec<NativeEntry>| -> Result<()> {
+        let write_entries = |txn: &mut RwTxn| {
+          for NativeEntry { key, value } in &entries {
+            let key = key.strip_prefix(&source_prefix).unwrap_or(key);
+            let key = format!("{target_prefix}{key}");
+            if raw {
+              writer.put_raw(txn, &key, value)?;
+            } else {
+              writer.put(txn, &key, &decode_entry(value)?.0)?;
+            }
+          }
+          Ok(())
+        };
+        if let Some(txn) = current_transaction {
+          transaction_stats.operations += entries.len();
+          write_entries(txn)
+        } else {
+          let mut txn = writer.environment.write_txn()?;
+          write_entries(&mut txn)?;
+          writer.commit(txn, entries.len(), 0)?;
+          Ok(())
+        }
+      };
+      let mut run = || {
+        let _scan = matches!(selection, CopySelection::Prefix(_)).then(|| source.sequential_scan());
+        let source_txn = source.read_txn()?;
+        let mut copied = 0;
+        match &selection {
+          CopySelection::Keys(keys) => {
+            for keys in keys.chunks(COPY_BATCH_SIZE) {
+              let mut entries = Vec::with_capacity(keys.len());
+              for key in keys {
+                if let Some(value) = source.get_raw(&source_txn, key)? {
+                  entries.push(NativeEntry {
+                    key: key.clone(),
+                    value,
+                  });
+                }
+              }
+              copied += entries.len();
+              write(entries)?;
+            }
+          }
+          CopySelection::Prefix(prefix) => {
+            let mut after = None;
+            loop {
+              let (entries, next) =
+                source.get_raw_range(&source_txn, prefix, after.as_deref(), COPY_BATCH_SIZE)?;
+              copied += entries.len();
+              write(entries)?;
+              after = next;
+              if after.is_none() {
+                break;
+              }
+            }
+          }
+        }
+        Ok(copied)
+      };
+      resolve(run());
+    }
+    DatabaseWriterMessage::DeleteWhere { filter, resolve } => {
+      let mut run = || {
+        let _scan = writer.sequential_scan();
+        let mut stats = DeleteStats::default();
+        let mut after = None;
+        loop {
+          after = if let Some(txn) = current_transaction {
+            writer.delete_where(
+              txn,
+              &filter,
+              after.as_deref(),
+              DELETE_BATCH_SIZE,
+              &mut stats,
+            )?
+          } else {
+            let deleted = stats.deleted;
+            let mut txn = writer.environment.write_txn()?;
+            let after = writer.delete_where(
+              &mut txn,
+              &filter,
+              after.as_deref(),
+              DELETE_BATCH_SIZE,
+              &mut stats,
+            )?;
+            writer.commit(txn, stats.deleted - deleted, 0)?;
+            after
+          };
+          if after.is_none() {
+            return Ok(stats);
+          }
+        }
+      };
+      resolve(run());
+    }
+    DatabaseWriterMessage::UpdateRefCount {
+      key,
+      delta,
+      resolve,
+    } => {
+      let mut run = || {
+        if let Some(txn) = current_transaction {
+          writer.update_ref_count(txn, &key, delta)
+        } else {
+          let mut txn = writer.environment.write_txn()?;
+          let count = writer.update_ref_count(&mut txn, &key, delta)?;
+          writer.commit(txn, 0, 0)?;
+          Ok(count)
+        }
+      };
+      resolve(run());
+    }
+    DatabaseWriterMessage::SweepUnreferenced { resolve } => {
+      let mut run = || {
+        let mut stats = DeleteStats::default();
+        if let Some(txn) = current_transaction {
+          writer.sweep_unreferenced(txn, &mut stats)?;
+        } else {
+          let mut txn = writer.environment.write_txn()?;
+          writer.sweep_unreferenced(&mut txn, &mut stats)?;
+          writer.commit(txn, stats.deleted, 0)?;
+        }
+        Ok(stats)
+      };
+      resolve(run());
+    }
+    DatabaseWriterMessage::PurgeTombstones {
+      before_txn_id,
+      resolve,
+    } => {
+      let mut run = || {
+        if let Some(txn) = current_transaction {
+          writer.purge_tombstones(txn, before_txn_id)
+        } else {
+          let mut txn = writer.environment.write_txn()?;
+          let purged = writer.purge_tombstones(&mut txn, before_txn_id)?;
+          writer.commit(txn, 0, 0)?;
+          Ok(purged)
+        }
+      };
+      resolve(run());
+    }
+    DatabaseWriterMessage::Benchmark {
+      mode,
+      ops,
+      value_size,
+      resolve,
+    } => {
+      if current_transaction.is_some() {
+        resolve(Err(DatabaseWriterError::TransactionOpen("benchmark")));
+      } else {
+        resolve(writer.benchmark(mode, ops, value_size));
+      }
+    }
+    DatabaseWriterMessage::CopyTo { path, resolve } => {
+      let _scan = writer.sequential_scan();
+      resolve(writer.copy_to(&path));
+    }
+    DatabaseWriterMessage::MoveTo { path, resolve } => {
+      if current_transaction.is_some() {
+        resolve(Err(DatabaseWriterError::TransactionOpen("moveTo")));
+      } else {
+        let _scan = writer.sequential_scan();
+        resolve(writer.copy_to(&path));
+      }
+    }
+    DatabaseWriterMessage::ApplyBatch {
+      entries,
+      marker,
+      resolve,
+    } => {
+      // Each step of the batch must be committed before the next one starts
+      if current_transaction.is_some() {
+        resolve(Err(DatabaseWriterError::TransactionOpen(
+          "coordinatedBatch",
+        )));
+      } else {
+        let run = || {
+          let mut txn = writer.environment.write_txn()?;
+          if writer.mark_batch(&mut txn, &marker)? {
+            for NativeEntry { key, value } in &entries {
+              writer.put(&mut txn, key, value)?;
+            }
+          }
+          writer.commit(txn, write_count, write_bytes)?;
+          Ok(())
+        };
+        resolve(run());
+      }
+    }
+    DatabaseWriterMessage::Stop => {
+      tracing::debug!("Stopping writer thread");
+      return true;
+    }
+    DatabaseWriterMessage::StartTransaction { resolve } => {
+      if current_transaction.is_none() {
+        let mut run = || {
+          *current_transaction = Some(writer.environment.write_txn()?);
+          *transaction_stats = TransactionStats {
+            started_at: Some(clock::now()),
+            ..Default::default()
+          };
+          Ok(())
+        };
+        resolve(run())
+      } else {
+        resolve(Ok(()))
+      }
+    }
+    DatabaseWriterMessage::CommitTransaction { resolve } => {
+      if let Some(txn) = current_transaction.take() {
+        resolve(commit_transaction(
+          writer,
+          txn,
+          std::mem::take(transaction_stats),
+        ))
+      } else {
+        // The transaction may have been committed by
+        // `enforce_transaction_limits`
+        resolve(Ok(()))
+      }
+    }
+    DatabaseWriterMessage::TransactionState { resolve } => {
+      resolve(Ok(TransactionState {
+        active: current_transaction.is_some(),
+        operations: transaction_stats.operations,
+        age: transaction_stats.started_at.map(clock::elapsed),
+      }));
+    }
+    DatabaseWriterMessage::Fence { resolve } => {
+      resolve(Ok(writer.environment.info().last_txn_id as u64));
+    }
+    DatabaseWriterMessage::PutMany {
+      entries,
+      skip_unchanged,
+      resolve,
+    } => {
+      let mut run = || {
+        // Unchanged entries are looked up within the transaction they'd be
+        // written in, before spending any time compressing them
+        let mut txn = None;
+        let entries: Vec<&NativeEntry> = if skip_unchanged {
+          let mut unchanged_txn = begin_write(writer, current_transaction)?;
+          let mut changed = Vec::with_capacity(entries.len());
+          for entry in &entries {
+            if !writer.is_unchanged(unchanged_txn.deref_mut(), &entry.key, &entry.value)? {
+              changed.push(entry);
+            }
+          }
+          txn = Some(unchanged_txn);
+          changed
+        } else {
+          entries.iter().collect()
+        };
+        if entries.is_empty() {
+          if let Some(RwTransaction::Owned(txn)) = txn {
+            txn.abort();
+          }
+          return Ok(());
+        }
+
+        // Oversized values are left to `DatabaseWriter::put` to reject or
+        // chunk, and deduplicated values to only be encoded once
+        let start = clock::now();
+        #[cfg(all(feature = "parallel", not(feature = "small")))]
+        let entries_iter = entries.par_iter();
+        #[cfg(not(all(feature = "parallel", not(feature = "small"))))]
+        let entries_iter = entries.iter();
+        let compressed_entries: Vec<Option<Vec<u8>>> = entries_iter
+          .map(|entry| {
+            let value = writer.transform_value(&entry.value);
+            let len = value.data.len();
+            (!writer.exceeds_max_value_size(len) && !writer.deduplicates(len))
+              .then(|| value.encode())
+          })
+          .collect();
+        writer.record_metrics(|metrics| metrics.compression += clock::elapsed(start));
+
+        let required = entries
+          .iter()
+          .zip(&compressed_entries)
+          .map(|(entry, compressed_value)| {
+            let value_size = compressed_value
+              .as_ref()
+              .map_or(entry.value.len(), Vec::len);
+            (entry.key.len() + value_size) as u64
+          })
+          .sum();
+        writer.ensure_disk_space(required)?;
+
+        let mut txn = match txn {
+          Some(txn) => txn,
+          None => begin_write(writer, current_transaction)?,
+        };
+
+        for (NativeEntry { key, value }, compressed_value) in entries.iter().zip(compressed_entries)
+        {
+          match compressed_value {
+            Some(compressed_value) => {
+              let key = writer.write_key(txn.deref_mut(), key)?;
+              writer.put_stored(txn.deref_mut(), &key, &compressed_value)?;
+            }
+            None => writer.put(txn.deref_mut(), key, value)?,
+          }
+        }
+
+        if let RwTransaction::Owned(txn) = txn {
+          let bytes = if entries.len() == write_count {
+            write_bytes
+          } else {
+            entries
+              .iter()
+              .map(|entry| entry.key.len() + entry.value.len())
+              .sum()
+          };
+          writer.commit(txn, entries.len(), bytes)?;
+        }
+
+        Ok(())
+      };
+      let result = run();
+      resolve(result);
+    }
+  }
+  false
+}
+
+/// The explicit write transaction if one is open, otherwise a new one
+fn begin_write<'t, 'e>(
+  writer: &'e DatabaseWriter,
+  current_transaction: &'t mut Option<RwTxn<'e>>,
+) -> Result<RwTransaction<'t, 'e>> {
+  Ok(match current_transaction {
+    Some(txn) => RwTransaction::Borrowed(txn),
+    None => RwTransaction::Owned(writer.environment.write_txn()?),
+  })
+}
+
+pub type ResolveCallback<T> = Box<dyn FnOnce(Result<T>) + Send>;
+
+/// Called with the error a message failed with, if any, see
+/// [`DatabaseWriterMessage::observe`]
+type OutcomeCallback = Box<dyn FnOnce(Option<String>) + Send>;
+
+/// Call `on_resolve` with the outcome before resolving
+fn observe<T: 'static>(resolve: &mut ResolveCallback<T>, on_resolve: OutcomeCallback) {
+  let inner = std::mem::replace(resolve, Box::new(|_| {}));
+  *resolve = Box::new(move |result| {
+    on_resolve(result.as_ref().err().map(ToString::to_string));
+    inner(result)
+  });
+}
+
+/// Called with the message of a panic caught while resolving, see
+/// [`DatabaseWriterMessage::guard`]
+type PanicCallback = Box<dyn FnOnce(String) + Send>;
+
+/// Call `on_panic` instead of unwinding if resolving panics
+fn guard<T: 'static>(resolve: &mut ResolveCallback<T>, on_panic: PanicCallback) {
+  let inner = std::mem::replace(resolve, Box::new(|_| {}));
+  *resolve = Box::new(move |result| {
+    if let Err(payload) = catch_unwind(AssertUnwindSafe(|| inner(result))) {
+      let message = payload
+        .downcast_ref::<&str>()
+        .map(|message| message.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "unknown panic".to_string());
+      on_panic(message);
+    }
+  });
+}
+
+pub enum DatabaseWriterMessage {
+  Get {
+    key: String,
+    resolve: ResolveCallback<Option<Vec<u8>>>,
+  },
+  GetManyFrom {
+    keys: Vec<DatabaseKey>,
+    resolve: ResolveCallback<Vec<Option<Vec<u8>>>>,
+  },
+  Put {
+    key: String,
+    value: Vec<u8>,
+    resolve: ResolveCallback<()>,
+  },
+  PutMany {
+    entries: Vec<NativeEntry>,
+    /// Skip entries whose key already holds the same value, see
+    /// [`DatabaseWriter::is_unchanged`]
+    skip_unchanged: bool,
+    resolve: ResolveCallback<()>,
+  },
+  /// Write a value of at most [`TINY_VALUE_MAX_SIZE`] bytes without
+  /// compressing it. Outside of an explicit transaction, it's committed along
+  /// with the other tiny writes queued with it, see [`TinyBatch`].
+  PutTiny {
+    key: String,
+    value: Vec<u8>,
+    resolve: ResolveCallback<()>,
+  },
+  /// Write an entry that is deleted again when the explicit transaction it
+  /// was written in commits. Outside of a transaction, it's deleted the next
+  /// time the database is opened after this process has exited.
+  PutTemp {
+    key: String,
+    value: Vec<u8>,
+    resolve: ResolveCallback<()>,
+  },
+  /// Write an uncompressed value of `size` bytes directly into the map
+  PutReserved {
+    key: String,
+    size: usize,
+    value: Vec<u8>,
+    resolve: ResolveCallback<()>,
+  },
+  /// Store a value as returned by [`DatabaseWriter::get_raw`]
+  PutRaw {
+    key: String,
+    value: Vec<u8>,
+    resolve: ResolveCallback<()>,
+  },
+  /// Write a content-addressed value unless its key is already present
+  PutContent {
+    key: String,
+    value: Vec<u8>,
+    resolve: ResolveCallback<()>,
+  },
+  /// Copy entries from another database, renaming keys starting with
+  /// `source_prefix` to start with `target_prefix` instead. Entries are read
+  /// from a single snapshot of the source and written in batches of
+  /// [`COPY_BATCH_SIZE`] unless an explicit transaction is open.
+  CopyEntries {
+    source: Arc<DatabaseWriter>,
+    selection: CopySelection,
+    source_prefix: String,
+    target_prefix: String,
+    /// Copy values as stored instead of decoding them and writing them again
+    raw: bool,
+    resolve: ResolveCallback<usize>,
+  },
+  /// Delete all entries matching a filter, in batches of
+  /// [`DELETE_BATCH_SIZE`] unless an explicit transaction is open
+  DeleteWhere {
+    filter: DeleteFilter,
+    resolve: ResolveCallback<DeleteStats>,
+  },
+  /// Add `delta` to the reference count of an entry
+  UpdateRefCount {
+    key: String,
+    delta: i64,
+    resolve: ResolveCallback<u64>,
+  },
+  /// Delete entries whose reference count was released down to zero
+  SweepUnreferenced {
+    resolve: ResolveCallback<DeleteStats>,
+  },
+  /// Remove tombstones left before `before_txn_id`, or all of them
+  PurgeTombstones {
+    before_txn_id: Option<u64>,
+    resolve: ResolveCallback<usize>,
+  },
+  /// Run [`DatabaseWriter::benchmark`], between other messages
+  Benchmark {
+    mode: BenchmarkMode,
+    ops: usize,
+    value_size: usize,
+    resolve: ResolveCallback<BenchmarkReport>,
+  },
+  /// Copy the committed state of the database into a new directory
+  CopyTo {
+    path: PathBuf,
+    resolve: ResolveCallback<()>,
+  },
+  /// Like [`DatabaseWriterMessage::CopyTo`], but fails if an explicit write
+  /// transaction is open, as its writes would be left out of the copy
+  MoveTo {
+    path: PathBuf,
+    resolve: ResolveCallback<()>,
+  },
+  StartTransaction {
+    resolve: ResolveCallback<()>,
+  },
+  CommitTransaction {
+    resolve: ResolveCallback<()>,
+  },
+  /// Write entries in their own transaction, along with recording or
+  /// clearing the marker of a batch written across two databases, see
+  /// [`crate::batch`]
+  ApplyBatch {
+    entries: Vec<NativeEntry>,
+    marker: BatchMarker,
+    resolve: ResolveCallback<()>,
+  },
+  /// Report on the explicit write transaction, after all previously sent
+  /// messages have been handled
+  TransactionState {
+    resolve: ResolveCallback<TransactionState>,
+  },
+  /// Resolve with the id of the last committed transaction, after all
+  /// previously sent messages have been handled
+  Fence {
+    resolve: ResolveCallback<u64>,
+  },
+  Stop,
+}
+
+impl DatabaseWriterMessage {
+  /// The method that sends this message, and the key it's for if it
+  /// concerns a single key
+  pub fn operation(&self) -> (&'static str, Option<&str>) {
+    match self {
+      DatabaseWriterMessage::Get { key, .. } => ("get", Some(key)),
+      DatabaseWriterMessage::GetManyFrom { .. } => ("getManyFrom", None),
+      DatabaseWriterMessage::Put { key, .. } => ("put", Some(key)),
+      DatabaseWriterMessage::PutMany { .. } => ("putMany", None),
+      DatabaseWriterMessage::PutTiny { key, .. } => ("putTinySync", Some(key)),
+      DatabaseWriterMessage::PutTemp { key, .. } => ("putTemp", Some(key)),
+      DatabaseWriterMessage::PutReserved { key, .. } => ("putReserved", Some(key)),
+      DatabaseWriterMessage::PutRaw { key, .. } => ("putRaw", Some(key)),
+      DatabaseWriterMessage::PutContent { key, .. } => ("putContent", Some(key)),
+      DatabaseWriterMessage::CopyEntries { .. } => ("copyEntries", None),
+      DatabaseWriterMessage::DeleteWhere { .. } => ("deleteWhere", None),
+      DatabaseWriterMessage::UpdateRefCount { key, delta, .. } if *delta > 0 => {
+        ("addRef", Some(key))
+      }
+      DatabaseWriterMessage::UpdateRefCount { key, .. } => ("release", Some(key)),
+      DatabaseWriterMessage::SweepUnreferenced { .. } => ("sweepUnreferenced", None),
+      DatabaseWriterMessage::PurgeTombstones { .. } => ("purgeTombstones", None),
+      DatabaseWriterMessage::Benchmark { .. } => ("benchmark", None),
+      DatabaseWriterMessage::CopyTo { .. } => ("cloneTo", None),
+      DatabaseWriterMessage::MoveTo { .. } => ("moveTo", None),
+      DatabaseWriterMessage::StartTransaction { .. } => ("startWriteTransaction", None),
+      DatabaseWriterMessage::CommitTransaction { .. } => ("commitWriteTransaction", None),
+      DatabaseWriterMessage::ApplyBatch { .. } => ("coordinatedBatch", None),
+      DatabaseWriterMessage::TransactionState { .. } => ("writeTransactionState", None),
+      DatabaseWriterMessage::Fence { .. } => ("fence", None),
+      DatabaseWriterMessage::Stop => ("close", None),
+    }
+  }
+
+  /// Whether [`DatabaseWriter::cancel_pending`] can drop this message
+  fn is_cancellable(&self) -> bool {
+    matches!(
+      self,
+      DatabaseWriterMessage::Put { .. }
+        | DatabaseWriterMessage::PutTemp { .. }
+        | DatabaseWriterMessage::PutReserved { .. }
+        | DatabaseWriterMessage::PutRaw { .. }
+        | DatabaseWriterMessage::PutContent { .. }
+    )
+  }
+
+  /// Call `on_resolve` with the outcome of this message once it's resolved
+  fn observe(&mut self, on_resolve: OutcomeCallback) {
+    match self {
+      DatabaseWriterMessage::Get { resolve, .. } => observe(resolve, on_resolve),
+      DatabaseWriterMessage::GetManyFrom { resolve, .. } => observe(resolve, on_resolve),
+      DatabaseWriterMessage::Put { resolve, .. }
+      | DatabaseWriterMessage::PutMany { resolve, .. }
+      | DatabaseWriterMessage::PutTiny { resolve, .. }
+      | DatabaseWriterMessage::PutTemp { resolve, .. }
+      | DatabaseWriterMessage::PutReserved { resolve, .. }
+      | DatabaseWriterMessage::PutRaw { resolve, .. }
+      | DatabaseWriterMessage::PutContent { resolve, .. }
+      | DatabaseWriterMessage::CopyTo { resolve, .. }
+      | DatabaseWriterMessage::MoveTo { resolve, .. }
+      | DatabaseWriterMessage::ApplyBatch { resolve, .. }
+      | DatabaseWriterMessage::StartTransaction { resolve }
+      | DatabaseWriterMessage::CommitTransaction { resolve } => observe(resolve, on_resolve),
+      DatabaseWriterMessage::CopyEntries { resolve, .. }
+      | DatabaseWriterMessage::PurgeTombstones { resolve, .. } => observe(resolve, on_resolve),
+      DatabaseWriterMessage::DeleteWhere { resolve, .. }
+      | DatabaseWriterMessage::SweepUnreferenced { resolve } => observe(resolve, on_resolve),
+      DatabaseWriterMessage::UpdateRefCount { resolve, .. } => observe(resolve, on_resolve),
+      DatabaseWriterMessage::Benchmark { resolve, .. } => observe(resolve, on_resolve),
+      DatabaseWriterMessage::TransactionState { resolve } => observe(resolve, on_resolve),
+      DatabaseWriterMessage::Fence { resolve } => observe(resolve, on_resolve),
+      DatabaseWriterMessage::Stop => on_resolve(None),
+    }
+  }
+
+  /// Call `on_panic` instead of unwinding if resolving this message panics
+  fn guard(&mut self, on_panic: PanicCallback) {
+    match self {
+      DatabaseWriterMessage::Get { resolve, .. } => guard(resolve, on_panic),
+      DatabaseWriterMessage::GetManyFrom { resolve, .. } => guard(resolve, on_panic),
+      DatabaseWriterMessage::Put { resolve, .. }
+      | DatabaseWriterMessage::PutMany { resolve, .. }
+      | DatabaseWriterMessage::PutTiny { resolve, .. }
+      | DatabaseWriterMessage::PutTemp { resolve, .. }
+      | DatabaseWriterMessage::PutReserved { resolve, .. }
+      | DatabaseWriterMessage::PutRaw { resolve, .. }
+      | DatabaseWriterMessage::PutContent { resolve, .. }
+      | DatabaseWriterMessage::CopyTo { resolve, .. }
+      | DatabaseWriterMessage::MoveTo { resolve, .. }
+      | DatabaseWriterMessage::ApplyBatch { resolve, .. }
+      | DatabaseWriterMessage::StartTransaction { resolve }
+      | DatabaseWriterMessage::CommitTransaction { resolve } => guard(resolve, on_panic),
+      DatabaseWriterMessage::CopyEntries { resolve, .. }
+      | DatabaseWriterMessage::PurgeTombstones { resolve, .. } => guard(resolve, on_panic),
+      DatabaseWriterMessage::DeleteWhere { resolve, .. }
+      | DatabaseWriterMessage::SweepUnreferenced { resolve } => guard(resolve, on_panic),
+      DatabaseWriterMessage::UpdateRefCount { resolve, .. } => guard(resolve, on_panic),
+      DatabaseWriterMessage::Benchmark { resolve, .. } => guard(resolve, on_panic),
+      DatabaseWriterMessage::TransactionState { resolve } => guard(resolve, on_panic),
+      DatabaseWriterMessage::Fence { resolve } => guard(resolve, on_panic),
+      DatabaseWriterMessage::Stop => {}
+    }
+  }
+
+  /// Whether this message writes to or deletes from the database
+  fn is_write(&self) -> bool {
+    matches!(
+      self,
+      DatabaseWriterMessage::Put { .. }
+        | DatabaseWriterMessage::PutMany { .. }
+        | DatabaseWriterMessage::PutTiny { .. }
+        | DatabaseWriterMessage::PutTemp { .. }
+        | DatabaseWriterMessage::PutReserved { .. }
+        | DatabaseWriterMessage::PutRaw { .. }
+        | DatabaseWriterMessage::PutContent { .. }
+        | DatabaseWriterMessage::CopyEntries { .. }
+        | DatabaseWriterMessage::DeleteWhere { .. }
+        | DatabaseWriterMessage::UpdateRefCount { .. }
+        | DatabaseWriterMessage::SweepUnreferenced { .. }
+        | DatabaseWriterMessage::PurgeTombstones { .. }
+    )
+  }
+
+  /// Fail a message dropped by [`DatabaseWriter::cancel_pending`]
+  fn cancel(self) {
+    self.reject(DatabaseWriterError::Cancelled)
+  }
+
+  /// Resolve this message with `err` without handling it
+  pub fn reject(self, err: DatabaseWriterError) {
+    match self {
+      DatabaseWriterMessage::Get { resolve, .. } => resolve(Err(err)),
+      DatabaseWriterMessage::GetManyFrom { resolve, .. } => resolve(Err(err)),
+      DatabaseWriterMessage::Put { resolve, .. }
+      | DatabaseWriterMessage::PutMany { resolve, .. }
+      | DatabaseWriterMessage::PutTiny { resolve, .. }
+      | DatabaseWriterMessage::PutTemp { resolve, .. }
+      | DatabaseWriterMessage::PutReserved { resolve, .. }
+      | DatabaseWriterMessage::PutRaw { resolve, .. }
+      | DatabaseWriterMessage::PutContent { resolve, .. }
+      | DatabaseWriterMessage::CopyTo { resolve, .. }
+      | DatabaseWriterMessage::MoveTo { resolve, .. }
+      | DatabaseWriterMessage::ApplyBatch { resolve, .. }
+      | DatabaseWriterMessage::StartTransaction { resolve }
+      | DatabaseWriterMessage::CommitTransaction { resolve } => resolve(Err(err)),
+      DatabaseWriterMessage::CopyEntries { resolve, .. }
+      | DatabaseWriterMessage::PurgeTombstones { resolve, .. } => resolve(Err(err)),
+      DatabaseWriterMessage::DeleteWhere { resolve, .. }
+      | DatabaseWriterMessage::SweepUnreferenced { resolve } => resolve(Err(err)),
+      DatabaseWriterMessage::UpdateRefCount { resolve, .. } => resolve(Err(err)),
+      DatabaseWriterMessage::Benchmark { resolve, .. } => resolve(Err(err)),
+      DatabaseWriterMessage::TransactionState { resolve } => resolve(Err(err)),
+      DatabaseWriterMessage::Fence { resolve } => resolve(Err(err)),
+      DatabaseWriterMessage::Stop => {}
+    }
+  }
+
+  /// Number of entries this message writes
+  fn write_count(&self) -> usize {
+    match self {
+      DatabaseWriterMessage::Put { .. }
+      | DatabaseWriterMessage::PutTiny { .. }
+      | DatabaseWriterMessage::PutTemp { .. }
+      | DatabaseWriterMessage::PutReserved { .. }
+      | DatabaseWriterMessage::PutRaw { .. }
+      | DatabaseWriterMessage::PutContent { .. } => 1,
+      DatabaseWriterMessage::PutMany { entries, .. }
+      | DatabaseWriterMessage::ApplyBatch { entries, .. } => entries.len(),
+      _ => 0,
+    }
+  }
+
+  /// Number of key and value bytes this message writes, before compression
+  fn write_bytes(&self) -> usize {
+    match self {
+      DatabaseWriterMessage::Put { key, value, .. }
+      | DatabaseWriterMessage::PutTiny { key, value, .. }
+      | DatabaseWriterMessage::PutTemp { key, value, .. }
+      | DatabaseWriterMessage::PutRaw { key, value, .. } => key.len() + value.len(),
+      DatabaseWriterMessage::PutReserved { key, size, .. } => key.len() + size,
+      DatabaseWriterMessage::PutMany { entries, .. }
+      | DatabaseWriterMessage::ApplyBatch { entries, .. } => entries
+        .iter()
+        .map(|entry| entry.key.len() + entry.value.len())
+        .sum(),
+      _ => 0,
+    }
+  }
+}
+
+/// Reported to the [`CommitListener`] after every write transaction commits
+#[derive(Clone, Debug)]
+pub struct CommitStats {
+  /// Number of entries written in the transaction
+  pub operations: usize,
+  /// Number of key and value bytes written, before compression
+  pub bytes: usize,
+  /// How long committing the transaction took
+  pub duration: Duration,
+}
+
+pub type CommitListener = Box<dyn Fn(CommitStats) + Send>;
+
+/// Called with errors that no caller is waiting for, along with the name of
+/// the operation that failed and the error's message, as described by
+/// [`DatabaseWriter::describe_error`]
+pub type ErrorListener = Box<dyn Fn(&'static str, &DatabaseWriterError, &str) + Send>;
+
+/// Time spent in each stage of the write pipeline since the database was
+/// opened, to tell whether writes are bound by compression or by IO
+#[derive(Clone, Debug, Default)]
+pub struct PipelineMetrics {
+  /// Number of messages handled by the writer thread
+  pub messages: u64,
+  /// Number of write transactions committed
+  pub commits: u64,
+  /// Time messages spent queued before the writer thread picked them up
+  pub queue_wait: Duration,
+  /// Time spent transforming and compressing values
+  pub compression: Duration,
+  /// Time spent writing entries into LMDB
+  pub put: Duration,
+  /// Time spent committing write transactions
+  pub commit: Duration,
+}
+
+/// Number of prefixes counted separately by
+/// [`LMDBOptions::access_stats_separator`]. Accesses of any other prefixes
+/// are only counted in [`AccessStats::untracked`].
+const ACCESS_STATS_MAX_PREFIXES: usize = 1000;
+
+const DEFAULT_ACCESS_STATS_SAMPLE_RATE: f64 = 0.01;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct AccessCounts {
+  pub reads: u64,
+  pub writes: u64,
+}
+
+/// Reads and writes per key prefix, see
+/// [`LMDBOptions::access_stats_separator`]
+#[derive(Clone, Debug, Default)]
+pub struct AccessStats {
+  pub prefixes: HashMap<String, AccessCounts>,
+  /// Accesses of prefixes beyond [`ACCESS_STATS_MAX_PREFIXES`]
+  pub untracked: AccessCounts,
+}
+
+/// What [`DatabaseWriter::benchmark`] measures
+#[derive(Debug, PartialEq)]
+#[napi(string_enum = "camelCase")]
+pub enum BenchmarkMode {
+  /// Writes committed one transaction each, which is bound by how fast the
+  /// file system syncs
+  Write,
+  /// Writes in a single transaction, which is bound by compression and LMDB
+  BatchWrite,
+  /// Reads of entries written beforehand
+  Read,
+}
+
+/// Throughput and latency measured by [`DatabaseWriter::benchmark`]
+#[derive(Debug)]
+pub struct BenchmarkReport {
+  pub ops: usize,
+  pub duration: Duration,
+  pub p50: Duration,
+  pub p90: Duration,
+  pub p99: Duration,
+  pub max: Duration,
+}
+
+impl BenchmarkReport {
+  fn new(duration: Duration, mut latencies: Vec<Duration>) -> Self {
+    latencies.sort_unstable();
+    let percentile = |p: f64| {
+      let index = ((latencies.len().saturating_sub(1)) as f64 * p).round() as usize;
+      latencies.get(index).copied().unwrap_or_default()
+    };
+    Self {
+      ops: latencies.len(),
+      duration,
+      p50: percentile(0.5),
+      p90: percentile(0.9),
+      p99: percentile(0.99),
+      max: latencies.last().copied().unwrap_or_default(),
+    }
+  }
+}
+
+/// Which entries [`DatabaseWriterMessage::CopyEntries`] copies, by their full
+/// keys in the source database
+pub enum CopySelection {
+  Keys(Vec<String>),
+  Prefix(String),
+}
+
+/// Which entries [`DatabaseWriterMessage::DeleteWhere`] deletes. Entries must
+/// match every condition that is set.
+#[derive(Default)]
+pub struct DeleteFilter {
+  pub prefix: Option<String>,
+  /// Only delete entries last written by a transaction before this one, see
+  /// [`LMDBOptions::track_write_txn_ids`]
+  pub older_than_txn_id: Option<u64>,
+  /// Only delete entries taking up more than this many bytes on disk
+  pub larger_than_bytes: Option<usize>,
+}
+
+#[derive(Debug, Default)]
+pub struct DeleteStats {
+  /// Number of entries looked at
+  pub scanned: usize,
+  pub deleted: usize,
+  /// Bytes taken up on disk by the deleted entries
+  pub bytes: usize,
+}
+
+/// Entries under a namespace, see [`DatabaseWriter::key_histogram`]
+#[derive(Debug, Default, PartialEq)]
+pub struct NamespaceUsage {
+  pub entries: usize,
+  pub key_bytes: usize,
+  /// Bytes taken up on disk by the values, including their chunks
+  pub value_bytes: usize,
+}
+
+/// An entry deleted while [`LMDBOptions::tombstones`] was enabled
+#[derive(Debug, PartialEq)]
+pub struct Tombstone {
+  pub key: String,
+  /// Id of the transaction that deleted the entry
+  pub txn_id: u64,
+}
+
+/// State of the explicit write transaction held by the writer thread
+pub struct TransactionState {
+  /// Whether a transaction started with
+  /// [`DatabaseWriterMessage::StartTransaction`] is open
+  pub active: bool,
+  /// Number of entries written within the open transaction
+  pub operations: usize,
+  /// Time since the open transaction was started
+  pub age: Option<Duration>,
+}
+
+pub enum RwTransaction<'a, 'b> {
+  Owned(RwTxn<'b>),
+  Borrowed(&'a mut RwTxn<'b>),
+}
+
+impl<'a, 'b> RwTransaction<'a, 'b> {
+  pub fn deref_mut(&mut self) -> &mut RwTxn<'b> {
+    match self {
+      RwTransaction::Borrowed(txn) => txn,
+      RwTransaction::Owned(txn) => txn,
+    }
+  }
+}
+
+/// A read transaction started by [`DatabaseWriter::read_txn`] or
+/// [`DatabaseWriter::static_read_txn`], counted while it's open
+pub struct ReadTxn<'e> {
+  txn: RoTxn<'e>,
+  _reader: OpenReadTxn,
+}
+
+impl<'e> ReadTxn<'e> {
+  pub fn commit(self) -> heed::Result<()> {
+    self.txn.commit()
+  }
+}
+
+impl<'e> std::ops::Deref for ReadTxn<'e> {
+  type Target = RoTxn<'e>;
+
+  fn deref(&self) -> &RoTxn<'e> {
+    &self.txn
+  }
+}
+
+/// Counts a read transaction as open until it's dropped along with it
+struct OpenReadTxn(Arc<AtomicUsize>);
+
+impl Drop for OpenReadTxn {
+  fn drop(&mut self) {
+    self.0.fetch_sub(1, Ordering::SeqCst);
+  }
+}
+
+pub enum Transaction<'a, 'b> {
+  Owned(ReadTxn<'b>),
+  Borrowed(&'a RoTxn<'b>),
+  Shared(Ref<'a, Snapshot>),
+}
+
+impl<'a, 'b> Transaction<'a, 'b> {
+  pub fn deref(&self) -> &RoTxn<'b> {
+    match self {
+      Transaction::Borrowed(txn) => txn,
+      Transaction::Owned(txn) => &txn,
+      Transaction::Shared(snapshot) => &snapshot.txn,
+    }
+  }
+}
+
+/// A read transaction along with the id of the committed transaction it
+/// reads from
+pub struct Snapshot {
+  pub txn: ReadTxn<'static>,
+  pub id: u64,
+  taken_at: Instant,
+}
+
+/// A snapshot of the database shared between reads on the same thread,
+/// replaced with a new one when it's read from more than `interval` after it
+/// was taken
+pub struct ReadReplica {
+  snapshot: RefCell<Option<Snapshot>>,
+  interval: Duration,
+}
+
+impl ReadReplica {
+  pub fn new(interval: Duration) -> Self {
+    Self {
+      snapshot: RefCell::new(None),
+      interval,
+    }
+  }
+
+  /// The current snapshot, refreshed first if it's older than the interval
+  pub fn snapshot(&self, database: &DatabaseWriter) -> Result<Ref<'_, Snapshot>> {
+    let fresh = self
+      .snapshot
+      .borrow()
+      .as_ref()
+      .is_some_and(|snapshot| clock::elapsed(snapshot.taken_at) < self.interval);
+    if !fresh {
+      // Release the old snapshot's reader slot before taking up another
+      *self.snapshot.borrow_mut() = None;
+      let (txn, id) = database.static_read_txn_with_id()?;
+      *self.snapshot.borrow_mut() = Some(Snapshot {
+        txn,
+        id,
+        taken_at: clock::now(),
+      });
+    }
+    Ok(Ref::map(self.snapshot.borrow(), |snapshot| {
+      snapshot.as_ref().expect("snapshot was just taken")
+    }))
+  }
+}
+
+/// A snapshot that can be read from any thread, see
+/// [`crate::LMDB::export_snapshot`]
+///
+/// LMDB doesn't allow a read transaction to be used by more than one thread
+/// at a time, so each thread starts its own with [`SharedSnapshot::begin`].
+pub struct SharedSnapshot {
+  /// Id of the committed transaction the snapshot was taken after
+  id: u64,
+  path: PathBuf,
+}
+
+impl SharedSnapshot {
+  pub fn new(database: &DatabaseWriter) -> Self {
+    Self {
+      id: database.environment.info().last_txn_id as u64,
+      path: database.environment.path().to_path_buf(),
+    }
+  }
+
+  /// Whether the snapshot was taken of `database`
+  pub fn is_of(&self, database: &DatabaseWriter) -> bool {
+    self.path == database.environment.path()
+  }
+
+  /// Start a read transaction on the snapshot. LMDB only starts read
+  /// transactions on the latest snapshot, so this fails with
+  /// [`DatabaseWriterError::SnapshotExpired`] once another transaction was
+  /// committed.
+  pub fn begin(&self, database: &DatabaseWriter) -> Result<Snapshot> {
+    let (txn, id) = database.static_read_txn_with_id()?;
+    if id != self.id {
+      return Err(DatabaseWriterError::SnapshotExpired {
+        id: self.id,
+        latest: id,
+      });
+    }
+    Ok(Snapshot {
+      txn,
+      id,
+      taken_at: clock::now(),
+    })
+  }
+}
+
+pub struct DatabaseWriterStats {
+  /// Number of entries in the main database
+  pub entries: u64,
+  /// Size of the data file on disk in bytes
+  pub disk_size: u64,
+  /// Size of the memory map in bytes
+  pub map_size: u64,
+}
+
+/// A decoded entry along with how it was stored
+pub struct StoredEntry {
+  pub value: Vec<u8>,
+  /// Size of the value as stored in the database, in bytes
+  pub stored_size: usize,
+  pub codec: Codec,
+}
+
+pub struct DatabaseWriter {
+  environment: Env,
+  database: heed::Database<Str, Bytes>,
+  options: LMDBOptions,
+  commit_listener: Mutex<Option<CommitListener>>,
+  error_listener: Mutex<Option<ErrorListener>>,
+  metrics: Mutex<PipelineMetrics>,
+  access_stats: Mutex<AccessStats>,
+  /// Number of reads and writes so far, to count one in every
+  /// [`DatabaseWriter::access_sample_interval`] of them
+  accesses: AtomicU64,
+  pending: Mutex<PendingOps>,
+  recovery: Option<Recovery>,
+  journal: Option<Arc<Journal>>,
+  /// Whether [`TEMP_KEYS_DATABASE`] may have keys in it, so writes only
+  /// look for them there once temporary keys have been written
+  has_temp_keys: AtomicBool,
+  internal: Mutex<InternalDatabases>,
+  /// Id of the last transaction committed when [`DatabaseWriter::internal`]
+  /// was last opened, see [`DatabaseWriter::refresh_internal_databases`]
+  internal_txn_id: AtomicUsize,
+  /// Number of [`ReadTxn`]s open, as the memory map can only be resized
+  /// while there are none
+  open_read_txns: Arc<AtomicUsize>,
+  /// Held to start read transactions, and exclusively to resize the map
+  map_resize: RwLock<()>,
+  /// Number of [`SequentialScan`]s running, the access pattern is restored
+  /// once there are none
+  sequential_scans: Mutex<usize>,
+  /// Whether the writer thread holds a transaction started with
+  /// [`DatabaseWriterMessage::StartTransaction`], as of the last message it
+  /// handled
+  transaction_open: AtomicBool,
+}
+
+/// A transaction discarded when opening the database, see
+/// [`LMDBOptions::verify_on_open`]
+#[derive(Clone, Debug)]
+pub struct Recovery {
+  /// Id of the last transaction, which was corrupted
+  pub discarded_txn_id: usize,
+  /// The error reading it
+  pub reason: String,
+}
+
+impl Drop for DatabaseWriter {
+  /// Close the environment once nothing else uses it, such as static read
+  /// transactions. This is left to the last owner of the writer rather than
+  /// every handle onto it, as heed fails to open the environment again while
+  /// it is closing.
+  fn drop(&mut self) {
+    let _ = self.environment.clone().prepare_for_closing();
+  }
+}
+
+impl DatabaseWriter {
+  pub fn environment(&self) -> &Env {
+    &self.environment
+  }
+
+  /// The options this database was opened with
+  pub fn options(&self) -> &LMDBOptions {
+    &self.options
+  }
+
+  /// The transaction discarded when this database was opened, if its last
+  /// transaction was found to be corrupted
+  pub fn recovery(&self) -> Option<&Recovery> {
+    self.recovery.as_ref()
+  }
+
+  /// Time spent in each stage of the write pipeline so far
+  pub fn pipeline_metrics(&self) -> PipelineMetrics {
+    self
+      .metrics
+      .lock()
+      .unwrap_or_else(PoisonError::into_inner)
+      .clone()
+  }
+
+  fn record_metrics(&self, record: impl FnOnce(&mut PipelineMetrics)) {
+    record(&mut self.metrics.lock().unwrap_or_else(PoisonError::into_inner));
+  }
+
+  /// Reads and writes per key prefix so far, scaled up to estimate the
+  /// accesses that weren't sampled. Empty unless
+  /// [`LMDBOptions::access_stats_separator`] is set.
+  pub fn access_stats(&self) -> AccessStats {
+    let interval = self.access_sample_interval();
+    let scale = |counts: &AccessCounts| AccessCounts {
+      reads: counts.reads * interval,
+      writes: counts.writes * interval,
+    };
+    let stats = self
+      .access_stats
+      .lock()
+      .unwrap_or_else(PoisonError::into_inner);
+    AccessStats {
+      prefixes: stats
+        .prefixes
+        .iter()
+        .map(|(prefix, counts)| (prefix.clone(), scale(counts)))
+        .collect(),
+      untracked: scale(&stats.untracked),
+    }
+  }
+
+  /// One in this many reads and writes is counted by
+  /// [`DatabaseWriter::access_stats`]
+  fn access_sample_interval(&self) -> u64 {
+    let rate = self
+      .options
+      .access_stats_sample_rate
+      .unwrap_or(DEFAULT_ACCESS_STATS_SAMPLE_RATE);
+    (1.0 / rate).round().max(1.0) as u64
+  }
+
+  fn record_access(&self, key: &str, write: bool) {
+    let Some(separator) = self.options.access_stats_separator.as_deref() else {
+      return;
+    };
+    let interval = self.access_sample_interval();
+    if !self
+      .accesses
+      .fetch_add(1, Ordering::Relaxed)
+      .is_multiple_of(interval)
+    {
+      return;
+    }
+    let prefix = match key.find(separator) {
+      Some(index) => &key[..index + separator.len()],
+      None => "",
+    };
+    let mut stats = self
+      .access_stats
+      .lock()
+      .unwrap_or_else(PoisonError::into_inner);
+    let stats = &mut *stats;
+    if !stats.prefixes.contains_key(prefix) && stats.prefixes.len() < ACCESS_STATS_MAX_PREFIXES {
+      stats
+        .prefixes
+        .insert(prefix.to_string(), AccessCounts::default());
+    }
+    let counts = match stats.prefixes.get_mut(prefix) {
+      Some(counts) => counts,
+      None => &mut stats.untracked,
+    };
+    if write {
+      counts.writes += 1;
+    } else {
+      counts.reads += 1;
+    }
+  }
+
+  /// Messages sent to the writer thread that it hasn't picked up yet, in
+  /// the order they were sent, leaving out cancelled ones
+  pub fn pending_ops(&self) -> Vec<PendingOp> {
+    let pending = self.pending.lock().unwrap_or_else(PoisonError::into_inner);
+    pending
+      .ops
+      .values()
+      .filter(|tracked| !tracked.cancelled)
+      .map(|tracked| tracked.op.clone())
+      .collect()
+  }
+
+  /// Drop the writes to `key` that are still queued, returning how many
+  /// were dropped. They fail with [`DatabaseWriterError::Cancelled`] once
+  /// the writer thread gets to them. Only writes to a single key, not
+  /// `putMany`, can be cancelled.
+  pub fn cancel_pending(&self, key: &str) -> usize {
+    let mut pending = self.pending.lock().unwrap_or_else(PoisonError::into_inner);
+    let mut count = 0;
+    for tracked in pending.ops.values_mut() {
+      if tracked.cancellable && !tracked.cancelled && tracked.op.key.as_deref() == Some(key) {
+        tracked.cancelled = true;
+        count += 1;
+      }
+    }
+    count
+  }
+
+  fn track_pending(&self, message: &DatabaseWriterMessage) -> u64 {
+    let (operation, key) = message.operation();
+    let mut pending = self.pending.lock().unwrap_or_else(PoisonError::into_inner);
+    let id = pending.next_id;
+    pending.next_id += 1;
+    pending.ops.insert(
+      id,
+      TrackedOp {
+        op: PendingOp {
+          operation,
+          key: key.map(str::to_string),
+        },
+        cancellable: message.is_cancellable(),
+        cancelled: false,
+      },
+    );
+    id
+  }
+
+  /// Stop tracking a message once the writer thread picks it up, returning
+  /// whether it was cancelled
+  fn untrack_pending(&self, id: u64) -> bool {
+    let mut pending = self.pending.lock().unwrap_or_else(PoisonError::into_inner);
+    pending
+      .ops
+      .remove(&id)
+      .is_some_and(|tracked| tracked.cancelled)
+  }
+
+  /// Tell the kernel how the memory map is about to be read. This is only a
+  /// hint, so failures are logged rather than returned.
+  pub fn advise(&self, pattern: &AccessPattern) {
+    let data_path = self.environment.path().join("data.mdb");
+    if let Err(err) = advise_memory_map(&data_path, pattern) {
+      tracing::debug!("Failed to advise {pattern:?} access to {data_path:?}: {err}");
+    }
+  }
+
+  /// Read the memory map sequentially until the returned guard is dropped,
+  /// or the guards of every scan running at the same time are. This applies
+  /// to the whole map, not only the pages the scan reads.
+  pub fn sequential_scan(&self) -> SequentialScan<'_> {
+    let mut scans = self
+      .sequential_scans
+      .lock()
+      .unwrap_or_else(PoisonError::into_inner);
+    if *scans == 0 {
+      self.advise(&AccessPattern::Sequential);
+    }
+    *scans += 1;
+    SequentialScan(self)
+  }
+
+  /// Set a function to call after every write transaction commits, replacing
+  /// any previous one
+  pub fn set_commit_listener(&self, listener: Option<CommitListener>) {
+    *self
+      .commit_listener
+      .lock()
+      .unwrap_or_else(PoisonError::into_inner) = listener;
+  }
+
+  /// Set a function to call with errors that no caller is waiting for,
+  /// replacing any previous one
+  pub fn set_error_listener(&self, listener: Option<ErrorListener>) {
+    *self
+      .error_listener
+      .lock()
+      .unwrap_or_else(PoisonError::into_inner) = listener;
+  }
+
+  /// Report an error that no caller is waiting for to the error listener, or
+  /// log it if there isn't one
+  pub fn report_error(&self, operation: &'static str, err: &DatabaseWriterError) {
+    let listener = self
+      .error_listener
+      .lock()
+      .unwrap_or_else(PoisonError::into_inner);
+    match listener.as_ref() {
+      Some(listener) => listener(operation, err, &self.describe_error(err)),
+      None => tracing::error!("Failed to {operation}: {}", self.describe_error(err)),
+    }
+  }
+
+  /// A key as it may be logged, hashed if [`LMDBOptions::redact_keys`] is
+  /// enabled
+  pub fn redact_key<'k>(&self, key: &'k str) -> Cow<'k, str> {
+    if self.options.redact_keys == Some(true) {
+      Cow::Owned(format!("#{:016x}", fnv1a(key.as_bytes())))
+    } else {
+      Cow::Borrowed(key)
+    }
+  }
+
+  /// Format an error to be logged, hashing the key it names if
+  /// [`LMDBOptions::redact_keys`] is enabled
+  pub fn describe_error(&self, err: &DatabaseWriterError) -> String {
+    match err {
+      DatabaseWriterError::MissingChunks(key)
+      | DatabaseWriterError::InvalidRawEntry(key)
+      | DatabaseWriterError::MissingDeduplicatedValue(key) => {
+        err.to_string().replace(key.as_str(), &self.redact_key(key))
+      }
+      err => err.to_string(),
+    }
+  }
+
+  /// Commit a write transaction and report it to the commit listener
+  fn commit(&self, txn: RwTxn, operations: usize, bytes: usize) -> Result<()> {
+    let start = clock::now();
+    txn.commit()?;
+    let duration = clock::elapsed(start);
+    self.record_metrics(|metrics| {
+      metrics.commits += 1;
+      metrics.commit += duration;
+    });
+    let listener = self
+      .commit_listener
+      .lock()
+      .unwrap_or_else(PoisonError::into_inner);
+    if let Some(listener) = listener.as_ref() {
+      listener(CommitStats {
+        operations,
+        bytes,
+        duration,
+      });
+    }
+    Ok(())
+  }
+}
+
+impl DatabaseWriter {
+  /// Create a new [`DatabaseWriter`] handle see [`LMDBOptions`] for
+  /// documentation on the settings.
+  pub fn new(options: &LMDBOptions) -> Result<Self> {
+    if options.read_only == Some(true) {
+      return Self::open(options, EnvFlags::READ_ONLY);
+    }
+    let writer = Self::open_verified(options)?;
+    writer.reconcile_stored_options()?;
+    let swept = writer.sweep_temp_keys()?;
+    if swept > 0 {
+      tracing::debug!(
+        "Deleted {swept} temporary keys left by exited processes in {:?}",
+        options.path
+      );
+    }
+    Ok(writer)
+  }
+
+  /// Open the database, falling back to its previous snapshot if
+  /// [`LMDBOptions::verify_on_open`] finds the last transaction corrupted
+  fn open_verified(options: &LMDBOptions) -> Result<Self> {
+    let Some(sample_size) = options.verify_on_open else {
+      return Self::open(options, EnvFlags::empty());
+    };
+    let reason = match Self::open(options, EnvFlags::empty()) {
+      Ok(writer) => match writer.verify(sample_size as usize) {
+        Err(err) if err.is_corruption() => {
+          let closing = writer.environment.clone().prepare_for_closing();
+          drop(writer);
+          closing.wait();
+          err.to_string()
+        }
+        result => return result.map(|_| writer),
+      },
+      // Opening the internal databases reads the main database, so opening
+      // can find the last transaction corrupted as well
+      Err(err) if err.is_corruption() => err.to_string(),
+      Err(err) => return Err(err),
+    };
+
+    let flags = EnvFlags::from_bits_retain(PREV_SNAPSHOT);
+    let mut writer = Self::open(options, flags)?;
+    // The transaction after the previous snapshot, as of before opening
+    // committed anything on top of it
+    let discarded_txn_id = writer.internal_txn_id.load(Ordering::Relaxed) + 1;
+    writer.verify(sample_size as usize)?;
+    // The previous snapshot only replaces the last transaction once
+    // something is committed on top of it
+    let mut txn = writer.environment.write_txn()?;
+    let marker = format!("{INTERNAL_KEY_PREFIX}discarded-txn");
+    writer
+      .database
+      .put(&mut txn, &marker, &(discarded_txn_id as u64).to_le_bytes())?;
+    txn.commit()?;
+    tracing::warn!(
+      "Discarded corrupted transaction {discarded_txn_id} in {:?}: {reason}",
+      options.path
+    );
+    writer.recovery = Some(Recovery {
+      discarded_txn_id,
+      reason,
+    });
+    Ok(writer)
+  }
+
+  /// Read up to `sample_size` entries spread evenly across the database,
+  /// failing if any of them can't be read
+  fn verify(&self, sample_size: usize) -> Result<()> {
+    let txn = self.environment.read_txn()?;
+    let len = self.entry_count(&txn)? as usize;
+    let step = (len / sample_size.max(1)).max(1);
+    for entry in self.entries(&txn)?.step_by(step).take(sample_size) {
+      let (key, stored) = entry?;
+      drop(self.read_stored(&txn, key, stored)?);
+    }
+    Ok(())
+  }
+
+  /// Number of entries, leaving out internal keys such as the names of the
+  /// internal databases
+  fn entry_count(&self, txn: &RoTxn) -> Result<u64> {
+    let internal = self.database.prefix_iter(txn, INTERNAL_KEY_PREFIX)?.count();
+    Ok(self.database.len(txn)? - internal as u64)
+  }
+
+  /// Iterate over the entries in key order, leaving out internal keys and
+  /// tombstoned entries
+  fn entries<'t>(
+    &self,
+    txn: &'t RoTxn,
+  ) -> Result<impl Iterator<Item = heed::Result<(&'t str, &'t [u8])>>> {
+    let entries = self.database.iter(txn)?;
+    Ok(entries.filter(|entry| {
+      !matches!(entry, Ok((key, stored))
+        if key.starts_with(INTERNAL_KEY_PREFIX) || tombstoned_entry(stored).is_some())
+    }))
+  }
+
+  fn open(options: &LMDBOptions, open_flags: EnvFlags) -> Result<Self> {
+    let path = Path::new(&options.path);
+    let read_only = open_flags.contains(EnvFlags::READ_ONLY);
+    if !read_only {
+      std::fs::create_dir_all(path)?;
+      // Left behind by a copy into this directory that didn't finish
+      remove_partial_copy(&path.join(PARTIAL_COPY_FILE))?;
+    }
+    let mut flags = open_flags;
+    flags.set(EnvFlags::MAP_ASYNC, options.async_writes);
+    flags.set(EnvFlags::NO_SYNC, options.async_writes);
+    flags.set(EnvFlags::WRITE_MAP, !read_only);
+    flags.set(EnvFlags::NO_READ_AHEAD, false);
+    flags.set(EnvFlags::NO_META_SYNC, options.async_writes);
+    let mut env_open_options = EnvOpenOptions::new();
+    unsafe { env_open_options.flags(flags) };
+    env_open_options.max_dbs(options.max_dbs.unwrap_or(DEFAULT_MAX_DBS) + InternalDatabases::COUNT);
+    // http://www.lmdb.tech/doc/group__mdb.html#gaa2506ec8dab3d969b0e609cd82e619e5
+    // max DB size that will be memory mapped
+    if let Some(map_size) = options.map_size {
+      env_open_options.map_size(map_size as usize);
+    }
+
+    let mut retries = 0;
+    let environment = loop {
+      match unsafe { env_open_options.open(path) } {
+        Ok(environment) => break environment,
+        Err(err) if retries < OPEN_RETRIES && is_transient_open_error(&err) => {
+          let backoff = OPEN_RETRY_BACKOFF * 2u32.pow(retries);
+          tracing::debug!("Failed to open database, retrying in {backoff:?}: {err}");
+          std::thread::sleep(backoff);
+          retries += 1;
+        }
+        Err(source) => {
+          return Err(DatabaseWriterError::OpenError {
+            path: path.to_path_buf(),
+            source,
+          })
+        }
+      }
+    };
+    if options.preallocate == Some(true) && !read_only {
+      let map_size = environment.info().map_size;
+      let pretouch = options.pretouch == Some(true);
+      let data_path = path.join("data.mdb");
+      // Checked up front, like large writes, rather than failing part-way
+      // through allocating the map
+      let growth = (map_size as u64).saturating_sub(allocated_size(&data_path)?);
+      if let Some(available) = available_disk_space(path)?.filter(|available| *available < growth) {
+        return Err(DatabaseWriterError::InsufficientDiskSpace {
+          required: growth,
+          available,
+        });
+      }
+      preallocate(&data_path, map_size, pretouch)?;
+    }
+    if options.auto_recover_lock.unwrap_or(false) && !read_only {
+      let cleared = environment.clear_stale_readers()?;
+      if cleared > 0 {
+        tracing::warn!("Cleared {cleared} stale readers left by dead processes in {path:?}");
+      }
+    }
+    let internal_txn_id = environment.info().last_txn_id;
+    let opened = if read_only {
+      environment.read_txn().and_then(|txn| {
+        let database = environment.open_database(&txn, None)?;
+        let internal = InternalDatabases::open(&environment, &txn)?;
+        txn.commit()?;
+        let database = database.ok_or(heed::Error::Mdb(heed::MdbError::NotFound))?;
+        Ok((database, internal))
+      })
+    } else {
+      environment.write_txn().and_then(|mut txn| {
+        let database = environment.create_database(&mut txn, None)?;
+        let internal = InternalDatabases::create(&environment, &mut txn)?;
+        txn.commit()?;
+        Ok((database, internal))
+      })
+    };
+    let (database, internal) = match opened {
+      Ok(opened) => opened,
+      Err(err) => {
+        // Otherwise heed keeps it open, failing to open it again with other
+        // flags
+        environment.prepare_for_closing().wait();
+        return Err(err.into());
+      }
+    };
+    let journal = match &options.journal_path {
+      Some(journal_path) => {
+        let max_bytes = options
+          .journal_max_bytes
+          .map_or(DEFAULT_JOURNAL_MAX_BYTES, |max_bytes| max_bytes as u64);
+        Some(Arc::new(Journal::open(Path::new(journal_path), max_bytes)?))
+      }
+      None => None,
+    };
+
+    let writer = Self {
+      database,
+      environment,
+      options: options.clone(),
+      commit_listener: Mutex::new(None),
+      error_listener: Mutex::new(None),
+      metrics: Mutex::default(),
+      access_stats: Mutex::default(),
+      accesses: AtomicU64::new(0),
+      pending: Mutex::default(),
+      recovery: None,
+      journal,
+      has_temp_keys: AtomicBool::new(false),
+      internal: Mutex::new(internal),
+      internal_txn_id: AtomicUsize::new(internal_txn_id),
+      open_read_txns: Arc::default(),
+      map_resize: RwLock::default(),
+      sequential_scans: Mutex::default(),
+      transaction_open: AtomicBool::new(false),
+    };
+    if let Some(pattern) = &options.access_pattern {
+      writer.advise(pattern);
+    }
+    Ok(writer)
+  }
+
+  /// Compare the options that change how entries are stored to the ones the
+  /// database was written with. Reading entries written with other options
+  /// would miss them, so differences fail with
+  /// [`DatabaseWriterError::OptionsMismatch`].
+  ///
+  /// Options other than the defaults are recorded while the database is still
+  /// empty, so options that weren't recorded are the defaults.
+  fn reconcile_stored_options(&self) -> Result<()> {
+    let txn = self.read_txn()?;
+    let stored = self.internal().options;
+    let is_empty = self.entry_count(&txn)? == 0;
+    let defaults = stored_options(&LMDBOptions::default());
+    let mut conflicts = Vec::new();
+    let mut unrecorded = Vec::new();
+    for ((field, requested), (_, default)) in
+      stored_options(&self.options).into_iter().zip(defaults)
+    {
+      let existing = match stored.map(|stored| stored.get(&txn, field)).transpose()? {
+        Some(Some(existing)) => existing.to_string(),
+        _ if requested == default => continue,
+        _ if is_empty => {
+          unrecorded.push((field, requested));
+          continue;
+        }
+        _ => default,
+      };
+      if existing != requested {
+        conflicts.push(OptionConflict {
+          field,
+          existing,
+          requested,
+        });
+      }
+    }
+    txn.commit()?;
+    options_mismatch(&self.options, conflicts)?;
+    if unrecorded.is_empty() {
+      return Ok(());
+    }
+
+    let mut txn = self.environment.write_txn()?;
+    let stored = writable(self.internal().options)?;
+    for (field, value) in unrecorded {
+      stored.put(&mut txn, field, &value)?;
+    }
+    txn.commit()?;
+    Ok(())
+  }
+
+  /// Check that `options` can be used to open this database again while its
+  /// environment is still open. The environment can't change until it's
+  /// closed, so only options that don't affect it may differ. A bigger map
+  /// size is only adopted once the environment is closed and opened again.
+  pub fn check_reopen_options(&self, options: &LMDBOptions) -> Result<()> {
+    let existing = &self.options;
+    let map_size = |options: &LMDBOptions| {
+      options
+        .map_size
+        .map_or("the default".to_string(), |map_size| map_size.to_string())
+    };
+    let max_dbs = |options: &LMDBOptions| options.max_dbs.unwrap_or(DEFAULT_MAX_DBS).to_string();
+    let read_only = |options: &LMDBOptions| (options.read_only == Some(true)).to_string();
+    let mut fields = vec![
+      (
+        "asyncWrites",
+        existing.async_writes.to_string(),
+        options.async_writes.to_string(),
+      ),
+      ("mapSize", map_size(existing), map_size(options)),
+      ("maxDbs", max_dbs(existing), max_dbs(options)),
+      ("readOnly", read_only(existing), read_only(options)),
+    ];
+    for ((field, existing), (_, requested)) in stored_options(existing)
+      .into_iter()
+      .zip(stored_options(options))
+    {
+      fields.push((field, existing, requested));
+    }
+    let conflicts = fields
+      .into_iter()
+      .filter(|(_, existing, requested)| existing != requested)
+      .map(|(field, existing, requested)| OptionConflict {
+        field,
+        existing,
+        requested,
+      })
+      .collect();
+    options_mismatch(options, conflicts)
+  }
+
+  /// Compress an entry and store it
+  pub fn get(&self, txn: &RoTxn, key: &str) -> Result<Option<Vec<u8>>> {
+    Ok(self.get_entry(txn, key)?.map(|entry| entry.value))
+  }
+
+  /// Read an entry along with information about how it is stored
+  pub fn get_entry(&self, txn: &RoTxn, key: &str) -> Result<Option<StoredEntry>> {
+    self.record_access(key, false);
+    let Some((key, stored)) = self.get_stored(txn, key)? else {
+      return Ok(None);
+    };
+    self.read_stored(txn, &key, stored).map(Some)
+  }
+
+  /// Decode the bytes stored under `storage_key`, reassembling chunked
+  /// entries and looking up deduplicated ones
+  fn read_stored(&self, txn: &RoTxn, storage_key: &str, stored: &[u8]) -> Result<StoredEntry> {
+    if let Some(count) = chunk_count(stored) {
+      return self.get_chunked(txn, storage_key, count, stored.len());
+    }
+    let encoded = match dedup_hash(stored) {
+      Some(hash) => self.get_deduplicated(txn, storage_key, &hash)?,
+      None => stored,
+    };
+    let (value, codec) = decode_entry(encoded)?;
+    Ok(StoredEntry {
+      value,
+      stored_size: encoded.len(),
+      codec,
+    })
+  }
+
+  /// Read an entry as it is stored, without decompressing it. Chunked
+  /// entries are reassembled and returned uncompressed.
+  ///
+  /// The result can be written into any database with
+  /// [`DatabaseWriter::put_raw`].
+  pub fn get_raw(&self, txn: &RoTxn, key: &str) -> Result<Option<Vec<u8>>> {
+    self.record_access(key, false);
+    let Some((key, stored)) = self.get_stored(txn, key)? else {
+      return Ok(None);
+    };
+    self.raw_value(txn, &key, stored).map(Some)
+  }
+
+  /// Read up to `limit` entries with keys starting with `prefix` and after
+  /// `after`, as returned by [`DatabaseWriter::get_raw`].
+  ///
+  /// Also returns the last key looked at, to continue from in another call,
+  /// or `None` once there is nothing left to read.
+  pub fn get_raw_range(
+    &self,
+    txn: &RoTxn,
+    prefix: &str,
+    after: Option<&str>,
+    limit: usize,
+  ) -> Result<(Vec<NativeEntry>, Option<String>)> {
+    let start = match after {
+      Some(after) => Bound::Excluded(after),
+      None if prefix.is_empty() => Bound::Unbounded,
+      None => Bound::Included(prefix),
+    };
+
+    let mut entries = Vec::new();
+    let mut last_key = None;
+    for entry in self.database.range(txn, &(start, Bound::Unbounded))? {
+      let (key, stored) = entry?;
+      if !key.starts_with(prefix) {
+        return Ok((entries, None));
+      }
+      if entries.len() == limit {
+        return Ok((entries, last_key));
+      }
+      last_key = Some(key.to_string());
+      if key.starts_with(INTERNAL_KEY_PREFIX) || tombstoned_entry(stored).is_some() {
+        continue;
+      }
+      let value = self.raw_value(txn, key, stored)?;
+      entries.push(NativeEntry {
+        key: self.original_key(txn, key)?,
+        value,
+      });
+    }
+    Ok((entries, None))
+  }
+
+  /// Read up to `count` distinct entries under `prefix`, picked by jumping
+  /// the cursor to pseudo-random keys rather than scanning the database.
+  ///
+  /// Each jump walks down the key space, picking one of the bytes keys have
+  /// wherever they branch off, so keys with fewer siblings are sampled more
+  /// often than keys in large namespaces.
+  /// Entries are returned the way [`DatabaseWriter::get_raw`] returns them,
+  /// in key order.
+  pub fn sample(
+    &self,
+    txn: &RoTxn,
+    prefix: &str,
+    count: usize,
+    seed: u64,
+  ) -> Result<Vec<NativeEntry>> {
+    let database = self.database.remap_key_type::<Bytes>();
+    let mut rng = SampleRng(seed | 1);
+    let mut keys = BTreeMap::new();
+    for _ in 0..count * SAMPLE_ATTEMPTS_PER_ENTRY {
+      if keys.len() == count {
+        break;
+      }
+      let mut current = prefix.as_bytes().to_vec();
+      let key = loop {
+        // LMDB doesn't accept empty keys to seek to
+        let bounds = if current.is_empty() {
+          (database.first(txn)?, database.last(txn)?)
+        } else {
+          (
+            database.prefix_iter(txn, &current)?.next().transpose()?,
+            database
+              .rev_prefix_iter(txn, &current)?
+              .next()
+              .transpose()?,
+          )
+        };
+        let (Some(low), Some(high)) = bounds else {
+          break None;
+        };
+        let (low, high) = (low.0, high.0);
+        if low == high {
+          break Some(low);
+        }
+        // Skip ahead to where the keys left under `current` branch off
+        let position = low.iter().zip(high).take_while(|(a, b)| a == b).count();
+        current = low[..position].to_vec();
+
+        // Jump through the bytes keys have at this position. `low` ends here
+        // if it's `current` itself, in which case it can be picked too.
+        let mut choices = Vec::new();
+        let mut next = low;
+        if low.len() == position {
+          choices.push(None);
+          let Some((key, _)) = database.get_greater_than(txn, low)? else {
+            break None;
+          };
+          next = key;
+        }
+        loop {
+          let byte = next[position];
+          choices.push(Some(byte));
+          if byte == high[position] {
+            break;
+          }
+          current.push(byte + 1);
+          let Some((key, _)) = database.get_greater_than_or_equal_to(txn, &current)? else {
+            break;
+          };
+          current.pop();
+          next = key;
+        }
+        match choices[rng.below(choices.len())] {
+          Some(byte) => current.push(byte),
+          None => break Some(low),
+        }
+      };
+      let Some(key) = key.and_then(|key| std::str::from_utf8(key).ok()) else {
+        continue;
+      };
+      if key.starts_with(INTERNAL_KEY_PREFIX) || keys.contains_key(key) {
+        continue;
+      }
+      if let Some(stored) = self.database.get(txn, key)? {
+        if tombstoned_entry(stored).is_none() {
+          keys.insert(key.to_string(), self.raw_value(txn, key, stored)?);
+        }
+      }
+    }
+
+    keys
+      .into_iter()
+      .map(|(key, value)| {
+        Ok(NativeEntry {
+          key: self.original_key(txn, &key)?,
+          value,
+        })
+      })
+      .collect()
+  }
+
+  /// Encode a stored entry the way [`DatabaseWriter::get_raw`] returns it
+  fn raw_value(&self, txn: &RoTxn, storage_key: &str, stored: &[u8]) -> Result<Vec<u8>> {
+    if let Some(hash) = dedup_hash(stored) {
+      return Ok(self.get_deduplicated(txn, storage_key, &hash)?.to_vec());
+    }
+    let Some(count) = chunk_count(stored) else {
+      return Ok(stored.to_vec());
+    };
+    let entry = self.get_chunked(txn, storage_key, count, stored.len())?;
+    Ok([&RAW_ENTRY_MARKER[..], &entry.value].concat())
+  }
+
+  /// Map a key as stored back to the key it was written with, which only
+  /// differs for keys hashed by [`LMDBOptions::hash_long_keys`]
+  fn original_key(&self, txn: &RoTxn, storage_key: &str) -> Result<String> {
+    if self.options.hash_long_keys != Some(true) {
+      return Ok(storage_key.to_string());
+    }
+    let Some(long_keys) = self.internal().long_keys else {
+      return Ok(storage_key.to_string());
+    };
+    let key = long_keys.get(txn, storage_key)?.unwrap_or(storage_key);
+    Ok(key.to_string())
+  }
+
+  /// Look up the stored bytes for a key, along with the key they are stored
+  /// under. Tombstoned entries are treated as missing.
+  fn get_stored<'t, 'k>(
+    &self,
+    txn: &'t RoTxn,
+    key: &'k str,
+  ) -> Result<Option<(Cow<'k, str>, &'t [u8])>> {
+    let storage_key = self.storage_key(key)?;
+    if self.is_long_key(key) && !self.long_key_matches(txn, &storage_key, key)? {
+      return Ok(None);
+    }
+    let stored = self.database.get(txn, &storage_key)?;
+    Ok(
+      stored
+        .filter(|stored| tombstoned_entry(stored).is_none())
+        .map(|stored| (storage_key, stored)),
+    )
+  }
+
+  /// Read entries out of one or more named databases using a single
+  /// transaction, so all values come from the same snapshot.
+  ///
+  /// Databases that don't exist are treated as empty. The named databases
+  /// used internally are rejected with
+  /// [`DatabaseWriterError::InternalDatabase`], their entries aren't stored
+  /// in the format this decodes.
+  pub fn get_many_from(&self, txn: &RoTxn, keys: &[DatabaseKey]) -> Result<Vec<Option<Vec<u8>>>> {
+    if let Some(DatabaseKey { db, .. }) = keys
+      .iter()
+      .find(|DatabaseKey { db, .. }| db.starts_with(INTERNAL_KEY_PREFIX))
+    {
+      return Err(DatabaseWriterError::InternalDatabase(db.clone()));
+    }
+    let mut databases: HashMap<&str, Option<heed::Database<Str, Bytes>>> = HashMap::new();
+    let mut results = Vec::with_capacity(keys.len());
+    for DatabaseKey { db, key } in keys {
+      let database = match databases.get(db.as_str()) {
+        Some(database) => *database,
+        None => {
+          let database = self.environment.open_database(txn, Some(db))?;
+          databases.insert(db, database);
+          database
+        }
+      };
+      let Some(database) = database else {
+        results.push(None);
+        continue;
+      };
+      let value = database
+        .get(txn, key)?
+        .map(decode_entry)
+        .transpose()?
+        .map(|(value, _)| value);
+      results.push(value);
+    }
+    Ok(results)
+  }
+
+  /// Read an entry and decompress it
+  pub fn put(&self, txn: &mut RwTxn, key: &str, data: &[u8]) -> Result<()> {
+    let key = self.write_key(txn, key)?;
+    let start = clock::now();
+    let value = self.transform_value(data);
+    self.check_value_size(&key, value.data.len())?;
+    if self.exceeds_max_value_size(value.data.len()) {
+      return self.put_chunked(txn, &key, &value.data);
+    }
+    if self.deduplicates(value.data.len()) {
+      return self.put_deduplicated(txn, &key, &value);
+    }
+    let stored = value.encode();
+    self.record_metrics(|metrics| metrics.compression += clock::elapsed(start));
+    self.put_stored(txn, &key, &stored)
+  }
+
+  /// Store a value of at most [`TINY_VALUE_MAX_SIZE`] bytes as it is, as
+  /// compressing it wouldn't save any space
+  pub fn put_tiny(&self, txn: &mut RwTxn, key: &str, value: &[u8]) -> Result<()> {
+    if value.len() > TINY_VALUE_MAX_SIZE {
+      return Err(DatabaseWriterError::ValueTooLarge {
+        len: value.len(),
+        max: TINY_VALUE_MAX_SIZE,
+      });
+    }
+    let key = self.write_key(txn, key)?;
+    self.put_stored(txn, &key, &encode_entry(Codec::None, value))
+  }
+
+  /// Write a value stored under [`content_key`]. As the key is derived from
+  /// the value, an entry that is already present is left as is.
+  pub fn put_content(&self, txn: &mut RwTxn, key: &str, value: &[u8]) -> Result<()> {
+    if self.get_stored(txn, key)?.is_some() {
+      return Ok(());
+    }
+    self.put(txn, key, value)
+  }
+
+  /// Store a value previously read with [`DatabaseWriter::get_raw`] without
+  /// compressing it again. Values decoding to more than
+  /// [`LMDBOptions::max_value_size`] go through the usual size limit
+  /// handling.
+  ///
+  /// The value is decoded before it's stored, so corrupt values are rejected
+  /// rather than failing when read.
+  pub fn put_raw(&self, txn: &mut RwTxn, key: &str, stored: &[u8]) -> Result<()> {
+    check_raw_entry(key, stored)?;
+    let size = raw_entry_size(stored).unwrap_or_default();
+    // Rejected from the header, without decoding a value that won't be stored
+    self.check_value_size(&self.storage_key(key)?, size)?;
+    let (value, _) =
+      decode_entry(stored).map_err(|_| DatabaseWriterError::InvalidRawEntry(key.to_string()))?;
+    let key = self.write_key(txn, key)?;
+    self.check_value_size(&key, value.len())?;
+    if self.exceeds_max_value_size(value.len()) {
+      return self.put_chunked(txn, &key, &value);
+    }
+    self.put_stored(txn, &key, stored)
+  }
+
+  /// Apply [`LMDBOptions::value_transforms`] to a value
+  fn transform_value<'v>(&self, data: &'v [u8]) -> TransformedValue<'v> {
+    let mut value = TransformedValue {
+      data: Cow::Borrowed(data),
+      compress: true,
+    };
+    for transform in self.options.value_transforms.iter().flatten() {
+      match transform {
+        ValueTransform::CompressedPassthrough => {
+          value.compress = value.compress && !is_compressed(&value.data);
+        }
+        ValueTransform::MinifyJson => {
+          if let Some(minified) = minify_json(&value.data) {
+            value.data = Cow::Owned(minified);
+          }
+        }
+      }
+    }
+    value
+  }
+
+  /// Store an encoded entry under a key returned by
+  /// [`DatabaseWriter::storage_key`], replacing any previous value
+  fn put_stored(&self, txn: &mut RwTxn, key: &str, stored: &[u8]) -> Result<()> {
+    let start = clock::now();
+    self.release_value(txn, key)?;
+    self.database.put(txn, key, stored)?;
+    self.record_metrics(|metrics| metrics.put += clock::elapsed(start));
+    Ok(())
+  }
+
+  /// Map a key to the key it is stored under, applying
+  /// [`LMDBOptions::hash_long_keys`] and [`LMDBOptions::max_key_length`]
+  fn storage_key<'k>(&self, key: &'k str) -> Result<Cow<'k, str>> {
+    if self.is_long_key(key) {
+      return Ok(Cow::Owned(hash_key(key, self.environment.max_key_size())));
+    }
+    let Some(max) = self.options.max_key_length.map(|max| max as usize) else {
+      return Ok(Cow::Borrowed(key));
+    };
+    if key.len() <= max {
+      return Ok(Cow::Borrowed(key));
+    }
+    if self.options.size_limit_policy != Some(SizeLimitPolicy::TruncateKeyHash) {
+      return Err(DatabaseWriterError::KeyTooLong {
+        len: key.len(),
+        max,
+      });
+    }
+    Ok(Cow::Owned(hash_key(key, max)))
+  }
+
+  /// Map a key to the key it is stored under, recording the original key for
+  /// keys hashed by [`LMDBOptions::hash_long_keys`]
+  fn write_key<'k>(&self, txn: &mut RwTxn, key: &'k str) -> Result<Cow<'k, str>> {
+    self.record_access(key, true);
+    let storage_key = self.storage_key(key)?;
+    if self.is_long_key(key) {
+      let long_keys = writable(self.internal().long_keys)?;
+      long_keys.put(txn, &storage_key, key)?;
+    }
+    if self.has_temp_keys.load(Ordering::Relaxed) {
+      // Written again as a regular entry, which should be kept
+      if let Some(temp_keys) = self.internal().temp_keys {
+        temp_keys.delete(txn, &storage_key)?;
+      }
+    }
+    if self.options.tombstones == Some(true) {
+      if let Some(tombstones) = self.internal().tombstones {
+        tombstones.delete(txn, &storage_key)?;
+      }
+    }
+    if self.options.track_write_txn_ids == Some(true) {
+      let write_txn_ids = writable(self.internal().write_txn_ids)?;
+      // The id this transaction will have once committed
+      let txn_id = self.environment.info().last_txn_id as u64 + 1;
+      write_txn_ids.put(txn, &storage_key, &txn_id.to_le_bytes())?;
+    }
+    Ok(storage_key)
+  }
+
+  /// Whether `key` is hashed by [`LMDBOptions::hash_long_keys`]
+  fn is_long_key(&self, key: &str) -> bool {
+    self.options.hash_long_keys == Some(true) && key.len() > self.environment.max_key_size()
+  }
+
+  /// Check that a hashed key was last written for `key` rather than for
+  /// another key with the same hash
+  fn long_key_matches(&self, txn: &RoTxn, storage_key: &str, key: &str) -> Result<bool> {
+    let Some(long_keys) = self.internal().long_keys else {
+      return Ok(false);
+    };
+    Ok(long_keys.get(txn, storage_key)? == Some(key))
+  }
+
+  /// Whether values of `len` bytes are stored once, see
+  /// [`LMDBOptions::dedup_min_size`]
+  fn deduplicates(&self, len: usize) -> bool {
+    self
+      .options
+      .dedup_min_size
+      .is_some_and(|min| len >= min as usize)
+  }
+
+  /// Store a value once under its hash, referring to it from `key`. The
+  /// value is only encoded if no other key refers to it yet.
+  fn put_deduplicated(&self, txn: &mut RwTxn, key: &str, value: &TransformedValue) -> Result<()> {
+    let hash = XxHash3_128::oneshot(&value.data).to_le_bytes();
+    let values = writable(self.internal().dedup_values)?;
+    let refs = writable(self.internal().dedup_refs)?;
+    let count = refs
+      .get(txn, &hash)?
+      .and_then(|count| count.try_into().ok())
+      .map_or(0, u64::from_le_bytes);
+    if count == 0 {
+      let start = clock::now();
+      let stored = value.encode();
+      self.record_metrics(|metrics| metrics.compression += clock::elapsed(start));
+      values.put(txn, &hash, &stored)?;
+    }
+    // Referenced before the previous value of `key` is released, in case it
+    // is the same value
+    refs.put(txn, &hash, &(count + 1).to_le_bytes())?;
+    self.put_stored(txn, key, &[&DEDUP_ENTRY_MARKER[..], &hash].concat())
+  }
+
+  /// Read the encoded value a deduplicated entry refers to
+  fn get_deduplicated<'t>(
+    &self,
+    txn: &'t RoTxn,
+    storage_key: &str,
+    hash: &[u8; 16],
+  ) -> Result<&'t [u8]> {
+    let missing_value = || DatabaseWriterError::MissingDeduplicatedValue(storage_key.to_string());
+    let values = self.internal().dedup_values.ok_or_else(missing_value)?;
+    values.get(txn, hash)?.ok_or_else(missing_value)
+  }
+
+  /// Drop a key's reference to a deduplicated value, deleting the value once
+  /// no key refers to it
+  fn release_deduplicated(&self, txn: &mut RwTxn, hash: &[u8; 16]) -> Result<()> {
+    let Some(refs) = self.internal().dedup_refs else {
+      return Ok(());
+    };
+    let count = refs
+      .get(txn, hash)?
+      .and_then(|count| count.try_into().ok())
+      .map_or(0, u64::from_le_bytes);
+    if count > 1 {
+      refs.put(txn, hash, &(count - 1).to_le_bytes())?;
+      return Ok(());
+    }
+    refs.delete(txn, hash)?;
+    if let Some(values) = self.internal().dedup_values {
+      values.delete(txn, hash)?;
+    }
+    Ok(())
+  }
+
+  fn exceeds_max_value_size(&self, len: usize) -> bool {
+    self
+      .options
+      .max_value_size
+      .is_some_and(|max| len as f64 > max)
+  }
+
+  /// Check that a value of `len` bytes, once transformed, can be stored
+  /// under `storage_key`. Values over [`LMDBOptions::max_value_size`] are
+  /// rejected unless [`SizeLimitPolicy::ChunkValue`] splits them up, in which
+  /// case the key has to leave room for the chunk suffix.
+  fn check_value_size(&self, storage_key: &str, len: usize) -> Result<()> {
+    if !self.exceeds_max_value_size(len) {
+      return Ok(());
+    }
+    if self.options.size_limit_policy != Some(SizeLimitPolicy::ChunkValue) {
+      return Err(DatabaseWriterError::ValueTooLarge {
+        len,
+        max: self.options.max_value_size.unwrap_or_default() as usize,
+      });
+    }
+    let max_key_size = self.environment.max_key_size() - CHUNK_KEY_SUFFIX_LEN;
+    if storage_key.len() > max_key_size {
+      return Err(DatabaseWriterError::KeyTooLong {
+        len: storage_key.len(),
+        max: max_key_size,
+      });
+    }
+    Ok(())
+  }
+
+  /// Check that [`DatabaseWriter::put`] would accept `key` and `data`, without
+  /// writing anything
+  pub fn check_put(&self, key: &str, data: &[u8]) -> Result<()> {
+    let storage_key = self.storage_key(key)?;
+    self.check_value_size(&storage_key, self.transform_value(data).data.len())
+  }
+
+  /// Reject writes made outside of an explicit transaction if
+  /// [`LMDBOptions::require_explicit_transactions`] is enabled
+  pub fn check_transaction(&self, msg: &DatabaseWriterMessage, in_transaction: bool) -> Result<()> {
+    if !in_transaction && msg.is_write() && self.options.require_explicit_transactions == Some(true)
+    {
+      return Err(DatabaseWriterError::TransactionRequired(msg.operation().0));
+    }
+    Ok(())
+  }
+
+  /// Whether the writer thread holds a transaction started with
+  /// [`DatabaseWriterMessage::StartTransaction`], as of the last message it
+  /// handled
+  pub fn is_transaction_open(&self) -> bool {
+    self.transaction_open.load(Ordering::Relaxed)
+  }
+
+  /// Store a value over [`LMDBOptions::max_value_size`] split into chunks,
+  /// once [`DatabaseWriter::check_value_size`] accepted it
+  fn put_chunked(&self, txn: &mut RwTxn, key: &str, data: &[u8]) -> Result<()> {
+    let max = self.options.max_value_size.unwrap_or_default() as usize;
+    self.release_value(txn, key)?;
+    let chunks = writable(self.internal().chunks)?;
+    let mut count: u32 = 0;
+    for chunk in data.chunks(max.max(1)) {
+      chunks.put(txn, &chunk_key(key, count), &encode_compressed(chunk))?;
+      count += 1;
+    }
+    let mut stored = CHUNKED_ENTRY_MARKER.to_vec();
+    stored.extend_from_slice(&count.to_le_bytes());
+    self.database.put(txn, key, &stored)?;
+    Ok(())
+  }
+
+  fn get_chunked(
+    &self,
+    txn: &RoTxn,
+    key: &str,
+    count: u32,
+    stored_size: usize,
+  ) -> Result<StoredEntry> {
+    let missing_chunks = || DatabaseWriterError::MissingChunks(key.to_string());
+    let chunks = self.internal().chunks.ok_or_else(missing_chunks)?;
+    let mut entry = StoredEntry {
+      value: Vec::new(),
+      stored_size,
+      codec: Codec::Lz4,
+    };
+    for index in 0..count {
+      let chunk = chunks
+        .get(txn, &chunk_key(key, index))?
+        .ok_or_else(missing_chunks)?;
+      entry.stored_size += chunk.len();
+      entry.value.extend(decode_entry(chunk)?.0);
+    }
+    Ok(entry)
+  }
+
+  /// Delete entries matching `filter`, looking at no more than `limit`
+  /// entries with keys after `after`.
+  ///
+  /// Returns the last key looked at, to continue from in another call, or
+  /// `None` once there is nothing left to look at.
+  pub fn delete_where(
+    &self,
+    txn: &mut RwTxn,
+    filter: &DeleteFilter,
+    after: Option<&str>,
+    limit: usize,
+    stats: &mut DeleteStats,
+  ) -> Result<Option<String>> {
+    let prefix = filter.prefix.as_deref().unwrap_or_default();
+    let start = match after {
+      Some(after) => Bound::Excluded(after),
+      // LMDB rejects empty keys, so an empty prefix can't be used as a bound
+      None if prefix.is_empty() => Bound::Unbounded,
+      None => Bound::Included(prefix),
+    };
+
+    let mut matches = Vec::new();
+    let mut last_key = None;
+    let mut scanned = 0;
+    for entry in self.database.range(txn, &(start, Bound::Unbounded))? {
+      let (key, stored) = entry?;
+      if !key.starts_with(prefix) {
+        last_key = None;
+        break;
+      }
+      if scanned == limit {
+        break;
+      }
+      scanned += 1;
+      last_key = Some(key.to_string());
+      if key.starts_with(INTERNAL_KEY_PREFIX) || tombstoned_entry(stored).is_some() {
+        continue;
+      }
+      if let Some(before) = filter.older_than_txn_id {
+        if self.write_txn_id(txn, key)?.unwrap_or(0) >= before {
+          continue;
+        }
+      }
+      let size = self.stored_size(txn, key, stored)?;
+      if filter.larger_than_bytes.is_some_and(|min| size <= min) {
+        continue;
+      }
+      matches.push((key.to_string(), size));
+    }
+    if scanned < limit {
+      last_key = None;
+    }
+    stats.scanned += scanned;
+
+    for (key, size) in matches {
+      if !self.tombstone_entry(txn, &key)? {
+        self.remove_entry(txn, &key)?;
+      }
+      stats.deleted += 1;
+      stats.bytes += size;
+    }
+    Ok(last_key)
+  }
+
+  /// Measure `ops` operations on values of `value_size` bytes against this
+  /// environment, using its options. The entries are written under an
+  /// internal prefix and deleted afterwards.
+  ///
+  /// Must not be called while a write transaction is open on this thread.
+  pub fn benchmark(
+    &self,
+    mode: BenchmarkMode,
+    ops: usize,
+    value_size: usize,
+  ) -> Result<BenchmarkReport> {
+    let key = |index: usize| format!("{INTERNAL_KEY_PREFIX}benchmark/{index:08}");
+    // Values that don't compress, as for most cache entries
+    let mut state = 0x9e3779b97f4a7c15u64;
+    let value: Vec<u8> = (0..value_size)
+      .map(|_| {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        state as u8
+      })
+      .collect();
+
+    let mut latencies = Vec::with_capacity(ops);
+    let start = Instant::now();
+    match mode {
+      BenchmarkMode::Write => {
+        for index in 0..ops {
+          let op_start = Instant::now();
+          let mut txn = self.environment.write_txn()?;
+          self.put(&mut txn, &key(index), &value)?;
+          txn.commit()?;
+          latencies.push(op_start.elapsed());
+        }
+      }
+      BenchmarkMode::BatchWrite => {
+        let mut txn = self.environment.write_txn()?;
+        for index in 0..ops {
+          let op_start = Instant::now();
+          self.put(&mut txn, &key(index), &value)?;
+          latencies.push(op_start.elapsed());
+        }
+        txn.commit()?;
+      }
+      BenchmarkMode::Read => {
+        let mut txn = self.environment.write_txn()?;
+        for index in 0..ops {
+          self.put(&mut txn, &key(index), &value)?;
+        }
+        txn.commit()?;
+        let start = Instant::now();
+        let txn = self.environment.read_txn()?;
+        for index in 0..ops {
+          let op_start = Instant::now();
+          self.get(&txn, &key(index))?;
+          latencies.push(op_start.elapsed());
+        }
+        let duration = start.elapsed();
+        drop(txn);
+        self.remove_benchmark_entries(ops, key)?;
+        return Ok(BenchmarkReport::new(duration, latencies));
+      }
+    }
+    let duration = start.elapsed();
+    self.remove_benchmark_entries(ops, key)?;
+    Ok(BenchmarkReport::new(duration, latencies))
+  }
+
+  fn remove_benchmark_entries(&self, ops: usize, key: impl Fn(usize) -> String) -> Result<()> {
+    let mut txn = self.environment.write_txn()?;
+    for index in 0..ops {
+      self.remove_entry(&mut txn, &key(index))?;
+    }
+    txn.commit()?;
+    Ok(())
+  }
+
+  /// Add `delta` to the reference count of `key`, saturating at zero, and
+  /// return the new count. Counts are kept apart from the entries, which
+  /// don't need to exist.
+  pub fn update_ref_count(&self, txn: &mut RwTxn, key: &str, delta: i64) -> Result<u64> {
+    let storage_key = self.storage_key(key)?;
+    let refs = writable(self.internal().refs)?;
+    let count = refs
+      .get(txn, &storage_key)?
+      .and_then(|count| count.try_into().ok())
+      .map_or(0, u64::from_le_bytes)
+      .saturating_add_signed(delta);
+    refs.put(txn, &storage_key, &count.to_le_bytes())?;
+    Ok(count)
+  }
+
+  /// Delete the entries whose reference count is zero, along with their
+  /// counts. Entries that never had a reference added are left alone.
+  pub fn sweep_unreferenced(&self, txn: &mut RwTxn, stats: &mut DeleteStats) -> Result<()> {
+    let Some(refs) = self.internal().refs else {
+      return Ok(());
+    };
+    let mut unreferenced = Vec::new();
+    for entry in refs.iter(txn)? {
+      let (key, count) = entry?;
+      stats.scanned += 1;
+      if count == 0u64.to_le_bytes() {
+        unreferenced.push(key.to_string());
+      }
+    }
+
+    for key in unreferenced {
+      let stored = self.database.get(txn, &key)?;
+      if let Some(stored) = stored.filter(|stored| tombstoned_entry(stored).is_none()) {
+        stats.bytes += self.stored_size(txn, &key, stored)?;
+        if !self.tombstone_entry(txn, &key)? {
+          self.remove_entry(txn, &key)?;
+        }
+        stats.deleted += 1;
+      }
+      refs.delete(txn, &key)?;
+    }
+    Ok(())
+  }
+
+  /// Keys starting with `prefix` whose entries take up between `min` and
+  /// `max` bytes on disk, inclusive. Sizes are those of the stored,
+  /// compressed values, so this doesn't decompress anything.
+  pub fn keys_by_stored_size(
+    &self,
+    txn: &RoTxn,
+    prefix: &str,
+    min: Option<usize>,
+    max: Option<usize>,
+  ) -> Result<Vec<String>> {
+    let _scan = self.sequential_scan();
+    let start = match prefix {
+      "" => Bound::Unbounded,
+      prefix => Bound::Included(prefix),
+    };
+
+    let mut keys = Vec::new();
+    for entry in self.database.range(txn, &(start, Bound::Unbounded))? {
+      let (key, stored) = entry?;
+      if !key.starts_with(prefix) {
+        break;
+      }
+      if key.starts_with(INTERNAL_KEY_PREFIX) || tombstoned_entry(stored).is_some() {
+        continue;
+      }
+      let size = self.stored_size(txn, key, stored)?;
+      if min.is_some_and(|min| size < min) || max.is_some_and(|max| size > max) {
+        continue;
+      }
+      keys.push(self.original_key(txn, key)?);
+    }
+    Ok(keys)
+  }
+
+  /// Count entries and their sizes by namespace, the first `depth` segments
+  /// of their keys after `prefix` when split by `delimiter`. Keys with fewer
+  /// segments are their own namespace. `delimiter` can't be empty and
+  /// `depth` has to be at least 1.
+  ///
+  /// Values are never read, only their stored sizes.
+  pub fn key_histogram(
+    &self,
+    txn: &RoTxn,
+    prefix: &str,
+    delimiter: &str,
+    depth: usize,
+  ) -> Result<BTreeMap<String, NamespaceUsage>> {
+    let _scan = self.sequential_scan();
+    let start = match prefix {
+      "" => Bound::Unbounded,
+      prefix => Bound::Included(prefix),
+    };
+
+    let mut histogram = BTreeMap::<String, NamespaceUsage>::new();
+    for entry in self.database.range(txn, &(start, Bound::Unbounded))? {
+      let (key, stored) = entry?;
+      if !key.starts_with(prefix) {
+        break;
+      }
+      if key.starts_with(INTERNAL_KEY_PREFIX) || tombstoned_entry(stored).is_some() {
+        continue;
+      }
+      let value_bytes = self.stored_size(txn, key, stored)?;
+      let original_key = self.original_key(txn, key)?;
+      let relative_key = original_key.get(prefix.len()..).unwrap_or(&original_key);
+      let namespace = match relative_key.match_indices(delimiter).nth(depth - 1) {
+        Some((end, _)) => &relative_key[..end],
+        None => relative_key,
+      };
+      let usage = match histogram.get_mut(namespace) {
+        Some(usage) => usage,
+        None => histogram.entry(namespace.to_string()).or_default(),
+      };
+      usage.entries += 1;
+      usage.key_bytes += original_key.len();
+      usage.value_bytes += value_bytes;
+    }
+    Ok(histogram)
+  }
+
+  /// Size of the entry for `key` on disk, including its chunks
+  pub fn entry_size(&self, txn: &RoTxn, key: &str) -> Result<Option<usize>> {
+    let Some((key, stored)) = self.get_stored(txn, key)? else {
+      return Ok(None);
+    };
+    self.stored_size(txn, &key, stored).map(Some)
+  }
+
+  /// Size `data` would take up once transformed and compressed for storage
+  pub fn encoded_size(&self, data: &[u8]) -> usize {
+    self.transform_value(data).encode().len()
+  }
+
+  /// Encode and decode a sample of up to `sample_size` entries, spread
+  /// evenly across the database, with each [`Codec`] to compare how they
+  /// would fare on real data.
+  ///
+  /// Entries are compared as they were written, without applying
+  /// [`LMDBOptions::value_transforms`].
+  pub fn compare_codecs(&self, txn: &RoTxn, sample_size: usize) -> Result<Vec<CodecComparison>> {
+    let len = self.entry_count(txn)? as usize;
+    let step = (len / sample_size.max(1)).max(1);
+    let mut sample = Vec::with_capacity(sample_size);
+    for entry in self.entries(txn)?.step_by(step).take(sample_size) {
+      let (key, stored) = entry?;
+      sample.push(self.read_stored(txn, key, stored)?.value);
+    }
+
+    let mut comparisons = Vec::new();
+    for codec in [Codec::Lz4, Codec::None] {
+      let mut comparison = CodecComparison {
+        codec,
+        entries: sample.len(),
+        value_bytes: 0,
+        encoded_bytes: 0,
+        encode: Duration::ZERO,
+        decode: Duration::ZERO,
+      };
+      for value in &sample {
+        let start = Instant::now();
+        let encoded = encode_entry(codec, value);
+        comparison.encode += start.elapsed();
+        let start = Instant::now();
+        decode_entry(&encoded)?;
+        comparison.decode += start.elapsed();
+        comparison.value_bytes += value.len();
+        comparison.encoded_bytes += encoded.len();
+      }
+      comparisons.push(comparison);
+    }
+    Ok(comparisons)
+  }
+
+  /// Size of an entry on disk, including its chunks or the deduplicated value
+  /// it refers to
+  fn stored_size(&self, txn: &RoTxn, key: &str, stored: &[u8]) -> Result<usize> {
+    if let Some(hash) = dedup_hash(stored) {
+      return Ok(stored.len() + self.get_deduplicated(txn, key, &hash)?.len());
+    }
+    let Some(count) = chunk_count(stored) else {
+      return Ok(stored.len());
+    };
+    let chunks = self.internal().chunks;
+    let mut size = stored.len();
+    if let Some(chunks) = chunks {
+      for index in 0..count {
+        size += chunks
+          .get(txn, &chunk_key(key, index))?
+          .map_or(0, <[u8]>::len);
+      }
+    }
+    Ok(size)
+  }
+
+  /// Delete the original key recorded for a key hashed by
+  /// [`LMDBOptions::hash_long_keys`]
+  fn remove_long_key(&self, txn: &mut RwTxn, storage_key: &str) -> Result<()> {
+    if self.options.hash_long_keys != Some(true) {
+      return Ok(());
+    }
+    if let Some(long_keys) = self.internal().long_keys {
+      long_keys.delete(txn, storage_key)?;
+    }
+    Ok(())
+  }
+
+  /// Id of the transaction that last wrote the entry stored under
+  /// `storage_key`, if [`LMDBOptions::track_write_txn_ids`] was enabled then
+  fn write_txn_id(&self, txn: &RoTxn, storage_key: &str) -> Result<Option<u64>> {
+    let Some(write_txn_ids) = self.internal().write_txn_ids else {
+      return Ok(None);
+    };
+    let txn_id = write_txn_ids.get(txn, storage_key)?;
+    Ok(txn_id.and_then(|txn_id| Some(u64::from_le_bytes(txn_id.try_into().ok()?))))
+  }
+
+  /// Delete the entry stored under `storage_key` along with everything
+  /// recorded for it: its chunks or deduplicated value, its long key and the
+  /// id of the transaction that wrote it
+  fn remove_entry(&self, txn: &mut RwTxn, storage_key: &str) -> Result<()> {
+    self.release_value(txn, storage_key)?;
+    self.remove_long_key(txn, storage_key)?;
+    // Checked whatever the current options, ids may have been recorded with
+    // other ones
+    if let Some(write_txn_ids) = self.internal().write_txn_ids {
+      write_txn_ids.delete(txn, storage_key)?;
+    }
+    self.database.delete(txn, storage_key)?;
+    Ok(())
+  }
+
+  /// Delete the entry for `key`, if there is one
+  pub fn delete(&self, txn: &mut RwTxn, key: &str) -> Result<()> {
+    self.record_access(key, true);
+    let storage_key = self.storage_key(key)?;
+    self.remove_entry(txn, &storage_key)
+  }
+
+  /// Write an entry that is deleted by [`DatabaseWriter::sweep_temp_keys`]
+  /// once this process has exited, unless it's written again with
+  /// [`DatabaseWriter::put`]
+  pub fn put_temp(&self, txn: &mut RwTxn, key: &str, data: &[u8]) -> Result<()> {
+    self.put(txn, key, data)?;
+    let storage_key = self.storage_key(key)?;
+    let temp_keys = writable(self.internal().temp_keys)?;
+    temp_keys.put(txn, &storage_key, &std::process::id().to_le_bytes())?;
+    self.has_temp_keys.store(true, Ordering::Relaxed);
+    Ok(())
+  }
+
+  /// Delete the entries written by [`DatabaseWriter::put_temp`] from
+  /// processes that are no longer running, returning how many were deleted
+  pub fn sweep_temp_keys(&self) -> Result<usize> {
+    let mut txn = self.environment.write_txn()?;
+    let Some(temp_keys) = self.internal().temp_keys else {
+      return Ok(0);
+    };
+    let mut swept = Vec::new();
+    let mut remaining = false;
+    for entry in temp_keys.iter(&txn)? {
+      let (key, pid) = entry?;
+      let pid = pid.try_into().map_or(0, u32::from_le_bytes);
+      if is_process_running(pid) {
+        remaining = true;
+      } else {
+        swept.push(key.to_string());
+      }
+    }
+
+    for key in &swept {
+      self.remove_entry(&mut txn, key)?;
+      temp_keys.delete(&mut txn, key)?;
+    }
+    txn.commit()?;
+    self.has_temp_keys.store(remaining, Ordering::Relaxed);
+    Ok(swept.len())
+  }
+
+  /// Whether `key` already holds `value`, so writing it again can be skipped
+  pub fn is_unchanged(&self, txn: &RoTxn, key: &str, value: &[u8]) -> Result<bool> {
+    Ok(
+      self
+        .get(txn, key)?
+        .is_some_and(|existing| existing == value),
+    )
+  }
+
+  /// Record or clear the marker of a batch written across two databases,
+  /// returning whether its entries should be written, which they aren't if
+  /// the batch was already applied to this database
+  fn mark_batch(&self, txn: &mut RwTxn, marker: &BatchMarker) -> Result<bool> {
+    match marker {
+      BatchMarker::Record(batch) => {
+        let batches = writable(self.internal().batches)?;
+        batches.put(txn, &batch.id, &batch.encode())?;
+      }
+      BatchMarker::Clear(id) => {
+        if let Some(batches) = self.internal().batches {
+          batches.delete(txn, id)?;
+        }
+      }
+      BatchMarker::Apply(id) => {
+        let applied = writable(self.internal().applied_batches)?;
+        if applied.get(txn, id)?.is_some() {
+          tracing::debug!("Coordinated batch {id} was already applied, skipping its entries");
+          return Ok(false);
+        }
+        applied.put(txn, id, &())?;
+      }
+      BatchMarker::ClearApplied(id) => {
+        if let Some(applied) = self.internal().applied_batches {
+          applied.delete(txn, id)?;
+        }
+      }
+    }
+    Ok(true)
+  }
+
+  /// Read the batches recorded on this database that are yet to be written
+  /// to their target
+  pub fn pending_batches(&self, txn: &RoTxn) -> Result<Vec<PendingBatch>> {
+    let Some(batches) = self.internal().batches else {
+      return Ok(Vec::new());
+    };
+    let mut pending = Vec::new();
+    for entry in batches.iter(txn)? {
+      let (id, data) = entry?;
+      let batch = PendingBatch::decode(id, data)
+        .ok_or_else(|| DatabaseWriterError::InvalidBatchMarker(id.to_string()))?;
+      pending.push(batch);
+    }
+    Ok(pending)
+  }
+
+  /// Mark an entry as deleted and leave a tombstone for it instead of
+  /// deleting it, if [`LMDBOptions::tombstones`] is enabled. Returns whether
+  /// it did, otherwise the entry still has to be deleted.
+  ///
+  /// The entry keeps its chunks, deduplicated value and long key until
+  /// [`DatabaseWriter::purge_tombstones`] removes it.
+  fn tombstone_entry(&self, txn: &mut RwTxn, storage_key: &str) -> Result<bool> {
+    if self.options.tombstones != Some(true) {
+      return Ok(false);
+    }
+    let Some(stored) = self.database.get(txn, storage_key)? else {
+      return Ok(true);
+    };
+    let entry = [&TOMBSTONED_ENTRY_MARKER[..], stored].concat();
+    let key = self.original_key(txn, storage_key)?;
+    // The id this transaction will have once committed
+    let txn_id = self.environment.info().last_txn_id as u64 + 1;
+    let tombstones = writable(self.internal().tombstones)?;
+    let tombstone = [&txn_id.to_le_bytes()[..], key.as_bytes()].concat();
+    tombstones.put(txn, storage_key, &tombstone)?;
+    self.database.put(txn, storage_key, &entry)?;
+    Ok(true)
+  }
+
+  /// Read up to `limit` tombstones for keys starting with `prefix`, in key
+  /// order, along with the key to continue after if there are more
+  pub fn tombstones(
+    &self,
+    txn: &RoTxn,
+    prefix: &str,
+    after: Option<&str>,
+    limit: usize,
+  ) -> Result<(Vec<Tombstone>, Option<String>)> {
+    let Some(tombstones) = self.internal().tombstones else {
+      return Ok((Vec::new(), None));
+    };
+    let start = match after {
+      Some(after) => Bound::Excluded(after),
+      None if prefix.is_empty() => Bound::Unbounded,
+      None => Bound::Included(prefix),
+    };
+
+    let mut entries = Vec::new();
+    let mut last_key = None;
+    for entry in tombstones.range(txn, &(start, Bound::Unbounded))? {
+      let (storage_key, tombstone) = entry?;
+      if !storage_key.starts_with(prefix) {
+        return Ok((entries, None));
+      }
+      if entries.len() == limit {
+        return Ok((entries, last_key));
+      }
+      last_key = Some(storage_key.to_string());
+      let (txn_id, key) = tombstone.split_at(8);
+      entries.push(Tombstone {
+        key: String::from_utf8_lossy(key).into_owned(),
+        txn_id: u64::from_le_bytes(txn_id.try_into().unwrap()),
+      });
+    }
+    Ok((entries, None))
+  }
+
+  /// Remove the tombstones left by transactions before `before_txn_id`, or
+  /// all of them, along with the entries they mark as deleted. Returns how
+  /// many were removed.
+  pub fn purge_tombstones(&self, txn: &mut RwTxn, before_txn_id: Option<u64>) -> Result<usize> {
+    let Some(tombstones) = self.internal().tombstones else {
+      return Ok(0);
+    };
+    let mut purged = Vec::new();
+    for entry in tombstones.iter(txn)? {
+      let (key, tombstone) = entry?;
+      let txn_id = u64::from_le_bytes(tombstone[..8].try_into().unwrap());
+      if before_txn_id.is_none_or(|before| txn_id < before) {
+        purged.push(key.to_string());
+      }
+    }
+
+    for key in &purged {
+      // Written again while tombstones were disabled, so the entry is kept
+      let stored = self.database.get(txn, key)?;
+      if stored.is_some_and(|stored| tombstoned_entry(stored).is_some()) {
+        self.remove_entry(txn, key)?;
+      }
+      tombstones.delete(txn, key)?;
+    }
+    Ok(purged.len())
+  }
+
+  /// Delete the chunks of the value stored under `key` if it was chunked, or
+  /// release the deduplicated value it refers to
+  fn release_value(&self, txn: &mut RwTxn, key: &str) -> Result<()> {
+    // Checked whatever the current options, the entry may have been written
+    // with other ones
+    let Some(stored) = self.database.get(txn, key)? else {
+      return Ok(());
+    };
+    let stored = tombstoned_entry(stored).unwrap_or(stored);
+    if let Some(hash) = dedup_hash(stored) {
+      return self.release_deduplicated(txn, &hash);
+    }
+    let Some(count) = chunk_count(stored) else {
+      return Ok(());
+    };
+    if let Some(chunks) = self.internal().chunks {
+      for index in 0..count {
+        chunks.delete(txn, &chunk_key(key, index))?;
+      }
+    }
+    Ok(())
+  }
+
+  /// Store an entry without compression, writing it straight into space
+  /// reserved in the memory map rather than into an intermediate buffer.
+  ///
+  /// `size` bytes are reserved and `data` is copied into them, the remainder
+  /// is zero-filled.
+  pub fn put_reserved(&self, txn: &mut RwTxn, key: &str, size: usize, data: &[u8]) -> Result<()> {
+    if data.len() > size {
+      return Err(DatabaseWriterError::ReservedSpaceExceeded {
+        size,
+        len: data.len(),
+      });
+    }
+    if self.exceeds_max_value_size(size) {
+      return Err(DatabaseWriterError::ValueTooLarge {
+        len: size,
+        max: self.options.max_value_size.unwrap_or_default() as usize,
+      });
+    }
+    let key = self.write_key(txn, key)?;
+    let start = clock::now();
+    self.release_value(txn, &key)?;
+    self
+      .database
+      .put_reserved(txn, &key, RAW_ENTRY_MARKER.len() + size, |space| {
+        space.write_all(&RAW_ENTRY_MARKER)?;
+        space.write_all(data)?;
+        space.fill_zeroes();
+        Ok(())
+      })?;
+    self.record_metrics(|metrics| metrics.put += clock::elapsed(start));
+    Ok(())
+  }
+
+  /// Fail early if writing `required` bytes would grow the data file by more
+  /// than the filesystem holding it has available, rather than part-way
+  /// through a large write. Writes that fit in the disk space already
+  /// allocated to the file, such as when it was preallocated, aren't checked.
+  pub fn ensure_disk_space(&self, required: u64) -> Result<()> {
+    let growth = self.file_growth(required)?;
+    if growth == 0 {
+      return Ok(());
+    }
+    let Some(available) = available_disk_space(Path::new(&self.options.path))? else {
+      return Ok(());
+    };
+    if available < growth {
+      return Err(DatabaseWriterError::InsufficientDiskSpace {
+        required: growth,
+        available,
+      });
+    }
+    Ok(())
+  }
+
+  /// How many bytes of disk space the data file needs on top of what is
+  /// allocated to it to fit `required` more bytes after its last used page.
+  /// Free pages within the file aren't counted, so this errs on the high side.
+  fn file_growth(&self, required: u64) -> Result<u64> {
+    let txn = self.read_txn()?;
+    let page_size = u64::from(self.database.stat(&txn)?.page_size);
+    txn.commit()?;
+    let used = (self.environment.info().last_page_number as u64 + 1) * page_size;
+    let allocated = allocated_size(&self.environment.path().join("data.mdb"))?;
+    Ok(used.saturating_add(required).saturating_sub(allocated))
+  }
+
+  /// Basic size statistics for the database
+  pub fn stats(&self) -> Result<DatabaseWriterStats> {
+    let txn = self.read_txn()?;
+    let entries = self.entry_count(&txn)?;
+    txn.commit()?;
+    Ok(DatabaseWriterStats {
+      entries,
+      disk_size: self.environment.real_disk_size()?,
+      map_size: self.environment.info().map_size as u64,
+    })
+  }
+
+  /// Copy the database into a new directory, compacting it along the way.
+  ///
+  /// The copy is written to a temporary file, synced and then renamed into
+  /// place, so the target either has the whole copy or no database at all.
+  /// The target directory must not already contain a database.
+  pub fn copy_to(&self, path: &Path) -> Result<()> {
+    std::fs::create_dir_all(path)?;
+    let target = path.join("data.mdb");
+    if target.exists() {
+      return Err(
+        std::io::Error::new(
+          std::io::ErrorKind::AlreadyExists,
+          format!("{target:?} already exists"),
+        )
+        .into(),
+      );
+    }
+    let partial = path.join(PARTIAL_COPY_FILE);
+    remove_partial_copy(&partial)?;
+    let file = self
+      .environment
+      .copy_to_file(&partial, CompactionOption::Enabled)?;
+    file.sync_all()?;
+    std::fs::rename(&partial, &target)?;
+    // Make the rename itself durable
+    #[cfg(unix)]
+    std::fs::File::open(path)?.sync_all()?;
+    Ok(())
+  }
+
+  /// Create a read transaction
+  pub fn read_txn(&self) -> heed::Result<ReadTxn<'_>> {
+    self.begin_read(|| self.environment.read_txn())
+  }
+
+  /// Create a static read transaction that owns a reference counted copy of
+  /// the database environment
+  pub fn static_read_txn(&self) -> heed::Result<ReadTxn<'static>> {
+    self.begin_read(|| self.environment.clone().static_read_txn())
+  }
+
+  /// Start a read transaction with `begin`, adopting the new size of the
+  /// memory map if another process grew it past the size of this one.
+  ///
+  /// LMDB requires that no other transaction of this process is open while
+  /// the map is resized, so until the other read transactions started here
+  /// are closed this fails with `MDB_MAP_RESIZED` instead.
+  fn begin_read<'e>(
+    &self,
+    begin: impl Fn() -> heed::Result<RoTxn<'e>>,
+  ) -> heed::Result<ReadTxn<'e>> {
+    let start = || {
+      let _starting = self
+        .map_resize
+        .read()
+        .unwrap_or_else(PoisonError::into_inner);
+      self.open_read_txns.fetch_add(1, Ordering::SeqCst);
+      let reader = OpenReadTxn(self.open_read_txns.clone());
+      self.refresh_internal_databases()?;
+      Ok(ReadTxn {
+        txn: begin()?,
+        _reader: reader,
+      })
+    };
+    match start() {
+      Err(heed::Error::Mdb(heed::MdbError::MapResized)) => {
+        let resizing = self
+          .map_resize
+          .write()
+          .unwrap_or_else(PoisonError::into_inner);
+        if self.open_read_txns.load(Ordering::SeqCst) > 0 {
+          return Err(heed::Error::Mdb(heed::MdbError::MapResized));
+        }
+        tracing::debug!("Adopting the map size another process grew the map to");
+        // Safe as no read transaction is open or can start while resizing,
+        // and a size of zero adopts the size the map was grown to
+        unsafe { self.environment.resize(0)? };
+        drop(resizing);
+        start()
+      }
+      result => result,
+    }
+  }
+
+  /// Handles to the named databases used internally
+  fn internal(&self) -> InternalDatabases {
+    *self.internal.lock().unwrap_or_else(PoisonError::into_inner)
+  }
+
+  /// Open the internal databases a read-only environment didn't have yet if
+  /// another process committed since, as they may have been created. This
+  /// happens before read transactions start, as databases opened after a
+  /// transaction started can't be used by it.
+  fn refresh_internal_databases(&self) -> heed::Result<()> {
+    if !self.is_read_only() {
+      return Ok(());
+    }
+    let last_txn_id = self.environment.info().last_txn_id;
+    if self.internal_txn_id.load(Ordering::Relaxed) == last_txn_id {
+      return Ok(());
+    }
+    // Held while opening, so databases are never opened concurrently
+    let mut internal = self.internal.lock().unwrap_or_else(PoisonError::into_inner);
+    if !internal.is_complete() {
+      let txn = self.environment.read_txn()?;
+      let refreshed = InternalDatabases::open(&self.environment, &txn)?;
+      txn.commit()?;
+      *internal = refreshed;
+    }
+    self.internal_txn_id.store(last_txn_id, Ordering::Relaxed);
+    Ok(())
+  }
+
+  /// Whether the database was opened with [`LMDBOptions::read_only`]
+  pub fn is_read_only(&self) -> bool {
+    self.options.read_only == Some(true)
+  }
+
+  /// Create a static read transaction along with the id of the committed
+  /// transaction it reads from
+  pub fn static_read_txn_with_id(&self) -> heed::Result<(ReadTxn<'static>, u64)> {
+    loop {
+      // LMDB doesn't expose the snapshot a read transaction uses, but if
+      // nothing was committed while it was created it must be the latest one
+      let last_txn_id = self.environment.info().last_txn_id;
+      let txn = self.static_read_txn()?;
+      if self.environment.info().last_txn_id == last_txn_id {
+        return Ok((txn, last_txn_id as u64));
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use std::env::temp_dir;
+  use std::sync::mpsc::channel;
+
+  use super::*;
+
+  fn random() -> String {
+    let value = rand::random::<i32>();
+    format!("{value}")
+  }
+
+  #[test]
+  fn database_writer_can_read_and_write() {
+    let db_path = temp_dir()
+      .join("lmdb-js-lite")
+      .join(random())
+      .join("lmdb-cache-tests.db");
+    let _ = std::fs::remove_dir_all(&db_path);
+
+    let options = LMDBOptions {
+      path: db_path.to_str().unwrap().to_string(),
+      async_writes: false,
+      map_size: None,
+      ..Default::default()
+    };
+
+    let writer = DatabaseWriter::new(&options).unwrap();
+    let mut write_txn = writer.environment().write_txn().unwrap();
+    writer
+      .put(&mut write_txn, "key", &vec![1, 2, 3, 3, 3, 3, 3, 3, 4])
+      .unwrap();
+    write_txn.commit().unwrap();
+
+    let read_txn = writer.environment().read_txn().unwrap();
+    let value = writer.get(&read_txn, "key").unwrap().unwrap();
+    assert_eq!(&value, &vec![1, 2, 3, 3, 3, 3, 3, 3, 4]);
+    drop(read_txn);
+    let read_txn = writer.environment().read_txn().unwrap();
+    let value = writer.get(&read_txn, "other-key").unwrap();
+    assert_eq!(&value, &None);
+  }
+
+  #[test]
+  fn database_writer_get_entry_reports_sizes() {
+    let options = LMDBOptions {
+      path: temp_dir()
+        .join("lmdb-js-lite")
+        .join(random())
+        .to_str()
+        .unwrap()
+        .to_string(),
+      ..Default::default()
+    };
+    let writer = DatabaseWriter::new(&options).unwrap();
+    let value = vec![7; 4096];
+    let mut txn = writer.environment().write_txn().unwrap();
+    writer.put(&mut txn, "key", &value).unwrap();
+    txn.commit().unwrap();
+
+    let txn = writer.read_txn().unwrap();
+    let entry = writer.get_entry(&txn, "key").unwrap().unwrap();
+    assert_eq!(entry.value, value);
+    assert_eq!(entry.codec, Codec::Lz4);
+    assert!(entry.stored_size < value.len());
+    assert!(writer.get_entry(&txn, "missing").unwrap().is_none());
+  }
+
+  #[test]
+  fn database_writer_rejects_options_that_change_how_entries_are_stored() {
+    let options = LMDBOptions {
+      path: temp_dir()
+        .join("lmdb-js-lite")
+        .join(random())
+        .to_str()
+        .unwrap()
+        .to_string(),
+      hash_long_keys: Some(true),
+      ..Default::default()
+    };
+    let reopen = |options: &LMDBOptions| {
+      let writer = DatabaseWriter::new(options)?;
+      let closing = writer.environment().clone().prepare_for_closing();
+      drop(writer);
+      closing.wait();
+      Ok::<_, DatabaseWriterError>(())
+    };
+    reopen(&options).unwrap();
+    reopen(&options).unwrap();
+
+    let requested = LMDBOptions {
+      key_collation: Some(KeyCollation::CaseInsensitive),
+      hash_long_keys: None,
+      ..options.clone()
+    };
+    let Err(DatabaseWriterError::OptionsMismatch { conflicts, .. }) = reopen(&requested) else {
+      panic!("expected conflicting options");
+    };
+    assert_eq!(
+      conflicts,
+      vec![OptionConflict {
+        field: "hashLongKeys",
+        existing: "true".to_string(),
+        requested: "false".to_string(),
+      }]
+    );
+  }
+
+  #[test]
+  fn database_writer_stores_incompressible_values_raw() {
+    let options = LMDBOptions {
+      path: temp_dir()
+        .join("lmdb-js-lite")
+        .join(random())
+        .to_str()
+        .unwrap()
+        .to_string(),
+      ..Default::default()
+    };
+    let writer = DatabaseWriter::new(&options).unwrap();
+    let value: Vec<u8> = (0..4096).map(|_| rand::random::<u8>()).collect();
+    let mut txn = writer.environment().write_txn().unwrap();
+    writer.put(&mut txn, "key", &value).unwrap();
+    txn.commit().unwrap();
+
+    let txn = writer.read_txn().unwrap();
+    let entry = writer.get_entry(&txn, "key").unwrap().unwrap();
+    assert_eq!(entry.value, value);
+    assert_eq!(entry.codec, Codec::None);
+    assert_eq!(entry.stored_size, value.len() + RAW_ENTRY_MARKER.len());
+  }
+
+  #[test]
+  fn database_writer_put_reserved() {
+    let options = LMDBOptions {
+      path: temp_dir()
+        .join("lmdb-js-lite")
+        .join(random())
+        .to_str()
+        .unwrap()
+        .to_string(),
+      ..Default::default()
+    };
+    let writer = DatabaseWriter::new(&options).unwrap();
+    let mut txn = writer.environment().write_txn().unwrap();
+    writer.put_reserved(&mut txn, "key", 5, &[1, 2, 3]).unwrap();
+    assert!(matches!(
+      writer.put_reserved(&mut txn, "other", 2, &[1, 2, 3]),
+      Err(DatabaseWriterError::ReservedSpaceExceeded { size: 2, len: 3 })
+    ));
+    txn.commit().unwrap();
+
+    let txn = writer.read_txn().unwrap();
+    let entry = writer.get_entry(&txn, "key").unwrap().unwrap();
+    assert_eq!(entry.value, vec![1, 2, 3, 0, 0]);
+    assert_eq!(entry.codec, Codec::None);
+    assert_eq!(writer.get(&txn, "key").unwrap(), Some(vec![1, 2, 3, 0, 0]));
+  }
+
+  #[cfg(target_os = "linux")]
+  #[test]
+  fn database_writer_finds_memory_map() {
+    let options = LMDBOptions {
+      path: temp_dir()
+        .join("lmdb-js-lite")
+        .join(random())
+        .to_str()
+        .unwrap()
+        .to_string(),
+      map_size: Some((1024 * 1024) as f64),
+      access_pattern: Some(AccessPattern::Random),
+      ..Default::default()
+    };
+    let writer = DatabaseWriter::new(&options).unwrap();
+    let data_path = writer.environment().path().join("data.mdb");
+    let (_, len) = find_memory_map(&data_path).unwrap().unwrap();
+    assert_eq!(len, 1024 * 1024);
+    advise_memory_map(&data_path, &AccessPattern::Sequential).unwrap();
+  }
+
+  #[test]
+  fn database_writer_finds_keys_by_stored_size() {
+    let options = LMDBOptions {
+      path: temp_dir()
+        .join("lmdb-js-lite")
+        .join(random())
+        .to_str()
+        .unwrap()
+        .to_string(),
+      ..Default::default()
+    };
+    let writer = DatabaseWriter::new(&options).unwrap();
+    let mut txn = writer.environment().write_txn().unwrap();
+    writer.put_reserved(&mut txn, "a:small", 10, &[]).unwrap();
+    writer.put_reserved(&mut txn, "a:large", 1000, &[]).unwrap();
+    writer.put_reserved(&mut txn, "b:large", 1000, &[]).unwrap();
+
+    let keys = |prefix, min, max| writer.keys_by_stored_size(&txn, prefix, min, max).unwrap();
+    assert_eq!(keys("", Some(100), None), vec!["a:large", "b:large"]);
+    assert_eq!(keys("a:", Some(100), None), vec!["a:large"]);
+    assert_eq!(keys("a:", None, Some(100)), vec!["a:small"]);
+    assert!(keys("a:", Some(2000), None).is_empty());
+  }
+
+  #[test]
+  fn database_writer_deduplicates_content_addressed_entries() {
+    let options = LMDBOptions {
+      path: temp_dir()
+        .join("lmdb-js-lite")
+        .join(random())
+        .to_str()
+        .unwrap()
+        .to_string(),
+      ..Default::default()
+    };
+    let writer = DatabaseWriter::new(&options).unwrap();
+    let key = content_key(b"value");
+    assert_eq!(key.len(), 32);
+    assert_ne!(key, content_key(b"other"));
+
+    let mut txn = writer.environment().write_txn().unwrap();
+    writer.put_content(&mut txn, &key, b"value").unwrap();
+    assert_eq!(writer.get(&txn, &key).unwrap(), Some(b"value".to_vec()));
+    // Entries that are already present aren't written again
+    writer.put(&mut txn, &key, b"stand-in").unwrap();
+    writer.put_content(&mut txn, &key, b"value").unwrap();
+    assert_eq!(writer.get(&txn, &key).unwrap(), Some(b"stand-in".to_vec()));
+  }
+
+  #[test]
+  fn database_writer_sweeps_released_entries() {
+    let options = LMDBOptions {
+      path: temp_dir()
+        .join("lmdb-js-lite")
+        .join(random())
+        .to_str()
+        .unwrap()
+        .to_string(),
+      ..Default::default()
+    };
+    let writer = DatabaseWriter::new(&options).unwrap();
+    let mut txn = writer.environment().write_txn().unwrap();
+    writer.put(&mut txn, "shared", &[1]).unwrap();
+    writer.put(&mut txn, "released", &[2]).unwrap();
+    writer.put(&mut txn, "unreferenced", &[3]).unwrap();
+    assert_eq!(writer.update_ref_count(&mut txn, "shared", 1).unwrap(), 1);
+    assert_eq!(writer.update_ref_count(&mut txn, "shared", 1).unwrap(), 2);
+    assert_eq!(writer.update_ref_count(&mut txn, "shared", -1).unwrap(), 1);
+    assert_eq!(writer.update_ref_count(&mut txn, "released", 1).unwrap(), 1);
+    assert_eq!(
+      writer.update_ref_count(&mut txn, "released", -1).unwrap(),
+      0
+    );
+    assert_eq!(
+      writer.update_ref_count(&mut txn, "released", -1).unwrap(),
+      0
+    );
+
+    let mut stats = DeleteStats::default();
+    writer.sweep_unreferenced(&mut txn, &mut stats).unwrap();
+    assert_eq!(stats.deleted, 1);
+    assert!(writer.get(&txn, "released").unwrap().is_none());
+    assert!(writer.get(&txn, "shared").unwrap().is_some());
+    assert!(writer.get(&txn, "unreferenced").unwrap().is_some());
+    // The count of a swept entry starts over
+    assert_eq!(writer.update_ref_count(&mut txn, "released", 1).unwrap(), 1);
+  }
+
+  #[test]
+  fn database_writer_compares_codecs_on_a_sample() {
+    let options = LMDBOptions {
+      path: temp_dir()
+        .join("lmdb-js-lite")
+        .join(random())
+        .to_str()
+        .unwrap()
+        .to_string(),
+      ..Default::default()
+    };
+    let writer = DatabaseWriter::new(&options).unwrap();
+    let mut txn = writer.environment().write_txn().unwrap();
+    for i in 0..10 {
+      writer.put(&mut txn, &format!("{i}"), &[0; 1000]).unwrap();
+    }
+    txn.commit().unwrap();
+
+    let txn = writer.environment().read_txn().unwrap();
+    let comparisons = writer.compare_codecs(&txn, 5).unwrap();
+    assert_eq!(comparisons.len(), 2);
+    let lz4 = &comparisons[0];
+    let none = &comparisons[1];
+    assert_eq!(
+      (lz4.codec, lz4.entries, lz4.value_bytes),
+      (Codec::Lz4, 5, 5000)
+    );
+    assert_eq!((none.codec, none.entries), (Codec::None, 5));
+    assert!(lz4.encoded_bytes < none.encoded_bytes);
+  }
+
+  #[test]
+  fn read_only_databases_can_be_read_but_not_written() {
+    let path = temp_dir().join("lmdb-js-lite").join(random());
+    let options = LMDBOptions {
+      path: path.to_str().unwrap().to_string(),
+      ..Default::default()
+    };
+    let writer = DatabaseWriter::new(&options).unwrap();
+    let mut txn = writer.environment().write_txn().unwrap();
+    writer.put(&mut txn, "key", &[1]).unwrap();
+    txn.commit().unwrap();
+    let map_size = writer.environment().info().map_size;
+    let closing = writer.environment().clone().prepare_for_closing();
+    drop(writer);
+    closing.wait();
+
+    let (writer, database) = start_make_database_writer(&LMDBOptions {
+      map_size: Some(map_size as f64),
+      read_only: Some(true),
+      ..options
+    })
+    .unwrap();
+    assert!(database.is_read_only());
+    let txn = database.read_txn().unwrap();
+    assert_eq!(database.get(&txn, "key").unwrap(), Some(vec![1]));
+    drop(txn);
+    assert_eq!(get_sync(&writer, "key"), Some(vec![1]));
+
+    let (tx, rx) = channel();
+    writer
+      .send(DatabaseWriterMessage::Put {
+        key: "key".into(),
+        value: vec![2],
+        resolve: Box::new(move |result| tx.send(result).unwrap()),
+      })
+      .unwrap();
+    assert!(rx.recv().unwrap().is_err());
+  }
+
+  #[test]
+  fn database_writer_falls_back_to_the_previous_snapshot() {
+    let path = temp_dir().join("lmdb-js-lite").join(random());
+    let options = LMDBOptions {
+      path: path.to_str().unwrap().to_string(),
+      verify_on_open: Some(10),
+      ..Default::default()
+    };
+    let writer = DatabaseWriter::new(&options).unwrap();
+    assert!(writer.recovery().is_none());
+    for key in ["a", "b"] {
+      let mut txn = writer.environment().write_txn().unwrap();
+      writer.put(&mut txn, key, &[1]).unwrap();
+      txn.commit().unwrap();
+    }
+    let last_txn_id = writer.environment().info().last_txn_id;
+    let closing = writer.environment().clone().prepare_for_closing();
+    drop(writer);
+    closing.wait();
+
+    // Point the main database of the newest meta page past the last page
+    let data_path = path.join("data.mdb");
+    let mut data = std::fs::read(&data_path).unwrap();
+    let page_size = u32::from_le_bytes(data[40..44].try_into().unwrap()) as usize;
+    let txn_id = |meta: &[u8]| u64::from_le_bytes(meta[144..152].try_into().unwrap());
+    let newest = if txn_id(&data[page_size..]) > txn_id(&data) {
+      page_size
+    } else {
+      0
+    };
+    let last_page = u64::from_le_bytes(data[newest + 136..newest + 144].try_into().unwrap());
+    data[newest + 128..newest + 136].copy_from_slice(&(last_page + 1).to_le_bytes());
+    std::fs::write(&data_path, data).unwrap();
+
+    let writer = DatabaseWriter::new(&options).unwrap();
+    let recovery = writer.recovery().unwrap();
+    assert_eq!(recovery.discarded_txn_id, last_txn_id);
+    let txn = writer.environment().read_txn().unwrap();
+    assert_eq!(writer.get(&txn, "a").unwrap(), Some(vec![1]));
+    assert_eq!(writer.get(&txn, "b").unwrap(), None);
+  }
+
+  #[test]
+  fn database_writer_benchmark_cleans_up_after_itself() {
+    let options = LMDBOptions {
+      path: temp_dir()
+        .join("lmdb-js-lite")
+        .join(random())
+        .to_str()
+        .unwrap()
+        .to_string(),
+      ..Default::default()
+    };
+    let writer = DatabaseWriter::new(&options).unwrap();
+    for mode in [
+      BenchmarkMode::Write,
+      BenchmarkMode::BatchWrite,
+      BenchmarkMode::Read,
+    ] {
+      let report = writer.benchmark(mode, 20, 64).unwrap();
+      assert_eq!(report.ops, 20);
+      assert!(report.p50 <= report.p90 && report.p90 <= report.p99);
+      assert!(report.p99 <= report.max);
+    }
+
+    let txn = writer.environment().read_txn().unwrap();
+    assert_eq!(writer.entry_count(&txn).unwrap(), 0);
+  }
+
+  #[test]
+  fn database_writer_copies_raw_entries() {
+    let options = LMDBOptions {
+      path: temp_dir()
+        .join("lmdb-js-lite")
+        .join(random())
+        .to_str()
+        .unwrap()
+        .to_string(),
+      ..Default::default()
+    };
+    let writer = DatabaseWriter::new(&options).unwrap();
+    let value = vec![1; 1024];
+    let mut txn = writer.environment().write_txn().unwrap();
+    writer.put(&mut txn, "key", &value).unwrap();
+    let raw = writer.get_raw(&txn, "key").unwrap().unwrap();
+    assert!(raw.len() < value.len());
+    writer.put_raw(&mut txn, "copy", &raw).unwrap();
+    assert_eq!(writer.get(&txn, "copy").unwrap(), Some(value));
+    assert!(matches!(
+      writer.put_raw(&mut txn, "invalid", &[1]),
+      Err(DatabaseWriterError::InvalidRawEntry(_))
+    ));
+    // A size header and four bytes that aren't an LZ4 block
+    assert!(matches!(
+      writer.put_raw(&mut txn, "invalid", &[16, 0, 0, 0, 0xff, 0xff, 0xff, 0xff]),
+      Err(DatabaseWriterError::InvalidRawEntry(_))
+    ));
+    assert!(matches!(
+      writer.put_raw(&mut txn, "invalid", &[0xff, 0xff, 0xff, 0x7f, 0]),
+      Err(DatabaseWriterError::InvalidRawEntry(_))
+    ));
+    assert!(writer.get_raw(&txn, "invalid").unwrap().is_none());
+    assert!(writer.get_raw(&txn, "missing").unwrap().is_none());
+    txn.commit().unwrap();
+    drop(writer);
+
+    // The size limit applies to the decoded value, not its encoding
+    let limited = DatabaseWriter::new(&LMDBOptions {
+      max_value_size: Some(512.0),
+      ..options
+    })
+    .unwrap();
+    let mut txn = limited.environment().write_txn().unwrap();
+    assert!(matches!(
+      limited.put_raw(&mut txn, "copy", &raw),
+      Err(DatabaseWriterError::ValueTooLarge { len: 1024, .. })
+    ));
+  }
+
+  #[test]
+  fn database_writer_counts_accesses_per_prefix() {
+    let options = LMDBOptions {
+      path: temp_dir()
+        .join("lmdb-js-lite")
+        .join(random())
+        .to_str()
+        .unwrap()
+        .to_string(),
+      access_stats_separator: Some(":".into()),
+      access_stats_sample_rate: Some(1.0),
+      ..Default::default()
+    };
+    let (writer, database) = start_make_database_writer(&options).unwrap();
+    put_sync(&writer, "a:1", vec![1]);
+    put_sync(&writer, "a:2", vec![2]);
+    put_sync(&writer, "b:1", vec![3]);
+    put_sync(&writer, "c", vec![4]);
+    assert_eq!(get_sync(&writer, "a:1"), Some(vec![1]));
+    assert_eq!(get_sync(&writer, "b:2"), None);
+
+    let stats = database.access_stats();
+    assert_eq!(stats.prefixes.len(), 3);
+    assert_eq!(
+      stats.prefixes["a:"],
+      AccessCounts {
+        reads: 1,
+        writes: 2
+      }
+    );
+    assert_eq!(
+      stats.prefixes["b:"],
+      AccessCounts {
+        reads: 1,
+        writes: 1
+      }
+    );
+    assert_eq!(
+      stats.prefixes[""],
+      AccessCounts {
+        reads: 0,
+        writes: 1
+      }
+    );
+    assert_eq!(stats.untracked, AccessCounts::default());
+  }
+
+  #[test]
+  fn database_writer_thread_records_pipeline_metrics() {
+    let options = LMDBOptions {
+      path: temp_dir()
+        .join("lmdb-js-lite")
+        .join(random())
+        .to_str()
+        .unwrap()
+        .to_string(),
+      ..Default::default()
+    };
+    let (writer, database) = start_make_database_writer(&options).unwrap();
+    put_sync(&writer, "key", vec![1; 1024]);
+    put_sync(&writer, "other", vec![2; 1024]);
+
+    let metrics = database.pipeline_metrics();
+    assert_eq!(metrics.messages, 2);
+    assert_eq!(metrics.commits, 2);
+    assert!(metrics.compression > Duration::ZERO);
+    assert!(metrics.put > Duration::ZERO);
+    assert!(metrics.commit > Duration::ZERO);
+  }
+
+  #[test]
+  fn database_writer_thread_cancels_pending_writes() {
+    let options = LMDBOptions {
+      path: temp_dir()
+        .join("lmdb-js-lite")
+        .join(random())
+        .to_str()
+        .unwrap()
+        .to_string(),
+      ..Default::default()
+    };
+    let (writer, database) = start_make_database_writer(&options).unwrap();
+    // Resolve callbacks run on the writer thread, so this one holds up the queue
+    let (unblock_tx, unblock_rx) = channel::<()>();
+    writer
+      .send(DatabaseWriterMessage::Get {
+        key: "key".into(),
+        resolve: Box::new(move |_| unblock_rx.recv().unwrap()),
+      })
+      .unwrap();
+    let (tx, rx) = channel();
+    for (key, value) in [("key", 1), ("key", 2), ("other", 3)] {
+      let tx = tx.clone();
+      writer
+        .send(DatabaseWriterMessage::Put {
+          key: key.into(),
+          value: vec![value],
+          resolve: Box::new(move |result| tx.send(result).unwrap()),
+        })
+        .unwrap();
+    }
+
+    let puts = database
+      .pending_ops()
+      .into_iter()
+      .filter(|op| op.operation == "put")
+      .count();
+    assert_eq!(puts, 3);
+    assert_eq!(database.cancel_pending("key"), 2);
+    assert_eq!(database.cancel_pending("key"), 0);
+    unblock_tx.send(()).unwrap();
+
+    let results: Vec<_> = (0..3).map(|_| rx.recv().unwrap()).collect();
+    assert!(matches!(results[0], Err(DatabaseWriterError::Cancelled)));
+    assert!(matches!(results[1], Err(DatabaseWriterError::Cancelled)));
+    assert!(results[2].is_ok());
+    assert!(database.pending_ops().is_empty());
+    let txn = database.read_txn().unwrap();
+    assert!(database.get(&txn, "key").unwrap().is_none());
+    assert_eq!(database.get(&txn, "other").unwrap(), Some(vec![3]));
+  }
+
+  #[test]
+  fn database_writer_thread_copies_entries() {
+    let options = || LMDBOptions {
+      path: temp_dir()
+        .join("lmdb-js-lite")
+        .join(random())
+        .to_str()
+        .unwrap()
+        .to_string(),
+      ..Default::default()
+    };
+    let (source, source_database) = start_make_database_writer(&options()).unwrap();
+    let (target, target_database) = start_make_database_writer(&options()).unwrap();
+    put_sync(&source, "a:1", vec![1]);
+    put_sync(&source, "a:2", vec![2]);
+    put_sync(&source, "b:1", vec![3]);
+
+    let copy_entries = |selection, raw| {
+      let (tx, rx) = channel();
+      target
+        .send(DatabaseWriterMessage::CopyEntries {
+          source: source_database.clone(),
+          selection,
+          source_prefix: "a:".into(),
+          target_prefix: "copy:".into(),
+          raw,
+          resolve: Box::new(move |result| tx.send(result.unwrap()).unwrap()),
+        })
+        .unwrap();
+      rx.recv().unwrap()
+    };
+    assert_eq!(copy_entries(CopySelection::Prefix("a:".into()), true), 2);
+    assert_eq!(
+      copy_entries(
+        CopySelection::Keys(vec!["b:1".into(), "missing".into()]),
+        false
+      ),
+      1
+    );
+
+    let txn = target_database.read_txn().unwrap();
+    assert_eq!(target_database.get(&txn, "copy:1").unwrap(), Some(vec![1]));
+    assert_eq!(target_database.get(&txn, "copy:2").unwrap(), Some(vec![2]));
+    assert_eq!(
+      target_database.get(&txn, "copy:b:1").unwrap(),
+      Some(vec![3])
+    );
+    assert_eq!(target_database.entry_count(&txn).unwrap(), 3);
+  }
+
+  #[test]
+  fn database_writer_thread_resolves_fences_in_order() {
+    let options = LMDBOptions {
+      path: temp_dir()
+        .join("lmdb-js-lite")
+        .join(random())
+        .to_str()
+        .unwrap()
+        .to_string(),
+      ..Default::default()
+    };
+    let (writer, database) = start_make_database_writer(&options).unwrap();
+    let fence = |writer: &DatabaseWriterHandle| {
+      let (tx, rx) = channel();
+      writer
+        .send(DatabaseWriterMessage::Fence {
+          resolve: Box::new(move |txn_id| tx.send(txn_id.unwrap()).unwrap()),
+        })
+        .unwrap();
+      rx
+    };
+
+    let before = fence(&writer).recv().unwrap();
+    writer
+      .send(DatabaseWriterMessage::Put {
+        key: "key".into(),
+        value: vec![1],
+        resolve: Box::new(|_| {}),
+      })
+      .unwrap();
+    let after = fence(&writer).recv().unwrap();
+    assert_eq!(after, before + 1);
+    assert_eq!(after, database.environment.info().last_txn_id as u64);
+    assert_eq!(get_sync(&writer, "key"), Some(vec![1]));
+  }
+
+  #[test]
+  fn database_writer_thread_reports_panics_while_resolving() {
+    let options = LMDBOptions {
+      path: temp_dir()
+        .join("lmdb-js-lite")
+        .join(random())
+        .to_str()
+        .unwrap()
+        .to_string(),
+      resolve_panic_policy: Some(ResolvePanicPolicy::Report),
+      ..Default::default()
+    };
+    let (writer, database) = start_make_database_writer(&options).unwrap();
+    let (errors_tx, errors_rx) = channel();
+    let errors_tx = Mutex::new(errors_tx);
+    database.set_error_listener(Some(Box::new(move |operation, err, _| {
+      let _ = errors_tx.lock().unwrap().send((operation, err.code()));
+    })));
+
+    writer
+      .send(DatabaseWriterMessage::Put {
+        key: "key".into(),
+        value: vec![1],
+        resolve: Box::new(|_| panic!("deferred was collected")),
+      })
+      .unwrap();
+    assert_eq!(errors_rx.recv().unwrap(), ("put", ErrorCode::ResolveFailed));
+    // The writer thread keeps handling messages
+    assert_eq!(get_sync(&writer, "key"), Some(vec![1]));
+  }
+
+  #[test]
+  fn database_writer_thread_reports_transaction_state() {
+    let options = LMDBOptions {
+      path: temp_dir()
+        .join("lmdb-js-lite")
+        .join(random())
+        .to_str()
+        .unwrap()
+        .to_string(),
+      ..Default::default()
+    };
+    let (writer, _) = start_make_database_writer(&options).unwrap();
+    let transaction_state = |writer: &DatabaseWriterHandle| {
+      let (tx, rx) = channel();
+      writer
+        .send(DatabaseWriterMessage::TransactionState {
+          resolve: Box::new(move |state| tx.send(state.unwrap()).unwrap()),
+        })
+        .unwrap();
+      rx.recv().unwrap()
+    };
+
+    assert!(!transaction_state(&writer).active);
+    writer
+      .send(DatabaseWriterMessage::StartTransaction {
+        resolve: Box::new(|_| {}),
+      })
+      .unwrap();
+    put_sync(&writer, "key", vec![1]);
+    put_sync(&writer, "other", vec![2]);
+    let state = transaction_state(&writer);
+    assert!(state.active);
+    assert_eq!(state.operations, 2);
+    assert!(state.age.is_some());
+
+    writer
+      .send(DatabaseWriterMessage::CommitTransaction {
+        resolve: Box::new(|_| {}),
+      })
+      .unwrap();
+    let state = transaction_state(&writer);
+    assert!(!state.active);
+    assert_eq!(state.operations, 0);
+  }
+
+  #[test]
+  fn database_writer_thread_commits_transactions_over_the_limit() {
+    let options = LMDBOptions {
+      path: temp_dir()
+        .join("lmdb-js-lite")
+        .join(random())
+        .to_str()
+        .unwrap()
+        .to_string(),
+      max_write_transaction_operations: Some(2),
+      ..Default::default()
+    };
+    let (writer, reader) = start_make_database_writer(&options).unwrap();
+    writer
+      .send(DatabaseWriterMessage::StartTransaction {
+        resolve: Box::new(|_| {}),
+      })
+      .unwrap();
+    put_sync(&writer, "key", vec![1]);
+    put_sync(&writer, "other", vec![2]);
+    let (tx, rx) = channel();
+    writer
+      .send(DatabaseWriterMessage::TransactionState {
+        resolve: Box::new(move |state| tx.send(state.unwrap()).unwrap()),
+      })
+      .unwrap();
+    assert!(!rx.recv().unwrap().active);
+
+    // Visible to readers outside the writer thread without an explicit commit
+    let txn = reader.read_txn().unwrap();
+    assert_eq!(reader.get(&txn, "other").unwrap(), Some(vec![2]));
+    txn.commit().unwrap();
+
+    let (tx, rx) = channel();
+    writer
+      .send(DatabaseWriterMessage::CommitTransaction {
+        resolve: Box::new(move |result| tx.send(result).unwrap()),
+      })
+      .unwrap();
+    rx.recv().unwrap().unwrap();
+  }
+
+  #[test]
+  fn database_writer_thread_aborts_expired_transactions() {
+    let options = LMDBOptions {
+      path: temp_dir()
+        .join("lmdb-js-lite")
+        .join(random())
+        .to_str()
+        .unwrap()
+        .to_string(),
+      max_write_transaction_age_ms: Some(50),
+      write_transaction_limit_policy: Some(WriteTransactionLimitPolicy::Abort),
+      ..Default::default()
+    };
+    let (writer, _) = start_make_database_writer(&options).unwrap();
+    writer
+      .send(DatabaseWriterMessage::StartTransaction {
+        resolve: Box::new(|_| {}),
+      })
+      .unwrap();
+    put_sync(&writer, "key", vec![1]);
+    std::thread::sleep(Duration::from_millis(200));
+
+    assert_eq!(get_sync(&writer, "key"), None);
+    // Neither the writes meant for the aborted transaction nor its commit
+    // succeed, until another transaction is started
+    let (tx, rx) = channel();
+    let put_tx = tx.clone();
+    writer
+      .send(DatabaseWriterMessage::Put {
+        key: "other".into(),
+        value: vec![2],
+        resolve: Box::new(move |result| put_tx.send(result).unwrap()),
+      })
+      .unwrap();
+    writer
+      .send(DatabaseWriterMessage::CommitTransaction {
+        resolve: Box::new(move |result| tx.send(result).unwrap()),
+      })
+      .unwrap();
+    for _ in 0..2 {
+      assert_eq!(
+        rx.recv().unwrap().unwrap_err().code(),
+        ErrorCode::TransactionAborted
+      );
+    }
+    assert_eq!(get_sync(&writer, "other"), None);
+    put_sync(&writer, "other", vec![2]);
+    assert_eq!(get_sync(&writer, "other"), Some(vec![2]));
+  }
+
+  #[test]
+  fn database_writer_thread_reports_commits() {
+    let options = LMDBOptions {
+      path: temp_dir()
+        .join("lmdb-js-lite")
+        .join(random())
+        .to_str()
+        .unwrap()
+        .to_string(),
+      ..Default::default()
+    };
+    let (writer, reader) = start_make_database_writer(&options).unwrap();
+    let (tx, rx) = channel();
+    reader.set_commit_listener(Some(Box::new(move |stats| tx.send(stats).unwrap())));
+
+    writer
+      .send(DatabaseWriterMessage::StartTransaction {
+        resolve: Box::new(|_| {}),
+      })
+      .unwrap();
+    put_sync(&writer, "key", vec![1, 2]);
+    put_sync(&writer, "other", vec![3]);
+    writer
+      .send(DatabaseWriterMessage::CommitTransaction {
+        resolve: Box::new(|_| {}),
+      })
+      .unwrap();
+    let stats = rx.recv().unwrap();
+    assert_eq!(stats.operations, 2);
+    assert_eq!(stats.bytes, 11);
+
+    put_sync(&writer, "key", vec![1]);
+    let stats = rx.recv().unwrap();
+    assert_eq!(stats.operations, 1);
+    assert_eq!(stats.bytes, 4);
+  }
+
+  #[test]
+  fn database_writer_truncates_long_keys() {
+    let options = LMDBOptions {
+      path: temp_dir()
+        .join("lmdb-js-lite")
+        .join(random())
+        .to_str()
+        .unwrap()
+        .to_string(),
+      max_key_length: Some(32),
+      size_limit_policy: Some(SizeLimitPolicy::TruncateKeyHash),
+      ..Default::default()
+    };
+    let writer = DatabaseWriter::new(&options).unwrap();
+    let long_key = "a".repeat(100);
+    let other_long_key = format!("{}b", "a".repeat(99));
+    assert_eq!(writer.storage_key(&long_key).unwrap().len(), 32);
+
+    let mut txn = writer.environment().write_txn().unwrap();
+    writer.put(&mut txn, &long_key, &[1]).unwrap();
+    writer.put(&mut txn, &other_long_key, &[2]).unwrap();
+    txn.commit().unwrap();
+
+    let txn = writer.read_txn().unwrap();
+    assert_eq!(writer.get(&txn, &long_key).unwrap(), Some(vec![1]));
+    assert_eq!(writer.get(&txn, &other_long_key).unwrap(), Some(vec![2]));
+  }
+
+  #[test]
+  fn database_writer_hashes_long_keys() {
+    let options = LMDBOptions {
+      path: temp_dir()
+        .join("lmdb-js-lite")
+        .join(random())
+        .to_str()
+        .unwrap()
+        .to_string(),
+      hash_long_keys: Some(true),
+      ..Default::default()
+    };
+    let writer = DatabaseWriter::new(&options).unwrap();
+    let long_key = "a".repeat(2000);
+    let mut txn = writer.environment().write_txn().unwrap();
+    writer.put(&mut txn, &long_key, &[1]).unwrap();
+    txn.commit().unwrap();
+
+    let txn = writer.read_txn().unwrap();
+    assert_eq!(writer.get(&txn, &long_key).unwrap(), Some(vec![1]));
+    txn.commit().unwrap();
+
+    // Simulate another key with the same hash overwriting the entry
+    let mut txn = writer.environment().write_txn().unwrap();
+    let long_keys = writer.internal().long_keys.unwrap();
+    let storage_key = writer.storage_key(&long_key).unwrap();
+    long_keys.put(&mut txn, &storage_key, "other").unwrap();
+    txn.commit().unwrap();
+
+    let txn = writer.read_txn().unwrap();
+    assert_eq!(writer.get(&txn, &long_key).unwrap(), None);
+  }
+
+  #[test]
+  #[cfg(unix)]
+  fn database_writer_checks_disk_space() {
+    let options = LMDBOptions {
+      path: temp_dir()
+        .join("lmdb-js-lite")
+        .join(random())
+        .to_str()
+        .unwrap()
+        .to_string(),
+      ..Default::default()
+    };
+    let writer = DatabaseWriter::new(&options).unwrap();
+    writer.ensure_disk_space(1).unwrap();
+    assert!(writer.file_growth(1 << 30).unwrap() > 0);
+    assert!(matches!(
+      writer.ensure_disk_space(u64::MAX),
+      Err(DatabaseWriterError::InsufficientDiskSpace { .. })
+    ));
+  }
+
+  #[test]
+  fn minify_json_removes_whitespace_outside_strings() {
+    assert_eq!(
+      minify_json(b" { \"a b\": [1, 2],\n \"c\": \"\\\" d\" }").unwrap(),
+      b"{\"a b\":[1,2],\"c\":\"\\\" d\"}"
+    );
+    assert_eq!(minify_json(b"not json"), None);
+    assert_eq!(minify_json(b"[\"unterminated"), None);
+  }
+
+  #[test]
+  fn database_writer_applies_value_transforms() {
+    let options = LMDBOptions {
+      path: temp_dir()
+        .join("lmdb-js-lite")
+        .join(random())
+        .to_str()
+        .unwrap()
+        .to_string(),
+      value_transforms: Some(vec![
+        ValueTransform::CompressedPassthrough,
+        ValueTransform::MinifyJson,
+      ]),
+      ..Default::default()
+    };
+    let writer = DatabaseWriter::new(&options).unwrap();
+    let gzipped = [0x1f, 0x8b, 1, 2, 3];
+    let mut txn = writer.environment().write_txn().unwrap();
+    writer.put(&mut txn, "gzipped", &gzipped).unwrap();
+    let json = format!("[ {} ]", vec!["{ \"a\": 1 }"; 16].join(", "));
+    writer.put(&mut txn, "json", json.as_bytes()).unwrap();
+    txn.commit().unwrap();
+
+    let txn = writer.read_txn().unwrap();
+    let entry = writer.get_entry(&txn, "gzipped").unwrap().unwrap();
+    assert_eq!(entry.value, gzipped);
+    assert_eq!(entry.codec, Codec::None);
+    let entry = writer.get_entry(&txn, "json").unwrap().unwrap();
+    assert_eq!(
+      entry.value,
+      format!("[{}]", vec!["{\"a\":1}"; 16].join(",")).as_bytes()
+    );
+    assert_eq!(entry.codec, Codec::Lz4);
+  }
+
+  #[test]
+  fn database_writer_delete_where() {
+    let options = LMDBOptions {
+      path: temp_dir()
+        .join("lmdb-js-lite")
+        .join(random())
+        .to_str()
+        .unwrap()
+        .to_string(),
+      max_value_size: Some(4.0),
+      size_limit_policy: Some(SizeLimitPolicy::ChunkValue),
+      ..Default::default()
+    };
+    let writer = DatabaseWriter::new(&options).unwrap();
+    let mut txn = writer.environment().write_txn().unwrap();
+    for key in ["a:1", "a:2", "a:3", "b:1"] {
+      writer.put(&mut txn, key, &[1]).unwrap();
+    }
+    writer
+      .put(&mut txn, "a:4", &(0..10).collect::<Vec<_>>())
+      .unwrap();
+    writer
+      .put(&mut txn, "c:1", &(0..10).collect::<Vec<_>>())
+      .unwrap();
+
+    // One entry at a time, to check deletes continue where they left off
+    let filter = DeleteFilter {
+      prefix: Some("a:".into()),
+      ..Default::default()
+    };
+    let mut stats = DeleteStats::default();
+    let mut after = None;
+    loop {
+      after = writer
+        .delete_where(&mut txn, &filter, after.as_deref(), 1, &mut stats)
+        .unwrap();
+      if after.is_none() {
+        break;
+      }
+    }
+    assert_eq!(stats.scanned, 4);
+    assert_eq!(stats.deleted, 4);
+    assert_eq!(writer.get(&txn, "a:4").unwrap(), None);
+    assert_eq!(writer.get(&txn, "b:1").unwrap(), Some(vec![1]));
+
+    // Only the chunked entry is large enough, internal databases are skipped
+    let filter = DeleteFilter {
+      larger_than_bytes: Some(20),
+      ..Default::default()
+    };
+    let mut stats = DeleteStats::default();
+    writer
+      .delete_where(&mut txn, &filter, None, 100, &mut stats)
+      .unwrap();
+    assert_eq!(stats.deleted, 1);
+    assert_eq!(writer.get(&txn, "c:1").unwrap(), None);
+    assert_eq!(writer.get(&txn, "b:1").unwrap(), Some(vec![1]));
+    let chunks = writer.internal().chunks.unwrap();
+    assert_eq!(chunks.len(&txn).unwrap(), 0);
+  }
+
+  #[test]
+  fn database_writer_delete_where_older_than_txn_id() {
+    let options = LMDBOptions {
+      path: temp_dir()
+        .join("lmdb-js-lite")
+        .join(random())
+        .to_str()
+        .unwrap()
+        .to_string(),
+      track_write_txn_ids: Some(true),
+      ..Default::default()
+    };
+    let writer = DatabaseWriter::new(&options).unwrap();
+    let mut txn = writer.environment().write_txn().unwrap();
+    writer.put(&mut txn, "a", &[1]).unwrap();
+    writer.put(&mut txn, "b", &[1]).unwrap();
+    txn.commit().unwrap();
+
+    // Rewriting an entry records the newer transaction
+    let mut txn = writer.environment().write_txn().unwrap();
+    let second = writer.environment().info().last_txn_id as u64 + 1;
+    writer.put(&mut txn, "b", &[2]).unwrap();
+    writer.put(&mut txn, "c", &[1]).unwrap();
+    txn.commit().unwrap();
+
+    let mut txn = writer.environment().write_txn().unwrap();
+    let filter = DeleteFilter {
+      older_than_txn_id: Some(second),
+      ..Default::default()
+    };
+    let mut stats = DeleteStats::default();
+    writer
+      .delete_where(&mut txn, &filter, None, 100, &mut stats)
+      .unwrap();
+    assert_eq!(stats.deleted, 1);
+    assert_eq!(writer.get(&txn, "a").unwrap(), None);
+    assert_eq!(writer.get(&txn, "b").unwrap(), Some(vec![2]));
+    assert_eq!(writer.get(&txn, "c").unwrap(), Some(vec![1]));
+
+    // Deleting an entry drops its record
+    let write_txn_ids = writer.internal().write_txn_ids.unwrap();
+    assert_eq!(write_txn_ids.len(&txn).unwrap(), 2);
+  }
+
+  #[test]
+  fn database_writer_rejects_large_values() {
+    let options = LMDBOptions {
+      path: temp_dir()
+        .join("lmdb-js-lite")
+        .join(random())
+        .to_str()
+        .unwrap()
+        .to_string(),
+      max_key_length: Some(8),
+      max_value_size: Some(4.0),
+      ..Default::default()
+    };
+    let writer = DatabaseWriter::new(&options).unwrap();
+    let mut txn = writer.environment().write_txn().unwrap();
+    assert!(matches!(
+      writer.put(&mut txn, "key", &[1, 2, 3, 4, 5]),
+      Err(DatabaseWriterError::ValueTooLarge { len: 5, max: 4 })
+    ));
+    writer.put(&mut txn, "long-key", &[1]).unwrap();
+    assert!(matches!(
+      writer.put(&mut txn, "longer-key", &[1]),
+      Err(DatabaseWriterError::KeyTooLong { len: 10, max: 8 })
+    ));
+  }
+
+  #[test]
+  fn database_writer_chunks_large_values() {
+    let options = LMDBOptions {
+      path: temp_dir()
+        .join("lmdb-js-lite")
+        .join(random())
+        .to_str()
+        .unwrap()
+        .to_string(),
+      max_value_size: Some(4.0),
+      size_limit_policy: Some(SizeLimitPolicy::ChunkValue),
+      ..Default::default()
+    };
     let writer = DatabaseWriter::new(&options).unwrap();
-    let mut write_txn = writer.environment().write_txn().unwrap();
-    writer
-      .put(&mut write_txn, "key", &vec![1, 2, 3, 3, 3, 3, 3, 3, 4])
-      .unwrap();
-    write_txn.commit().unwrap();
+    let value: Vec<u8> = (0..10).collect();
+    let mut txn = writer.environment().write_txn().unwrap();
+    writer.put(&mut txn, "key", &value).unwrap();
+    txn.commit().unwrap();
 
-    let read_txn = writer.environment().read_txn().unwrap();
-    let value = writer.get(&read_txn, "key").unwrap().unwrap();
-    assert_eq!(&value, &vec![1, 2, 3, 3, 3, 3, 3, 3, 4]);
-    drop(read_txn);
-    let read_txn = writer.environment().read_txn().unwrap();
-    let value = writer.get(&read_txn, "other-key").unwrap();
-    assert_eq!(&value, &None);
+    let txn = writer.read_txn().unwrap();
+    assert_eq!(writer.get(&txn, "key").unwrap(), Some(value.clone()));
+    txn.commit().unwrap();
+
+    // Overwriting a chunked value removes its chunks
+    let mut txn = writer.environment().write_txn().unwrap();
+    writer.put(&mut txn, "key", &[1]).unwrap();
+    let chunks = writer.internal().chunks.unwrap();
+    assert_eq!(chunks.len(&txn).unwrap(), 0);
+    txn.commit().unwrap();
+
+    let txn = writer.read_txn().unwrap();
+    assert_eq!(writer.get(&txn, "key").unwrap(), Some(vec![1]));
+    drop(txn);
+
+    // Chunk keys must fit within LMDB's maximum key size
+    let key = "k".repeat(writer.environment().max_key_size() - CHUNK_KEY_SUFFIX_LEN + 1);
+    let mut txn = writer.environment().write_txn().unwrap();
+    assert!(matches!(
+      writer.put(&mut txn, &key, &value),
+      Err(DatabaseWriterError::KeyTooLong { .. })
+    ));
+    writer.put(&mut txn, &key[1..], &value).unwrap();
+    txn.commit().unwrap();
+    drop(writer);
+
+    // Chunks are removed even once the database is opened without chunking
+    let writer = DatabaseWriter::new(&LMDBOptions {
+      size_limit_policy: None,
+      max_value_size: None,
+      ..options
+    })
+    .unwrap();
+    let mut txn = writer.environment().write_txn().unwrap();
+    writer.put(&mut txn, &key[1..], &[1]).unwrap();
+    let chunks = writer.internal().chunks.unwrap();
+    assert_eq!(chunks.len(&txn).unwrap(), 0);
   }
 
   #[test]
@@ -380,6 +5925,7 @@ mod test {
       path: db_path.to_str().unwrap().to_string(),
       async_writes: false,
       map_size: None,
+      ..Default::default()
     };
 
     let (writer, _) = start_make_database_writer(&options).unwrap();
@@ -399,6 +5945,7 @@ mod test {
       path: db_path.to_str().unwrap().to_string(),
       async_writes: false,
       map_size: None,
+      ..Default::default()
     };
 
     let (writer, _) = start_make_database_writer(&options).unwrap();
@@ -422,6 +5969,7 @@ mod test {
       path: db_path.to_str().unwrap().to_string(),
       async_writes: false,
       map_size: None,
+      ..Default::default()
     };
 
     let (writer, _) = start_make_database_writer(&options).unwrap();
@@ -438,6 +5986,7 @@ mod test {
             value: vec![1, 2, 3, 3, 3, 3, 3, 3, 4],
           },
         ],
+        skip_unchanged: false,
         resolve: Box::new(move |result| {
           tx.send(result).unwrap();
         }),
@@ -452,6 +6001,49 @@ mod test {
     assert_eq!(result, Some(vec![1, 2, 3, 3, 3, 3, 3, 3, 4]));
   }
 
+  #[test]
+  fn database_writer_thread_skips_unchanged_entries() {
+    let options = LMDBOptions {
+      path: temp_dir()
+        .join("lmdb-js-lite")
+        .join(random())
+        .to_str()
+        .unwrap()
+        .to_string(),
+      ..Default::default()
+    };
+    let (writer, database) = start_make_database_writer(&options).unwrap();
+    put_sync(&writer, "key1", vec![1, 2, 3]);
+    let put_many = |entries: Vec<(&str, Vec<u8>)>| {
+      let (tx, rx) = channel();
+      writer
+        .send(DatabaseWriterMessage::PutMany {
+          entries: entries
+            .into_iter()
+            .map(|(key, value)| NativeEntry {
+              key: key.into(),
+              value,
+            })
+            .collect(),
+          skip_unchanged: true,
+          resolve: Box::new(move |result| tx.send(result).unwrap()),
+        })
+        .unwrap();
+      rx.recv().unwrap().unwrap();
+      database.environment.info().last_txn_id
+    };
+
+    let last_txn_id = database.environment.info().last_txn_id;
+    assert_eq!(put_many(vec![("key1", vec![1, 2, 3])]), last_txn_id);
+    assert_eq!(
+      put_many(vec![("key1", vec![1, 2, 3]), ("key2", vec![4])]),
+      last_txn_id + 1
+    );
+    assert_eq!(get_sync(&writer, "key2"), Some(vec![4]));
+    put_many(vec![("key1", vec![5])]);
+    assert_eq!(get_sync(&writer, "key1"), Some(vec![5]));
+  }
+
   #[test]
   fn database_writer_thread_read_within_transaction() {
     let db_path = temp_dir()
@@ -464,6 +6056,7 @@ mod test {
       path: db_path.to_str().unwrap().to_string(),
       async_writes: false,
       map_size: None,
+      ..Default::default()
     };
 
     let (writer, reader) = start_make_database_writer(&options).unwrap();
@@ -498,6 +6091,616 @@ mod test {
     assert_eq!(result, Some(vec![1, 2, 3, 3, 3, 3, 3, 3, 4]));
   }
 
+  #[test]
+  fn database_writer_thread_get_many_from_named_databases() {
+    let db_path = temp_dir()
+      .join("lmdb-js-lite")
+      .join(random())
+      .join("lmdb-cache-tests.db");
+    let _ = std::fs::remove_dir_all(&db_path);
+
+    let options = LMDBOptions {
+      path: db_path.to_str().unwrap().to_string(),
+      async_writes: false,
+      map_size: None,
+      ..Default::default()
+    };
+
+    let (writer, reader) = start_make_database_writer(&options).unwrap();
+    put_sync(&writer, "key1", vec![1, 2, 3]);
+    let mut write_txn = reader.environment().write_txn().unwrap();
+    let other: heed::Database<Str, Bytes> = reader
+      .environment()
+      .create_database(&mut write_txn, Some("other"))
+      .unwrap();
+    other
+      .put(
+        &mut write_txn,
+        "key1",
+        &lz4_flex::block::compress_prepend_size(&[4, 5, 6]),
+      )
+      .unwrap();
+    write_txn.commit().unwrap();
+
+    let (tx, rx) = channel();
+    writer
+      .send(DatabaseWriterMessage::GetManyFrom {
+        keys: vec![
+          DatabaseKey {
+            db: "other".into(),
+            key: "key1".into(),
+          },
+          DatabaseKey {
+            db: "other".into(),
+            key: "key2".into(),
+          },
+          DatabaseKey {
+            db: "missing".into(),
+            key: "key1".into(),
+          },
+        ],
+        resolve: Box::new(move |result| tx.send(result).unwrap()),
+      })
+      .unwrap();
+    let result = rx.recv().unwrap().unwrap();
+    assert_eq!(result, vec![Some(vec![4, 5, 6]), None, None]);
+
+    let (tx, rx) = channel();
+    writer
+      .send(DatabaseWriterMessage::GetManyFrom {
+        keys: vec![DatabaseKey {
+          db: TOMBSTONES_DATABASE.into(),
+          key: "key1".into(),
+        }],
+        resolve: Box::new(move |result| tx.send(result).unwrap()),
+      })
+      .unwrap();
+    let err = rx.recv().unwrap().unwrap_err();
+    assert_eq!(err.code(), ErrorCode::InvalidArgument);
+  }
+
+  #[test]
+  fn database_writer_thread_copy_to() {
+    let db_path = temp_dir()
+      .join("lmdb-js-lite")
+      .join(random())
+      .join("lmdb-cache-tests.db");
+    let _ = std::fs::remove_dir_all(&db_path);
+    let clone_path = db_path.with_extension("clone");
+    let _ = std::fs::remove_dir_all(&clone_path);
+
+    let options = LMDBOptions {
+      path: db_path.to_str().unwrap().to_string(),
+      async_writes: false,
+      map_size: None,
+      ..Default::default()
+    };
+
+    let (writer, _) = start_make_database_writer(&options).unwrap();
+    put_sync(&writer, "key1", vec![1, 2, 3]);
+    let (tx, rx) = channel();
+    writer
+      .send(DatabaseWriterMessage::CopyTo {
+        path: clone_path.clone(),
+        resolve: Box::new(move |result| tx.send(result).unwrap()),
+      })
+      .unwrap();
+    rx.recv().unwrap().unwrap();
+    put_sync(&writer, "key2", vec![1, 2, 3]);
+
+    let (clone_writer, clone) = start_make_database_writer(&LMDBOptions {
+      path: clone_path.to_str().unwrap().to_string(),
+      ..options
+    })
+    .unwrap();
+    let txn = clone.read_txn().unwrap();
+    assert_eq!(clone.get(&txn, "key1").unwrap(), Some(vec![1, 2, 3]));
+    assert_eq!(clone.get(&txn, "key2").unwrap(), None);
+    drop(txn);
+    put_sync(&clone_writer, "key3", vec![4]);
+    assert_eq!(get_sync(&writer, "key3"), None);
+  }
+
+  #[test]
+  fn database_writer_deduplicates_values() {
+    let options = LMDBOptions {
+      path: temp_dir()
+        .join("lmdb-js-lite")
+        .join(random())
+        .to_str()
+        .unwrap()
+        .to_string(),
+      dedup_min_size: Some(64),
+      ..Default::default()
+    };
+    let writer = DatabaseWriter::new(&options).unwrap();
+    let shared_values = |txn: &RoTxn| {
+      let values = writer.internal().dedup_values.unwrap();
+      values.len(txn).unwrap()
+    };
+    let artifact = vec![7; 1024];
+
+    let mut txn = writer.environment.write_txn().unwrap();
+    writer.put(&mut txn, "a", &artifact).unwrap();
+    writer.put(&mut txn, "b", &artifact).unwrap();
+    writer.put(&mut txn, "small", &[7; 8]).unwrap();
+    assert_eq!(shared_values(&txn), 1);
+    assert_eq!(writer.get(&txn, "a").unwrap(), Some(artifact.clone()));
+    assert_eq!(writer.get(&txn, "b").unwrap(), Some(artifact.clone()));
+    let raw = writer.get_raw(&txn, "b").unwrap().unwrap();
+    assert_eq!(decode_raw_entry("b", &raw).unwrap(), artifact);
+
+    // Writing the same value again keeps it referenced
+    writer.put(&mut txn, "a", &artifact).unwrap();
+    writer.delete(&mut txn, "b").unwrap();
+    assert_eq!(shared_values(&txn), 1);
+    assert_eq!(writer.get(&txn, "a").unwrap(), Some(artifact.clone()));
+    writer.put(&mut txn, "a", &[8; 1024]).unwrap();
+    assert_eq!(shared_values(&txn), 1);
+    writer.delete(&mut txn, "a").unwrap();
+    assert_eq!(shared_values(&txn), 0);
+    txn.commit().unwrap();
+  }
+
+  #[test]
+  fn database_writer_samples_entries() {
+    let options = LMDBOptions {
+      path: temp_dir()
+        .join("lmdb-js-lite")
+        .join(random())
+        .to_str()
+        .unwrap()
+        .to_string(),
+      ..Default::default()
+    };
+    let writer = DatabaseWriter::new(&options).unwrap();
+    let mut txn = writer.environment.write_txn().unwrap();
+    for namespace in ["assets", "bundles"] {
+      for i in 0..500 {
+        let key = format!("{namespace}:{i}");
+        writer.put(&mut txn, &key, &[1; 16]).unwrap();
+      }
+    }
+    writer.put(&mut txn, "other", &[2]).unwrap();
+    txn.commit().unwrap();
+
+    let txn = writer.environment.read_txn().unwrap();
+    let sample = writer.sample(&txn, "", 50, 42).unwrap();
+    assert_eq!(sample.len(), 50);
+    assert!(sample.windows(2).all(|pair| pair[0].key < pair[1].key));
+    assert!(sample.iter().any(|entry| entry.key.starts_with("assets:")));
+    assert!(sample.iter().any(|entry| entry.key.starts_with("bundles:")));
+
+    let sample = writer.sample(&txn, "bundles:", 10, 42).unwrap();
+    assert_eq!(sample.len(), 10);
+    assert!(sample.iter().all(|entry| entry.key.starts_with("bundles:")));
+    assert_eq!(writer.sample(&txn, "missing", 10, 42).unwrap().len(), 0);
+    assert!(writer.sample(&txn, "", 2000, 42).unwrap().len() <= 1001);
+  }
+
+  #[test]
+  fn database_writer_counts_keys_by_namespace() {
+    let options = LMDBOptions {
+      path: temp_dir()
+        .join("lmdb-js-lite")
+        .join(random())
+        .to_str()
+        .unwrap()
+        .to_string(),
+      ..Default::default()
+    };
+    let writer = DatabaseWriter::new(&options).unwrap();
+    let mut txn = writer.environment.write_txn().unwrap();
+    writer.put(&mut txn, "cache:assets:1", &[1; 4]).unwrap();
+    writer.put(&mut txn, "cache:assets:2", &[1; 4]).unwrap();
+    writer.put(&mut txn, "cache:bundles:1", &[1; 4]).unwrap();
+    writer.put(&mut txn, "cache", &[1; 4]).unwrap();
+    writer.put(&mut txn, "other", &[1; 4]).unwrap();
+    txn.commit().unwrap();
+
+    let txn = writer.environment.read_txn().unwrap();
+    let value_bytes = writer.entry_size(&txn, "other").unwrap().unwrap();
+    let histogram = writer.key_histogram(&txn, "", ":", 1).unwrap();
+    assert_eq!(histogram.keys().collect::<Vec<_>>(), ["cache", "other"]);
+    assert_eq!(
+      histogram["cache"],
+      NamespaceUsage {
+        entries: 4,
+        key_bytes: 48,
+        value_bytes: 4 * value_bytes,
+      }
+    );
+
+    let histogram = writer.key_histogram(&txn, "cache:", ":", 1).unwrap();
+    assert_eq!(histogram.keys().collect::<Vec<_>>(), ["assets", "bundles"]);
+    assert_eq!(histogram["assets"].entries, 2);
+    let histogram = writer.key_histogram(&txn, "", ":", 2).unwrap();
+    assert_eq!(
+      histogram.keys().collect::<Vec<_>>(),
+      ["cache", "cache:assets", "cache:bundles", "other"]
+    );
+  }
+
+  #[test]
+  fn database_writer_counts_open_read_transactions() {
+    let options = LMDBOptions {
+      path: temp_dir()
+        .join("lmdb-js-lite")
+        .join(random())
+        .to_str()
+        .unwrap()
+        .to_string(),
+      ..Default::default()
+    };
+    let writer = DatabaseWriter::new(&options).unwrap();
+    let txn = writer.read_txn().unwrap();
+    let (snapshot, _) = writer.static_read_txn_with_id().unwrap();
+    assert_eq!(writer.open_read_txns.load(Ordering::SeqCst), 2);
+    txn.commit().unwrap();
+    drop(snapshot);
+    assert_eq!(writer.open_read_txns.load(Ordering::SeqCst), 0);
+  }
+
+  #[test]
+  fn database_writer_restores_access_pattern_after_the_last_scan() {
+    let options = LMDBOptions {
+      path: temp_dir()
+        .join("lmdb-js-lite")
+        .join(random())
+        .to_str()
+        .unwrap()
+        .to_string(),
+      ..Default::default()
+    };
+    let writer = DatabaseWriter::new(&options).unwrap();
+    let scans = || *writer.sequential_scans.lock().unwrap();
+    let first = writer.sequential_scan();
+    let second = writer.sequential_scan();
+    assert_eq!(scans(), 2);
+    drop(first);
+    assert_eq!(scans(), 1);
+    drop(second);
+    assert_eq!(scans(), 0);
+  }
+
+  #[test]
+  fn database_writer_leaves_tombstones() {
+    let options = LMDBOptions {
+      path: temp_dir()
+        .join("lmdb-js-lite")
+        .join(random())
+        .to_str()
+        .unwrap()
+        .to_string(),
+      tombstones: Some(true),
+      ..Default::default()
+    };
+    let writer = DatabaseWriter::new(&options).unwrap();
+    let mut txn = writer.environment.write_txn().unwrap();
+    for key in ["a:1", "a:2", "b:1"] {
+      writer.put(&mut txn, key, &[1]).unwrap();
+    }
+    txn.commit().unwrap();
+
+    let delete = |prefix: &str| {
+      let mut txn = writer.environment.write_txn().unwrap();
+      let filter = DeleteFilter {
+        prefix: Some(prefix.to_string()),
+        ..Default::default()
+      };
+      let mut stats = DeleteStats::default();
+      writer
+        .delete_where(&mut txn, &filter, None, 100, &mut stats)
+        .unwrap();
+      txn.commit().unwrap();
+      writer.environment.info().last_txn_id as u64
+    };
+    let first_delete = delete("a:");
+    let second_delete = delete("b:");
+
+    let txn = writer.environment.read_txn().unwrap();
+    assert_eq!(writer.get(&txn, "a:1").unwrap(), None);
+    assert!(writer.database.get(&txn, "a:1").unwrap().is_some());
+    assert_eq!(writer.key_histogram(&txn, "", ":", 1).unwrap().len(), 0);
+    let (tombstones, next) = writer.tombstones(&txn, "", None, 2).unwrap();
+    assert_eq!(
+      tombstones,
+      [
+        Tombstone {
+          key: "a:1".to_string(),
+          txn_id: first_delete,
+        },
+        Tombstone {
+          key: "a:2".to_string(),
+          txn_id: first_delete,
+        },
+      ]
+    );
+    let (tombstones, _) = writer.tombstones(&txn, "", next.as_deref(), 2).unwrap();
+    assert_eq!(tombstones[0].txn_id, second_delete);
+    drop(txn);
+
+    let mut txn = writer.environment.write_txn().unwrap();
+    writer.put(&mut txn, "a:1", &[2]).unwrap();
+    assert_eq!(
+      writer
+        .purge_tombstones(&mut txn, Some(second_delete))
+        .unwrap(),
+      1
+    );
+    txn.commit().unwrap();
+    let txn = writer.environment.read_txn().unwrap();
+    let (tombstones, _) = writer.tombstones(&txn, "", None, 10).unwrap();
+    assert_eq!(tombstones.len(), 1);
+    assert_eq!(tombstones[0].key, "b:1");
+    assert_eq!(writer.get(&txn, "a:1").unwrap(), Some(vec![2]));
+    assert!(writer.database.get(&txn, "a:2").unwrap().is_none());
+    assert!(writer.database.get(&txn, "b:1").unwrap().is_some());
+  }
+
+  #[test]
+  fn database_writer_thread_deletes_temp_keys_on_commit() {
+    let options = LMDBOptions {
+      path: temp_dir()
+        .join("lmdb-js-lite")
+        .join(random())
+        .to_str()
+        .unwrap()
+        .to_string(),
+      ..Default::default()
+    };
+    let (writer, _) = start_make_database_writer(&options).unwrap();
+    writer
+      .send(DatabaseWriterMessage::StartTransaction {
+        resolve: Box::new(|_| {}),
+      })
+      .unwrap();
+    let (tx, rx) = channel();
+    writer
+      .send(DatabaseWriterMessage::PutTemp {
+        key: "temp".to_string(),
+        value: vec![1],
+        resolve: Box::new(move |result| tx.send(result).unwrap()),
+      })
+      .unwrap();
+    rx.recv().unwrap().unwrap();
+    put_sync(&writer, "kept", vec![2]);
+    assert_eq!(get_sync(&writer, "temp"), Some(vec![1]));
+
+    let (tx, rx) = channel();
+    writer
+      .send(DatabaseWriterMessage::CommitTransaction {
+        resolve: Box::new(move |result| tx.send(result).unwrap()),
+      })
+      .unwrap();
+    rx.recv().unwrap().unwrap();
+    assert_eq!(get_sync(&writer, "temp"), None);
+    assert_eq!(get_sync(&writer, "kept"), Some(vec![2]));
+  }
+
+  #[test]
+  fn database_writer_thread_requires_explicit_transactions() {
+    let options = LMDBOptions {
+      path: temp_dir()
+        .join("lmdb-js-lite")
+        .join(random())
+        .to_str()
+        .unwrap()
+        .to_string(),
+      require_explicit_transactions: Some(true),
+      ..Default::default()
+    };
+    let (writer, _) = start_make_database_writer(&options).unwrap();
+    let (tx, rx) = channel();
+    writer
+      .send(DatabaseWriterMessage::Put {
+        key: "key".to_string(),
+        value: vec![1],
+        resolve: Box::new(move |result| tx.send(result).unwrap()),
+      })
+      .unwrap();
+    let err = rx.recv().unwrap().unwrap_err();
+    assert!(matches!(
+      err,
+      DatabaseWriterError::TransactionRequired("put")
+    ));
+    assert_eq!(get_sync(&writer, "key"), None);
+
+    writer
+      .send(DatabaseWriterMessage::StartTransaction {
+        resolve: Box::new(|_| {}),
+      })
+      .unwrap();
+    put_sync(&writer, "key", vec![1]);
+    assert_eq!(get_sync(&writer, "key"), Some(vec![1]));
+  }
+
+  #[test]
+  fn database_writer_sweeps_temp_keys_of_exited_processes() {
+    let options = LMDBOptions {
+      path: temp_dir()
+        .join("lmdb-js-lite")
+        .join(random())
+        .to_str()
+        .unwrap()
+        .to_string(),
+      ..Default::default()
+    };
+    let writer = DatabaseWriter::new(&options).unwrap();
+    let mut txn = writer.environment.write_txn().unwrap();
+    writer.put_temp(&mut txn, "temp", &[1]).unwrap();
+    writer.put_temp(&mut txn, "exited", &[1]).unwrap();
+    writer.put_temp(&mut txn, "kept", &[1]).unwrap();
+    writer.put(&mut txn, "kept", &[2]).unwrap();
+    // As if written by a process that has exited since
+    let temp_keys = writer.internal().temp_keys.unwrap();
+    temp_keys
+      .put(&mut txn, "exited", &0u32.to_le_bytes())
+      .unwrap();
+    txn.commit().unwrap();
+
+    assert_eq!(writer.sweep_temp_keys().unwrap(), 1);
+    let txn = writer.environment.read_txn().unwrap();
+    assert_eq!(writer.get(&txn, "exited").unwrap(), None);
+    assert_eq!(writer.get(&txn, "temp").unwrap(), Some(vec![1]));
+    assert_eq!(writer.get(&txn, "kept").unwrap(), Some(vec![2]));
+    assert_eq!(temp_keys.len(&txn).unwrap(), 1);
+  }
+
+  #[test]
+  fn database_writer_thread_journals_messages() {
+    let path = temp_dir().join("lmdb-js-lite").join(random());
+    let journal_path = path.join("journal.log");
+    let options = LMDBOptions {
+      path: path.to_str().unwrap().to_string(),
+      journal_path: Some(journal_path.to_str().unwrap().to_string()),
+      ..Default::default()
+    };
+    let (writer, _) = start_make_database_writer(&options).unwrap();
+    put_sync(&writer, "key", vec![1, 2]);
+    assert_eq!(get_sync(&writer, "key"), Some(vec![1, 2]));
+
+    let journal = std::fs::read_to_string(journal_path).unwrap();
+    let lines: Vec<Vec<&str>> = journal
+      .lines()
+      .map(|line| line.split('\t').collect())
+      .collect();
+    assert_eq!(lines.len(), 2);
+    assert_eq!(lines[0][1..4], ["put", "\"key\"", "5"]);
+    assert_eq!(lines[0][6], "ok");
+    assert_eq!(lines[1][1..4], ["get", "\"key\"", "0"]);
+  }
+
+  #[test]
+  fn database_writer_thread_redacts_journaled_keys() {
+    let path = temp_dir().join("lmdb-js-lite").join(random());
+    let journal_path = path.join("journal.log");
+    let options = LMDBOptions {
+      path: path.to_str().unwrap().to_string(),
+      journal_path: Some(journal_path.to_str().unwrap().to_string()),
+      redact_keys: Some(true),
+      ..Default::default()
+    };
+    let (writer, _) = start_make_database_writer(&options).unwrap();
+    put_sync(&writer, "src/secret.js", vec![1, 2]);
+    let (tx, rx) = channel();
+    writer
+      .send(DatabaseWriterMessage::PutRaw {
+        key: "src/secret.js".into(),
+        value: vec![0],
+        resolve: Box::new(move |result| tx.send(result).unwrap()),
+      })
+      .unwrap();
+    assert!(rx.recv().unwrap().is_err());
+
+    let journal = std::fs::read_to_string(journal_path).unwrap();
+    assert!(!journal.contains("secret"));
+    let redacted = format!("#{:016x}", fnv1a(b"src/secret.js"));
+    assert_eq!(journal.matches(&redacted).count(), 3);
+  }
+
+  #[cfg(target_os = "linux")]
+  #[test]
+  fn database_writer_preallocates_the_map() {
+    use std::os::unix::fs::MetadataExt;
+
+    let path = temp_dir().join("lmdb-js-lite").join(random());
+    let options = LMDBOptions {
+      path: path.to_str().unwrap().to_string(),
+      map_size: Some(4.0 * 1024.0 * 1024.0),
+      preallocate: Some(true),
+      pretouch: Some(true),
+      ..Default::default()
+    };
+    let writer = DatabaseWriter::new(&options).unwrap();
+    let metadata = std::fs::metadata(path.join("data.mdb")).unwrap();
+    assert!(metadata.blocks() * 512 >= 4 * 1024 * 1024);
+    // Writes that fit in the preallocated file don't need more disk space
+    assert_eq!(writer.file_growth(1024 * 1024).unwrap(), 0);
+  }
+
+  #[test]
+  fn database_writer_copy_to_replaces_unfinished_copies() {
+    let options = LMDBOptions {
+      path: temp_dir()
+        .join("lmdb-js-lite")
+        .join(random())
+        .to_str()
+        .unwrap()
+        .to_string(),
+      ..Default::default()
+    };
+    let writer = DatabaseWriter::new(&options).unwrap();
+    let clone_path = temp_dir().join("lmdb-js-lite").join(random());
+    std::fs::create_dir_all(&clone_path).unwrap();
+    std::fs::write(clone_path.join(PARTIAL_COPY_FILE), [1, 2, 3]).unwrap();
+
+    writer.copy_to(&clone_path).unwrap();
+    assert!(clone_path.join("data.mdb").exists());
+    assert!(!clone_path.join(PARTIAL_COPY_FILE).exists());
+    let err = writer.copy_to(&clone_path).unwrap_err();
+    assert_eq!(err.code(), crate::error::ErrorCode::IOError);
+  }
+
+  #[test]
+  fn database_writer_thread_with_low_priority() {
+    let db_path = temp_dir()
+      .join("lmdb-js-lite")
+      .join(random())
+      .join("lmdb-cache-tests.db");
+    let _ = std::fs::remove_dir_all(&db_path);
+
+    let options = LMDBOptions {
+      path: db_path.to_str().unwrap().to_string(),
+      async_writes: false,
+      map_size: None,
+      writer_thread_name: Some("test-writer".into()),
+      writer_thread_priority: Some(WriterThreadPriority::Background),
+      ..Default::default()
+    };
+
+    let (writer, _) = start_make_database_writer(&options).unwrap();
+    let thread_name = writer
+      .thread
+      .lock()
+      .unwrap()
+      .thread_handle
+      .as_ref()
+      .and_then(|thread_handle| thread_handle.thread().name().map(String::from));
+    assert_eq!(thread_name.as_deref(), Some("test-writer"));
+    put_sync(&writer, "key1", vec![1, 2, 3]);
+    assert_eq!(get_sync(&writer, "key1"), Some(vec![1, 2, 3]));
+  }
+
+  #[test]
+  fn database_writer_thread_restarts_after_idle_timeout() {
+    let db_path = temp_dir()
+      .join("lmdb-js-lite")
+      .join(random())
+      .join("lmdb-cache-tests.db");
+    let _ = std::fs::remove_dir_all(&db_path);
+
+    let options = LMDBOptions {
+      path: db_path.to_str().unwrap().to_string(),
+      async_writes: false,
+      map_size: None,
+      writer_idle_timeout_ms: Some(10),
+      ..Default::default()
+    };
+
+    let (writer, _) = start_make_database_writer(&options).unwrap();
+    put_sync(&writer, "key1", vec![1, 2, 3]);
+    // Returns once the thread has stopped for being idle
+    let thread_handle = writer.thread.lock().unwrap().thread_handle.take();
+    thread_handle.unwrap().join().unwrap();
+    assert!(!writer.thread.lock().unwrap().running);
+
+    put_sync(&writer, "key2", vec![4, 5, 6]);
+    assert!(writer.thread.lock().unwrap().running);
+    assert_eq!(get_sync(&writer, "key1"), Some(vec![1, 2, 3]));
+    assert_eq!(get_sync(&writer, "key2"), Some(vec![4, 5, 6]));
+  }
+
   fn put_sync(writer: &DatabaseWriterHandle, key: impl Into<String>, value: Vec<u8>) {
     let (tx, rx) = channel();
     writer
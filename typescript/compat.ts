@@ -1,6 +1,13 @@
 // @ts-check
 import { Lmdb } from "../index";
 
+export {
+  coordinatedBatch,
+  type CoordinatedBatchOperation,
+} from "./coordinated-batch";
+export { putFrom, type PutFromOptions } from "./put-from";
+export { rawEntries, type RawEntriesOptions } from "./raw-entries";
+
 interface DBOpenOptions {
   name: string;
   // unused
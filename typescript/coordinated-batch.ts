@@ -0,0 +1,18 @@
+import type { Entry, Lmdb } from "../index";
+
+export interface CoordinatedBatchOperation {
+  db: Lmdb;
+  entries: Entry[];
+}
+
+/**
+ * Write entries to two databases, so that if the process exits after the
+ * first has committed, the second is written the next time both are open.
+ * See `Lmdb.coordinatedBatch`.
+ */
+export async function coordinatedBatch([
+  first,
+  second,
+]: [CoordinatedBatchOperation, CoordinatedBatchOperation]): Promise<void> {
+  await first.db.coordinatedBatch(first.entries, second.db, second.entries);
+}
@@ -0,0 +1,36 @@
+import type { Entry, Lmdb } from "../index";
+
+export interface PutFromOptions {
+  /** Number of entries written per transaction. Defaults to 10000. */
+  batchSize?: number;
+}
+
+/**
+ * Write the entries produced by an iterator, in transactions of `batchSize`
+ * entries. The next batch is only pulled once the previous one has been
+ * committed, so a fast producer can't queue up unbounded writes.
+ *
+ * Resolves with the number of entries written.
+ */
+export async function putFrom(
+  db: Lmdb,
+  entries: AsyncIterable<Entry> | Iterable<Entry>,
+  options: PutFromOptions = {},
+): Promise<number> {
+  const batchSize = options.batchSize ?? 10000;
+  let batch: Entry[] = [];
+  let written = 0;
+  for await (const entry of entries) {
+    batch.push(entry);
+    if (batch.length >= batchSize) {
+      await db.putMany(batch);
+      written += batch.length;
+      batch = [];
+    }
+  }
+  if (batch.length > 0) {
+    await db.putMany(batch);
+    written += batch.length;
+  }
+  return written;
+}
@@ -0,0 +1,26 @@
+import type { Lmdb, RawEntry } from "../index";
+
+export interface RawEntriesOptions {
+  /** Number of entries read at a time. Defaults to 1000. */
+  batchSize?: number;
+}
+
+/**
+ * Iterate over every entry as it is stored, without decompressing it, in
+ * key order. Values can be written into another database with `putRaw`.
+ *
+ * Batches are read from separate snapshots, so start a read transaction on
+ * `db` first to iterate over a single snapshot.
+ */
+export function* rawEntries(
+  db: Lmdb,
+  options: RawEntriesOptions = {},
+): Generator<RawEntry> {
+  const limit = options.batchSize ?? 1000;
+  let after: string | undefined;
+  do {
+    const batch = db.getRawEntries({ after, limit });
+    yield* batch.entries;
+    after = batch.next;
+  } while (after != null);
+}